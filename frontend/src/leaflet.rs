@@ -10,17 +10,104 @@ use std::rc::Rc;
 use std::cell::Cell;
 
 pub struct MapHandle {
-    #[allow(dead_code)]
     map: JsValue,
     leaflet: JsValue,
     marker_layer: JsValue,
     line_layer: JsValue,
     on_select: Rc<dyn Fn(Option<String>)>,
+    default_view: DefaultView,
+    scale: Option<JsValue>,
+    /// Set while the app moves the map itself, so moveend doesn't search.
+    quiet_move: Rc<Cell<bool>>,
 }
 
+/// Where the map falls back to when a search comes back empty. Defaults to
+/// all of New Zealand; `index.html` can override it with `map-default-center`
+/// ("lat,lon") and `map-default-zoom` meta tags.
+#[derive(Clone, Copy, PartialEq)]
+pub struct DefaultView {
+    pub lat: f64,
+    pub lon: f64,
+    pub zoom: f64,
+}
+
+impl Default for DefaultView {
+    fn default() -> Self {
+        Self { lat: -41.0, lon: 173.0, zoom: 6.0 }
+    }
+}
+
+impl DefaultView {
+    pub fn from_document() -> Self {
+        let mut view = Self::default();
+        if let Some(center) = meta_content("map-default-center")
+            && let Some((lat, lon)) = center.split_once(',')
+            && let (Ok(lat), Ok(lon)) = (lat.trim().parse(), lon.trim().parse())
+        {
+            view.lat = lat;
+            view.lon = lon;
+        }
+        if let Some(zoom) = meta_content("map-default-zoom").and_then(|zoom| zoom.trim().parse().ok()) {
+            view.zoom = zoom;
+        }
+        view
+    }
+}
+
+/// Base map tiles plus the attribution and usage terms their provider requires.
+/// Defaults to OpenStreetMap; `index.html` can override each field with a
+/// `tile-url`, `tile-attribution` or `tile-terms-url` meta tag.
+#[derive(Clone, PartialEq)]
+pub struct TileSource {
+    pub url: String,
+    pub attribution: String,
+    pub terms_url: String,
+}
+
+impl Default for TileSource {
+    fn default() -> Self {
+        Self {
+            url: "https://{s}.tile.openstreetmap.org/{z}/{x}/{y}.png".to_string(),
+            attribution: "© OpenStreetMap contributors".to_string(),
+            terms_url: "https://www.openstreetmap.org/copyright".to_string(),
+        }
+    }
+}
+
+impl TileSource {
+    pub fn from_document() -> Self {
+        let mut source = Self::default();
+        if let Some(url) = meta_content("tile-url") {
+            source.url = url;
+        }
+        if let Some(attribution) = meta_content("tile-attribution") {
+            source.attribution = attribution;
+        }
+        if let Some(terms_url) = meta_content("tile-terms-url") {
+            source.terms_url = terms_url;
+        }
+        source
+    }
+}
+
+pub(crate) fn meta_content(name: &str) -> Option<String> {
+    web_sys::window()?
+        .document()?
+        .query_selector(&format!("meta[name=\"{}\"]", name))
+        .ok()??
+        .get_attribute("content")
+        .filter(|content| !content.trim().is_empty())
+}
+
+/// Space kept between the search box and the map edge on the first fit, in
+/// pixels, so markers on the boundary aren't cut in half.
+pub const FIT_PADDING_PX: f64 = 20.0;
+
 pub fn init_map(
     element: HtmlElement,
     bbox: Bbox,
+    tiles: &TileSource,
+    default_view: DefaultView,
     on_move: impl Fn(Bbox) + 'static,
     on_select: Rc<dyn Fn(Option<String>)>,
 ) -> MapHandle {
@@ -29,30 +116,32 @@ pub fn init_map(
         .expect("Leaflet not loaded");
     let on_move = Rc::new(on_move);
 
-    let map = call_method(&leaflet, "map", &[element.into()])
+    // Attribution is rendered by the app beneath the map, so it can also credit trail data.
+    let map_options = Object::new();
+    Reflect::set(&map_options, &JsValue::from_str("attributionControl"), &JsValue::FALSE).ok();
+    let map = call_method(&leaflet, "map", &[element.into(), map_options.into()])
         .expect("map init failed");
     let options = Object::new();
     Reflect::set(&options, &JsValue::from_str("maxZoom"), &JsValue::from_f64(18.0)).ok();
-    Reflect::set(
-        &options,
-        &JsValue::from_str("attribution"),
-        &JsValue::from_str("© OpenStreetMap contributors"),
-    )
-    .ok();
 
     let tile_layer = call_method(
         &leaflet,
         "tileLayer",
         &[
-            JsValue::from_str("https://{s}.tile.openstreetmap.org/{z}/{x}/{y}.png"),
+            JsValue::from_str(&tiles.url),
             options.into(),
         ],
     )
     .expect("tile layer init failed");
     call_method(&tile_layer, "addTo", &[map.clone()]).ok();
 
+    // Padding only widens the view; the moveend handler below still reads the
+    // visible bounds from `getBounds`, so later searches cover what's on screen.
     let bounds = lat_lng_bounds(&leaflet, bbox);
-    call_method(&map, "fitBounds", &[bounds.clone()]).ok();
+    let padding = Array::of2(&JsValue::from_f64(FIT_PADDING_PX), &JsValue::from_f64(FIT_PADDING_PX));
+    let fit_options = Object::new();
+    Reflect::set(&fit_options, &JsValue::from_str("padding"), &padding).ok();
+    call_method(&map, "fitBounds", &[bounds, fit_options.into()]).ok();
 
     let marker_layer = call_method(&leaflet, "layerGroup", &[])
         .expect("layerGroup init failed");
@@ -65,12 +154,18 @@ pub fn init_map(
     let map_for_callback = map.clone();
     let pending_timer = Rc::new(Cell::new(0i32));
     let timer_ref = pending_timer.clone();
+    let quiet_move = Rc::new(Cell::new(false));
+    let quiet_ref = quiet_move.clone();
     let callback = Closure::wrap(Box::new(move || {
         let old = timer_ref.get();
         if old != 0 {
             let window = web_sys::window().unwrap();
             window.clear_timeout_with_handle(old);
         }
+        if quiet_ref.get() {
+            timer_ref.set(0);
+            return;
+        }
         let map_clone = map_for_callback.clone();
         let on_move_ref = on_move.clone();
         let inner = Closure::once_into_js(move || {
@@ -102,25 +197,67 @@ pub fn init_map(
     call_method(&map, "on", &[JsValue::from_str("click"), map_click.as_ref().clone()]).ok();
     map_click.forget();
 
-    MapHandle { map, leaflet, marker_layer, line_layer, on_select }
+    MapHandle { map, leaflet, marker_layer, line_layer, on_select, default_view, scale: None, quiet_move }
+}
+
+/// Swap the scale bar for one in miles (`imperial`) or kilometres. The bar
+/// tracks zoom on its own and doesn't touch the moveend handler.
+pub fn set_scale_units(handle: &mut MapHandle, imperial: bool) {
+    if let Some(old) = handle.scale.take() {
+        call_method(&old, "remove", &[]).ok();
+    }
+    let Ok(control) = Reflect::get(&handle.leaflet, &JsValue::from_str("control")) else {
+        return;
+    };
+    let scale_options = Object::new();
+    Reflect::set(&scale_options, &JsValue::from_str("metric"), &JsValue::from_bool(!imperial)).ok();
+    Reflect::set(&scale_options, &JsValue::from_str("imperial"), &JsValue::from_bool(imperial)).ok();
+    if let Ok(scale) = call_method(&control, "scale", &[scale_options.into()]) {
+        call_method(&scale, "addTo", std::slice::from_ref(&handle.map)).ok();
+        handle.scale = Some(scale);
+    }
 }
 
 pub fn update_markers(handle: &MapHandle, trails: &[Trail]) {
     call_method(&handle.marker_layer, "clearLayers", &[]).ok();
     call_method(&handle.line_layer, "clearLayers", &[]).ok();
     for trail in trails {
-        if trail.lat == 0.0 && trail.lon == 0.0 {
+        let (Some(lat), Some(lon)) = (trail.lat, trail.lon) else {
             continue;
-        }
-        let latlng = Array::of2(
-            &JsValue::from_f64(trail.lat),
-            &JsValue::from_f64(trail.lon),
-        );
+        };
+        let latlng = Array::of2(&JsValue::from_f64(lat), &JsValue::from_f64(lon));
         let marker = call_method(&handle.leaflet, "marker", &[latlng.into()])
             .expect("marker failed");
-        call_method(&marker, "bindPopup", &[JsValue::from_str(&trail.name)]).ok();
+        call_method(&marker, "bindPopup", &[JsValue::from_str(&popup_html(trail))]).ok();
 
-        // On click, draw the trail's polyline and select the trail
+        // The popup's "View details" link selects the trail like a list click.
+        // Leaflet rebuilds the popup on each open, so the same handler is
+        // attached to the new link every time.
+        {
+            let trail_id = trail.id.clone();
+            let on_select = handle.on_select.clone();
+            let on_click = Closure::wrap(Box::new(move || {
+                on_select(Some(trail_id.clone()));
+            }) as Box<dyn FnMut()>)
+            .into_js_value();
+            let callback = Closure::wrap(Box::new(move |event: JsValue| {
+                let link = Reflect::get(&event, &JsValue::from_str("popup"))
+                    .and_then(|popup| call_method(&popup, "getElement", &[]))
+                    .and_then(|element| call_method(&element, "querySelector", &[JsValue::from_str(".popup-details")]));
+                let Ok(link) = link else {
+                    return;
+                };
+                if link.is_null() || link.is_undefined() {
+                    return;
+                }
+                Reflect::set(&link, &JsValue::from_str("onclick"), &on_click).ok();
+            }) as Box<dyn FnMut(JsValue)>);
+            call_method(&marker, "on", &[JsValue::from_str("popupopen"), callback.as_ref().clone()]).ok();
+            callback.forget();
+        }
+
+        // On click, draw the trail's polyline (each section on its own, so
+        // disjoint sections aren't joined) and select the trail
         {
             let leaflet = handle.leaflet.clone();
             let line_layer = handle.line_layer.clone();
@@ -130,12 +267,17 @@ pub fn update_markers(handle: &MapHandle, trails: &[Trail]) {
             let callback = Closure::wrap(Box::new(move || {
                 call_method(&line_layer, "clearLayers", &[]).ok();
                 if !line.is_empty() {
+                    // Nested arrays make Leaflet draw a multi-polyline.
                     let latlngs = Array::new();
-                    for pt in &line {
-                        latlngs.push(&Array::of2(
-                            &JsValue::from_f64(pt[0]),
-                            &JsValue::from_f64(pt[1]),
-                        ));
+                    for segment in &line {
+                        let points = Array::new();
+                        for pt in segment {
+                            points.push(&Array::of2(
+                                &JsValue::from_f64(pt[0]),
+                                &JsValue::from_f64(pt[1]),
+                            ));
+                        }
+                        latlngs.push(&points);
                     }
                     let opts = Object::new();
                     Reflect::set(&opts, &JsValue::from_str("color"), &JsValue::from_str("#e63946")).ok();
@@ -154,6 +296,62 @@ pub fn update_markers(handle: &MapHandle, trails: &[Trail]) {
     }
 }
 
+/// Popup body for a trail marker: name, distance, difficulty, dog access and links.
+fn popup_html(trail: &Trail) -> String {
+    let distance = if trail.distance_km == 0.0 {
+        "Unknown distance".to_string()
+    } else if trail.distance_source == "computed_from_geometry" {
+        format!("~{:.1} km (estimated)", trail.distance_km)
+    } else {
+        format!("{:.1} km", trail.distance_km)
+    };
+    let difficulty = crate::format_label(&format!("{:?}", trail.difficulty).to_lowercase());
+    format!(
+        "<strong>{}</strong><br>{} · {}<br><span class=\"dog-badge dog-{}\">Dogs: {}</span><br>\
+         <a href=\"#trail-{}\" class=\"popup-details\">View details</a> · \
+         <a href=\"{}\" target=\"_blank\" rel=\"noreferrer\">Open map</a>",
+        escape_html(&trail.name),
+        distance,
+        difficulty,
+        escape_html(&trail.dog_policy),
+        escape_html(&crate::format_label(&trail.dog_policy)),
+        escape_html(&trail.id),
+        escape_html(crate::safe_href(&trail.map_url)),
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Move the map back to its configured default center and zoom without
+/// searching there: a nationwide view would send Overpass a huge query.
+/// Unanimated, so Leaflet fires moveend before `setView` returns.
+pub fn reset_view(handle: &MapHandle) {
+    let center = Array::of2(
+        &JsValue::from_f64(handle.default_view.lat),
+        &JsValue::from_f64(handle.default_view.lon),
+    );
+    let options = Object::new();
+    Reflect::set(&options, &JsValue::from_str("animate"), &JsValue::FALSE).ok();
+    handle.quiet_move.set(true);
+    call_method(
+        &handle.map,
+        "setView",
+        &[center.into(), JsValue::from_f64(handle.default_view.zoom), options.into()],
+    )
+    .ok();
+    handle.quiet_move.set(false);
+}
+
+/// The map's current viewport, for one-off searches outside the moveend handler.
+pub fn get_current_bounds(handle: &MapHandle) -> Option<Bbox> {
+    get_bounds(&handle.map)
+}
+
 fn call_method(target: &JsValue, name: &str, args: &[JsValue]) -> Result<JsValue, JsValue> {
     let function = Reflect::get(target, &JsValue::from_str(name))?;
     let function = function.dyn_into::<Function>()?;
@@ -1,3 +1,10 @@
+#![allow(
+    clippy::cloned_ref_to_slice_refs,
+    clippy::derivable_impls,
+    clippy::explicit_auto_deref,
+    clippy::wrong_self_convention
+)]
+
 use wasm_bindgen::prelude::*;
 use yew::prelude::*;
 
@@ -5,6 +12,8 @@ mod leaflet;
 
 use gloo_net::http::Request;
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::rc::Rc;
 use wasm_bindgen::JsCast;
 
@@ -79,7 +88,7 @@ impl Default for Filters {
             dog: DogFilter::AllowedOrPartial,
             difficulty: None,
             min_km: 0.0,
-            max_km: 70.0,
+            max_km: DEFAULT_SLIDER_MAX_KM,
             autorefresh: true,
             bbox: Bbox::default(),
         }
@@ -91,6 +100,10 @@ struct ResultsState {
     trails: Vec<Trail>,
     loading: bool,
     error: Option<String>,
+    /// Whether any search has returned yet, so the empty state isn't shown on first load.
+    loaded: bool,
+    /// Trails in the last response that couldn't be read and were left out.
+    skipped: usize,
 }
 
 impl Default for ResultsState {
@@ -99,6 +112,8 @@ impl Default for ResultsState {
             trails: Vec::new(),
             loading: false,
             error: None,
+            loaded: false,
+            skipped: 0,
         }
     }
 }
@@ -111,16 +126,54 @@ struct Trail {
     provider: String,
     location: String,
     distance_km: f32,
+    /// `computed_from_geometry` when `distance_km` is measured from the line.
+    #[serde(default)]
+    distance_source: String,
     elevation_m: Option<f32>,
+    #[serde(default)]
+    elevation_point_m: Option<f32>,
+    #[serde(default)]
+    net_elevation_m: Option<f32>,
     difficulty: Difficulty,
     dog_policy: String,
     dog_notes: Option<String>,
     surface: String,
+    #[serde(default)]
+    surfaces: Vec<(String, f32)>,
+    /// Server-rendered surface breakdown, e.g. "70% gravel, 30% grass".
+    #[serde(default)]
+    surface_label: String,
     map_url: String,
-    lat: f64,
-    lon: f64,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    /// One polyline per section of the track.
+    #[serde(default)]
+    line: Vec<Vec<[f64; 2]>>,
+    #[serde(default)]
+    match_score: Option<u8>,
     #[serde(default)]
-    line: Vec<[f64; 2]>,
+    region: Option<String>,
+    #[serde(default)]
+    data_checked: Option<String>,
+    #[serde(default)]
+    data_outdated: bool,
+    #[serde(default)]
+    featured: Option<bool>,
+    /// Server-built one-liner such as "Easy 5.2 km gravel loop, dogs allowed".
+    #[serde(default)]
+    summary: String,
+    /// How to show `provider`; `provider` stays the stable key.
+    #[serde(default)]
+    provider_name: String,
+    /// Server-worded cautions; `kind` doubles as a CSS class.
+    #[serde(default)]
+    warnings: Vec<TrailWarning>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+struct TrailWarning {
+    kind: String,
+    message: String,
 }
 
 impl Default for Bbox {
@@ -134,6 +187,24 @@ impl Default for Bbox {
     }
 }
 
+/// Top of the distance sliders unless `index.html` sets a `distance-slider-max`
+/// meta tag.
+const DEFAULT_SLIDER_MAX_KM: f32 = 100.0;
+
+/// The configured slider top, in km.
+fn slider_max_km() -> f32 {
+    leaflet::meta_content("distance-slider-max")
+        .and_then(|value| value.trim().parse::<f32>().ok())
+        .filter(|km| *km > 0.0)
+        .unwrap_or(DEFAULT_SLIDER_MAX_KM)
+}
+
+/// How long a cached response is shown without a loading state, in milliseconds.
+const RESULT_CACHE_TTL_MS: f64 = 60_000.0;
+
+/// Recent responses keyed by query string, with the `Date.now()` they arrived.
+type ResultCache = Rc<RefCell<HashMap<String, (f64, Vec<Trail>, usize)>>>;
+
 #[wasm_bindgen(start)]
 pub fn start() {
     yew::Renderer::<App>::new().render();
@@ -141,13 +212,20 @@ pub fn start() {
 
 #[function_component(App)]
 fn app() -> Html {
-    let filters = use_state(Filters::default);
+    let configured_slider_max = *use_memo((), |_| slider_max_km());
+    let filters = use_state(|| Filters { max_km: configured_slider_max, ..Filters::default() });
     let results = use_state(ResultsState::default);
     let map_ref = use_node_ref();
     let map_handle = use_mut_ref(|| None::<leaflet::MapHandle>);
     let slider_min = use_state(|| 0.0f32);
-    let slider_max = use_state(|| 70.0f32);
+    let slider_max = use_state(|| configured_slider_max);
     let selected_trail = use_state(|| None::<String>);
+    // Display-only toggle layered over the server response; flipping it never refetches.
+    let show_no_dog = use_state(|| true);
+    let imperial_units = use_state(stored_imperial_units);
+    let result_cache: ResultCache = use_mut_ref(HashMap::new);
+    let tiles = use_memo((), |_| leaflet::TileSource::from_document());
+    let default_view = use_memo((), |_| leaflet::DefaultView::from_document());
 
     // Keep a ref in sync with the latest filters so the map callback can read it
     // without suffering from stale-closure captures.
@@ -160,6 +238,8 @@ fn app() -> Html {
         let map_ref = map_ref.clone();
         let map_handle = map_handle.clone();
         let selected_trail = selected_trail.clone();
+        let tiles = tiles.clone();
+        let default_view = *default_view;
         use_effect_with(
             (),
             move |_| {
@@ -171,7 +251,7 @@ fn app() -> Html {
                             selected_trail.set(id);
                         })
                     };
-                    let handle = leaflet::init_map(element, bbox, move |bounds| {
+                    let handle = leaflet::init_map(element, bbox, &tiles, default_view, move |bounds| {
                         let mut next = filters_ref.borrow().clone();
                         next.bbox = bounds;
                         filters.set(next);
@@ -185,12 +265,27 @@ fn app() -> Html {
 
     {
         let results = results.clone();
+        let result_cache = result_cache.clone();
         use_effect_with(
             (*filters).clone(),
             move |current| {
                 if current.autorefresh {
-                    fetch_trails(current.clone(), results.clone());
+                    fetch_trails(current.clone(), results.clone(), result_cache.clone());
+                }
+                || ()
+            },
+        );
+    }
+
+    {
+        let map_handle = map_handle.clone();
+        use_effect_with(
+            *imperial_units,
+            move |imperial| {
+                if let Some(ref mut handle) = *map_handle.borrow_mut() {
+                    leaflet::set_scale_units(handle, *imperial);
                 }
+                store_imperial_units(*imperial);
                 || ()
             },
         );
@@ -198,7 +293,7 @@ fn app() -> Html {
 
     {
         let map_handle = map_handle.clone();
-        let trails = (*results).trails.clone();
+        let trails = visible_trails(&results.trails, *show_no_dog);
         use_effect_with(
             trails,
             move |trails| {
@@ -210,17 +305,30 @@ fn app() -> Html {
         );
     }
 
+    // When a finished search comes back empty, fall back to the default view.
+    // `reset_view` doesn't search the new view, and keying on the empty flag
+    // keeps a second empty result from moving the map again.
+    {
+        let map_handle = map_handle.clone();
+        let empty = results.loaded && !results.loading && results.error.is_none() && results.trails.is_empty();
+        use_effect_with(
+            empty,
+            move |empty| {
+                if *empty && let Some(ref handle) = *map_handle.borrow() {
+                    leaflet::reset_view(handle);
+                }
+                || ()
+            },
+        );
+    }
+
     {
         let selected_id = (*selected_trail).clone();
         use_effect_with(
             selected_id,
             move |id| {
                 if let Some(id) = id {
-                    let code = format!(
-                        "document.getElementById('trail-{}')?.scrollIntoView({{behavior:'smooth',block:'center'}})",
-                        id
-                    );
-                    let _ = js_sys::eval(&code);
+                    scroll_to_trail(id);
                 }
                 || ()
             },
@@ -321,11 +429,59 @@ fn app() -> Html {
         })
     };
 
+    // Manual search of the visible map area. With autorefresh on, updating the
+    // bbox already triggers the fetch, so only fetch directly when it's off.
+    let on_search_area = {
+        let filters = filters.clone();
+        let map_handle = map_handle.clone();
+        let results = results.clone();
+        let result_cache = result_cache.clone();
+        Callback::from(move |_: MouseEvent| {
+            let Some(bounds) = map_handle.borrow().as_ref().and_then(leaflet::get_current_bounds) else {
+                return;
+            };
+            let mut next = (*filters).clone();
+            next.bbox = bounds;
+            if !next.autorefresh {
+                fetch_trails(next.clone(), results.clone(), result_cache.clone());
+            }
+            filters.set(next);
+        })
+    };
+
+    let on_show_no_dog = {
+        let show_no_dog = show_no_dog.clone();
+        Callback::from(move |event: Event| {
+            let target = event.target().unwrap();
+            let input = target.dyn_into::<web_sys::HtmlInputElement>().unwrap();
+            show_no_dog.set(input.checked());
+        })
+    };
+
+    let on_imperial_units = {
+        let imperial_units = imperial_units.clone();
+        Callback::from(move |event: Event| {
+            let target = event.target().unwrap();
+            let input = target.dyn_into::<web_sys::HtmlInputElement>().unwrap();
+            imperial_units.set(input.checked());
+        })
+    };
+
+    let on_retry = {
+        let filters = filters.clone();
+        let results = results.clone();
+        let result_cache = result_cache.clone();
+        Callback::from(move |_: MouseEvent| {
+            fetch_trails((*filters).clone(), results.clone(), result_cache.clone());
+        })
+    };
+
     let loading = (*results).loading;
     let error = (*results).error.clone();
-    let trails = (*results).trails.clone();
-    let min_percent = (*slider_min / 70.0 * 100.0).clamp(0.0, 100.0);
-    let max_percent = (*slider_max / 70.0 * 100.0).clamp(0.0, 100.0);
+    let trails = visible_trails(&results.trails, *show_no_dog);
+    let slider_top = configured_slider_max;
+    let min_percent = (*slider_min / slider_top * 100.0).clamp(0.0, 100.0);
+    let max_percent = (*slider_max / slider_top * 100.0).clamp(0.0, 100.0);
     let fill_style = format!(
         "left: {:.2}%; right: {:.2}%;",
         min_percent,
@@ -339,6 +495,7 @@ fn app() -> Html {
                     <p class="eyebrow">{"dogtrails"}</p>
                     <h1>{"For you + dog"}</h1>
                 </div>
+                <a class="mode-switch" href="/?mode=ssr">{"Simple HTML version"}</a>
             </header>
             <main>
                 <section class="card form-card">
@@ -386,14 +543,23 @@ fn app() -> Html {
                             <div class="range-sliders">
                                 <div class="range-track"></div>
                                 <div class="range-fill" style={fill_style}></div>
-                                <input class="range-input range-input-min" type="range" min="0" max="70" step="1" value={slider_min.to_string()} oninput={on_min_input} onchange={on_min_change} />
-                                <input class="range-input range-input-max" type="range" min="0" max="70" step="1" value={slider_max.to_string()} oninput={on_max_input} onchange={on_max_change} />
+                                <input class="range-input range-input-min" type="range" min="0" max={slider_top.to_string()} step="1" value={slider_min.to_string()} oninput={on_min_input} onchange={on_min_change} />
+                                <input class="range-input range-input-max" type="range" min="0" max={slider_top.to_string()} step="1" value={slider_max.to_string()} oninput={on_max_input} onchange={on_max_change} />
                             </div>
                         </div>
                         <label class="checkbox">
                             <input type="checkbox" checked={(*filters).autorefresh} onchange={on_autorefresh} />
                             {"Autorefresh"}
                         </label>
+                        <button type="button" class="ghost" onclick={on_search_area}>{"Search this area"}</button>
+                        <label class="checkbox">
+                            <input type="checkbox" checked={*show_no_dog} onchange={on_show_no_dog} />
+                            {"Show no-dog trails"}
+                        </label>
+                        <label class="checkbox">
+                            <input type="checkbox" checked={*imperial_units} onchange={on_imperial_units} />
+                            {"Miles on scale bar"}
+                        </label>
                     </div>
                 </section>
 
@@ -401,9 +567,16 @@ fn app() -> Html {
                     <div class="results-layout">
                         <div class="map-panel">
                             <div id="map" ref={map_ref}></div>
+                            if results.loaded && !loading && error.is_none() && trails.is_empty() {
+                                <div class="map-empty">{"No trails here — try zooming out"}</div>
+                            }
+                            {render_attribution(&tiles, &trails)}
                         </div>
                         <div class="results">
-                            {render_results(loading, error, trails, (*selected_trail).clone())}
+                            if results.skipped > 0 && !loading {
+                                <div class="note">{format!("{} {} skipped (bad data)", results.skipped, if results.skipped == 1 { "result" } else { "results" })}</div>
+                            }
+                            {render_results(loading, error, trails, (*selected_trail).clone(), on_retry)}
                         </div>
                     </div>
                 </section>
@@ -412,12 +585,23 @@ fn app() -> Html {
     }
 }
 
-fn render_results(loading: bool, error: Option<String>, trails: Vec<Trail>, selected_id: Option<String>) -> Html {
+fn render_results(
+    loading: bool,
+    error: Option<String>,
+    trails: Vec<Trail>,
+    selected_id: Option<String>,
+    on_retry: Callback<MouseEvent>,
+) -> Html {
     if loading {
         return html! { <div class="note">{"Loading trails…"}</div> };
     }
     if let Some(message) = error {
-        return html! { <div class="warning">{message}</div> };
+        return html! {
+            <div class="warning error-box">
+                <p>{message}</p>
+                <button type="button" class="ghost" onclick={on_retry}>{"Retry"}</button>
+            </div>
+        };
     }
     if trails.is_empty() {
         return html! { <div class="warning">{"No trails matched your filters."}</div> };
@@ -432,44 +616,104 @@ fn render_results(loading: bool, error: Option<String>, trails: Vec<Trail>, sele
             } else {
                 html! {}
             };
-            let distance_label = if trail.distance_km == 0.0 {
-                "Unknown".to_string()
-            } else {
-                format!("{:.1} km", trail.distance_km)
-            };
-            let elevation_label = if let Some(elevation) = trail.elevation_m {
-                format!("{} m", elevation)
-            } else {
-                "Unknown".to_string()
+            let mut elevation_label = match (trail.elevation_m, trail.elevation_point_m) {
+                (Some(gain), _) => format!("{} m gain", gain),
+                (None, Some(point)) => format!("{} m above sea level", point),
+                (None, None) => "Unknown".to_string(),
             };
+            if let Some(net) = trail.net_elevation_m {
+                elevation_label.push_str(&format!(", {:+.0} m start to end", net));
+            }
             html! {
                 <article class={class} id={format!("trail-{}", trail.id)}>
-                    <h3>{trail.name.clone()}</h3>
+                    <h3>
+                        {trail.name.clone()}
+                        if trail.featured == Some(true) {
+                            <span class="featured" title="Featured track">{" ★"}</span>
+                        }
+                    </h3>
+                    <p class="summary">{trail.summary.clone()}</p>
+                    if let Some(score) = trail.match_score {
+                        <p class="match-score">{format!("{}% match", score)}</p>
+                    }
                     <dl class="trail-detail">
-                        <dt>{"Distance"}</dt>
-                        <dd>{distance_label}</dd>
                         <dt>{"Elevation"}</dt>
                         <dd>{elevation_label}</dd>
-                        <dt>{"Difficulty"}</dt>
-                        <dd>{format_label(&format!("{:?}", trail.difficulty).to_lowercase())}</dd>
-                        <dt>{"Dogs"}</dt>
-                        <dd>{format_label(&trail.dog_policy)}</dd>
-                        <dt>{"Surface"}</dt>
-                        <dd>{trail.surface.clone()}</dd>
-                        <dt>{"Area"}</dt>
-                        <dd>{trail.location.clone()}</dd>
+                        if trail.surfaces.len() > 1 {
+                            <dt>{"Surface"}</dt>
+                            <dd>{trail.surface_label.clone()}</dd>
+                        }
                         <dt>{"Source"}</dt>
-                        <dd><a href={trail.map_url.clone()} target="_blank" rel="noreferrer">{trail.provider.clone()}</a></dd>
+                        <dd><a href={safe_href(&trail.map_url).to_string()} target="_blank" rel="noreferrer">{provider_label(trail)}</a></dd>
                         <dt>{"ID"}</dt>
                         <dd>{trail.id.clone()}</dd>
                     </dl>
                     {warning}
+                    { for trail.warnings.iter().map(|warning| html! {
+                        <div class={classes!("warning", warning.kind.clone())}>{warning.message.clone()}</div>
+                    }) }
+                    if trail.data_outdated {
+                        <div class="warning outdated">{format!("Last verified {} — may be outdated.", trail.data_checked.as_deref().and_then(|checked| checked.get(..4)).unwrap_or("long ago"))}</div>
+                    }
                 </article>
             }
         })
     }
 }
 
+/// Credits for the base map plus each data provider present in `trails`.
+/// DOC data is CC BY 4.0 and must be credited wherever it's shown.
+fn render_attribution(tiles: &leaflet::TileSource, trails: &[Trail]) -> Html {
+    let has_provider = |name: &str| trails.iter().any(|trail| trail.provider == name);
+    html! {
+        <div class="attribution">
+            <span>
+                {format!("Map tiles {} ", tiles.attribution)}
+                <a href={tiles.terms_url.clone()} target="_blank" rel="noreferrer">{"(terms)"}</a>
+            </span>
+            if has_provider("OpenStreetMap") {
+                <span>
+                    {"Trail data © "}
+                    <a href="https://www.openstreetmap.org/copyright" target="_blank" rel="noreferrer">{"OpenStreetMap contributors"}</a>
+                </span>
+            }
+            if has_provider("DOC") {
+                <span>
+                    {"Data © "}
+                    <a href="https://www.doc.govt.nz/about-us/our-policies-and-profile/copyright/" target="_blank" rel="noreferrer">{"Department of Conservation"}</a>
+                    {" (CC BY 4.0)"}
+                </span>
+            }
+        </div>
+    }
+}
+
+/// `localStorage` key holding the scale bar units, "imperial" or "metric".
+const UNITS_STORAGE_KEY: &str = "dogtrails-units";
+
+/// The saved units preference; metric unless the user picked miles before.
+fn stored_imperial_units() -> bool {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .and_then(|storage| storage.get_item(UNITS_STORAGE_KEY).ok().flatten())
+        .is_some_and(|units| units == "imperial")
+}
+
+fn store_imperial_units(imperial: bool) {
+    if let Some(storage) = web_sys::window().and_then(|window| window.local_storage().ok().flatten()) {
+        let units = if imperial { "imperial" } else { "metric" };
+        storage.set_item(UNITS_STORAGE_KEY, units).ok();
+    }
+}
+
+fn visible_trails(trails: &[Trail], show_no_dog: bool) -> Vec<Trail> {
+    trails
+        .iter()
+        .filter(|trail| show_no_dog || trail.dog_policy != "not_allowed")
+        .cloned()
+        .collect()
+}
+
 fn change_select(
     state: UseStateHandle<Filters>,
     update: impl Fn(String, &mut Filters) + 'static,
@@ -486,54 +730,115 @@ fn change_select(
     })
 }
 
-fn fetch_trails(filters: Filters, results: UseStateHandle<ResultsState>) {
+/// Search with `filters` and store the results. Each trail in the response is
+/// read on its own, so malformed records are dropped and counted in
+/// `ResultsState::skipped` instead of failing the whole list.
+fn fetch_trails(filters: Filters, results: UseStateHandle<ResultsState>, cache: ResultCache) {
     wasm_bindgen_futures::spawn_local(async move {
+        let query_string = trail_query_string(&filters);
+
+        // Show a fresh cached response straight away and revalidate quietly.
+        let now = js_sys::Date::now();
+        let cached = cache
+            .borrow()
+            .get(&query_string)
+            .filter(|(fetched_at, _, _)| now - fetched_at < RESULT_CACHE_TTL_MS)
+            .map(|(_, trails, skipped)| (trails.clone(), *skipped));
         let mut next = (*results).clone();
-        next.loading = true;
         next.error = None;
-        results.set(next);
-
-        let mut params = filters.bbox.to_query();
-        params.push(("effort".to_string(), to_query_effort(filters.effort)));
-        params.push(("length".to_string(), to_query_length(filters.length)));
-        params.push(("dog".to_string(), to_query_dog(filters.dog)));
-        params.push(("min_km".to_string(), filters.min_km.to_string()));
-        params.push(("max_km".to_string(), filters.max_km.to_string()));
-        if let Some(difficulty) = filters.difficulty {
-            params.push(("difficulty".to_string(), to_query_difficulty(difficulty)));
+        match cached {
+            Some((trails, skipped)) => {
+                next.trails = trails;
+                next.skipped = skipped;
+                next.loading = false;
+            }
+            None => next.loading = true,
         }
-
-        let query_string = params
-            .iter()
-            .map(|(key, value)| format!("{}={}", key, urlencoding::encode(value)))
-            .collect::<Vec<_>>()
-            .join("&");
+        results.set(next);
 
         match Request::get(&format!("/api/trails?{}", query_string)).send().await {
-            Ok(response) => match response.json::<Vec<Trail>>().await {
-                Ok(trails) => {
+            Ok(response) if !response.ok() => {
+                let mut next = (*results).clone();
+                next.loading = false;
+                next.error = Some(search_error_message(Some(response.status())));
+                results.set(next);
+            }
+            // Parse trails one by one so a single malformed record is skipped
+            // rather than failing the whole list.
+            Ok(response) => match response.json::<Vec<serde_json::Value>>().await {
+                Ok(values) => {
+                    let (trails, skipped) = parse_trails(values);
+                    let now = js_sys::Date::now();
+                    let mut cache = cache.borrow_mut();
+                    cache.retain(|_, (fetched_at, _, _)| now - *fetched_at < RESULT_CACHE_TTL_MS);
+                    cache.insert(query_string, (now, trails.clone(), skipped));
                     let mut next = (*results).clone();
                     next.trails = trails;
+                    next.skipped = skipped;
                     next.loading = false;
+                    next.loaded = true;
                     results.set(next);
                 }
-                Err(err) => {
+                // A 2xx whose body isn't a trail list.
+                Err(_) => {
                     let mut next = (*results).clone();
                     next.loading = false;
-                    next.error = Some(err.to_string());
+                    next.error = Some(search_error_message(Some(200)));
                     results.set(next);
                 }
             },
-            Err(err) => {
+            Err(_) => {
                 let mut next = (*results).clone();
                 next.loading = false;
-                next.error = Some(err.to_string());
+                next.error = Some(search_error_message(None));
                 results.set(next);
             }
         }
     });
 }
 
+/// What to tell the user when a search fails: by HTTP status, or `None` when
+/// the request never got an answer.
+fn search_error_message(status: Option<u16>) -> String {
+    match status {
+        None => "Couldn't reach the server. Check your connection and try again.".to_string(),
+        Some(429) => "The trail services are busy right now. Wait a moment and try again.".to_string(),
+        Some(400) => "Those filters couldn't be used. Try changing them.".to_string(),
+        Some(502..=504) => "A trail provider isn't responding. Try again shortly.".to_string(),
+        Some(status) if status >= 500 => "Something went wrong on our side. Try again shortly.".to_string(),
+        Some(_) => "The server sent something unexpected. Try again.".to_string(),
+    }
+}
+
+/// Trails that deserialize, plus a count of the ones that don't.
+fn parse_trails(values: Vec<serde_json::Value>) -> (Vec<Trail>, usize) {
+    let total = values.len();
+    let trails: Vec<Trail> = values
+        .into_iter()
+        .filter_map(|value| serde_json::from_value(value).ok())
+        .collect();
+    let skipped = total - trails.len();
+    (trails, skipped)
+}
+
+fn trail_query_string(filters: &Filters) -> String {
+    let mut params = filters.bbox.to_query();
+    params.push(("effort".to_string(), to_query_effort(filters.effort.clone())));
+    params.push(("length".to_string(), to_query_length(filters.length.clone())));
+    params.push(("dog".to_string(), to_query_dog(filters.dog.clone())));
+    params.push(("min_km".to_string(), filters.min_km.to_string()));
+    params.push(("max_km".to_string(), filters.max_km.to_string()));
+    if let Some(difficulty) = filters.difficulty.clone() {
+        params.push(("difficulty".to_string(), to_query_difficulty(difficulty)));
+    }
+
+    params
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, urlencoding::encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
 fn to_query_effort(value: Effort) -> String {
     match value {
         Effort::Easy => "easy".to_string(),
@@ -566,6 +871,40 @@ fn to_query_difficulty(value: Difficulty) -> String {
     }
 }
 
+/// Smoothly centre a trail's card in the results list. A direct DOM call, so
+/// it works under a Content-Security-Policy that forbids `eval`.
+fn scroll_to_trail(id: &str) {
+    let Some(card) = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id(&format!("trail-{id}")))
+    else {
+        return;
+    };
+    let options = web_sys::ScrollIntoViewOptions::new();
+    options.set_behavior(web_sys::ScrollBehavior::Smooth);
+    options.set_block(web_sys::ScrollLogicalPosition::Center);
+    card.scroll_into_view_with_scroll_into_view_options(&options);
+}
+
+/// The server's display name for the provider, or its key from older servers.
+fn provider_label(trail: &Trail) -> String {
+    if trail.provider_name.is_empty() {
+        trail.provider.clone()
+    } else {
+        trail.provider_name.clone()
+    }
+}
+
 fn format_label(value: &str) -> String {
     value.replace('_', " ")
 }
+
+/// `url` if it's an http(s) link, so a provider's `javascript:` URL can't
+/// end up in an href; `#` otherwise.
+fn safe_href(url: &str) -> &str {
+    let scheme = url.split_once(':').map(|(scheme, _)| scheme.to_ascii_lowercase());
+    match scheme.as_deref() {
+        Some("http" | "https") => url,
+        _ => "#",
+    }
+}
@@ -0,0 +1,60 @@
+//! Jittered exponential backoff for upstream retries.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Waits between retries: `base` doubled per attempt, with up to half of it
+/// taken off at random so clients that failed together don't retry together.
+/// The jitter comes from a small seeded generator; a fixed seed repeats the
+/// same waits, which is what tests want.
+#[derive(Clone, Debug)]
+pub(crate) struct Backoff {
+    base: Duration,
+    state: u64,
+}
+
+impl Backoff {
+    /// Seeded from `seed`, or from the process's random hasher keys when `None`.
+    pub(crate) fn new(base: Duration, seed: Option<u64>) -> Self {
+        let state = seed.unwrap_or_else(|| RandomState::new().build_hasher().finish());
+        Self { base, state }
+    }
+
+    /// The wait before retry number `attempt`, counting from 1.
+    pub(crate) fn delay(&mut self, attempt: u32) -> Duration {
+        let full = self.base.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        // Keep the top 53 bits for an evenly spread fraction in [0, 1).
+        let fraction = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        full.mul_f64(1.0 - fraction / 2.0)
+    }
+
+    /// splitmix64: tiny, and plenty for spreading retries.
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_delays_are_reproducible() {
+        let delays = |seed| {
+            let mut backoff = Backoff::new(Duration::from_secs(1), Some(seed));
+            (1..=4).map(|attempt| backoff.delay(attempt)).collect::<Vec<_>>()
+        };
+        let first = delays(7);
+        assert_eq!(first, delays(7));
+        assert_ne!(first, delays(8));
+        for (attempt, delay) in (1..=4).zip(&first) {
+            let full = Duration::from_secs(1 << (attempt - 1));
+            assert!(*delay > full / 2 && *delay <= full, "attempt {attempt}: {delay:?}");
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Elevation lookups against an Open-Elevation compatible DEM service.
+
+use serde::{Deserialize, Serialize};
+
+use crate::TrailError;
+
+#[derive(Deserialize)]
+struct LookupResponse {
+    results: Vec<LookupResult>,
+}
+
+#[derive(Deserialize)]
+struct LookupResult {
+    elevation: f32,
+}
+
+/// One sample of an elevation profile, for charting.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ProfilePoint {
+    pub distance_km: f32,
+    pub elevation_m: f32,
+}
+
+/// Look up elevations for `[lat, lon]` points, in order.
+pub(crate) async fn fetch_elevations(
+    client: &reqwest::Client,
+    dem_url: &str,
+    points: &[[f64; 2]],
+) -> Result<Vec<f32>, TrailError> {
+    let locations = points
+        .iter()
+        .map(|point| format!("{},{}", point[0], point[1]))
+        .collect::<Vec<_>>()
+        .join("|");
+    let response = client
+        .get(format!("{}/api/v1/lookup", dem_url.trim_end_matches('/')))
+        .query(&[("locations", locations)])
+        .send()
+        .await
+        .map_err(|err| TrailError::Upstream(format!("DEM request failed: {err}")))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "<no body>".to_string());
+        return Err(TrailError::Upstream(format!(
+            "DEM request failed with status {}: {}",
+            status, body
+        )));
+    }
+
+    let data: LookupResponse = response
+        .json()
+        .await
+        .map_err(|err| TrailError::Upstream(format!("DEM response parse failed: {err}")))?;
+    if data.results.len() != points.len() {
+        return Err(TrailError::Upstream(format!(
+            "DEM returned {} elevations for {} points",
+            data.results.len(),
+            points.len()
+        )));
+    }
+    Ok(data.results.into_iter().map(|result| result.elevation).collect())
+}
+
+/// Total climb and net change (end minus start) over a profile; `None` for
+/// fewer than two samples.
+pub(crate) fn elevation_change(profile: &[ProfilePoint]) -> Option<(f32, f32)> {
+    if profile.len() < 2 {
+        return None;
+    }
+    let gain = profile
+        .windows(2)
+        .map(|pair| (pair[1].elevation_m - pair[0].elevation_m).max(0.0))
+        .sum();
+    Some((gain, profile[profile.len() - 1].elevation_m - profile[0].elevation_m))
+}
+
+/// Indexes of up to `max_samples` points spread evenly along `line`,
+/// always including both ends.
+pub(crate) fn sample_indexes(len: usize, max_samples: usize) -> Vec<usize> {
+    if len <= max_samples.max(2) {
+        return (0..len).collect();
+    }
+    let step = (len - 1) as f64 / (max_samples.max(2) - 1) as f64;
+    let mut indexes: Vec<usize> = (0..max_samples.max(2))
+        .map(|sample| (sample as f64 * step).round() as usize)
+        .collect();
+    indexes.dedup();
+    indexes
+}
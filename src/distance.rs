@@ -0,0 +1,132 @@
+//! Great-circle distance models for trail lengths.
+
+/// Mean earth radius in km, as used by the haversine model.
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// WGS84 ellipsoid, for the Vincenty model.
+const WGS84_A: f64 = 6378.137;
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DistanceModel {
+    /// Spherical earth; fast and within ~0.5% anywhere.
+    #[default]
+    Haversine,
+    /// Vincenty's inverse formula on the WGS84 ellipsoid; sub-metre precision.
+    Vincenty,
+}
+
+impl DistanceModel {
+    pub fn distance_km(&self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        match self {
+            DistanceModel::Haversine => haversine_km(lat1, lon1, lat2, lon2),
+            DistanceModel::Vincenty => vincenty_km(lat1, lon1, lat2, lon2)
+                .unwrap_or_else(|| haversine_km(lat1, lon1, lat2, lon2)),
+        }
+    }
+}
+
+/// Total length of a `[lat, lon]` polyline made of one or more sections. The
+/// gaps between sections aren't counted.
+pub fn polyline_length_km(segments: &[Vec<[f64; 2]>], model: DistanceModel) -> f64 {
+    segments
+        .iter()
+        .flat_map(|segment| segment.windows(2))
+        .map(|pair| model.distance_km(pair[0][0], pair[0][1], pair[1][0], pair[1][1]))
+        .sum()
+}
+
+pub(crate) fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let dlat = (lat2 - lat1).to_radians();
+    let dlon = (lon2 - lon1).to_radians();
+    let lat1 = lat1.to_radians();
+    let lat2 = lat2.to_radians();
+
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    let c = 2.0 * a.sqrt().asin();
+    EARTH_RADIUS_KM * c
+}
+
+/// `None` when the iteration fails to converge (near-antipodal points).
+fn vincenty_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Option<f64> {
+    let b = WGS84_A * (1.0 - WGS84_F);
+    let l = (lon2 - lon1).to_radians();
+    let u1 = ((1.0 - WGS84_F) * lat1.to_radians().tan()).atan();
+    let u2 = ((1.0 - WGS84_F) * lat2.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..200 {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+            + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2))
+        .sqrt();
+        if sin_sigma == 0.0 {
+            return Some(0.0);
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+        let cos_2sigma_m = if cos_sq_alpha == 0.0 {
+            0.0
+        } else {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha
+        };
+        let c = WGS84_F / 16.0 * cos_sq_alpha * (4.0 + WGS84_F * (4.0 - 3.0 * cos_sq_alpha));
+        let previous = lambda;
+        lambda = l
+            + (1.0 - c)
+                * WGS84_F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)));
+        if (lambda - previous).abs() < 1e-12 {
+            let u_sq = cos_sq_alpha * (WGS84_A * WGS84_A - b * b) / (b * b);
+            let big_a = 1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+            let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+            let delta_sigma = big_b
+                * sin_sigma
+                * (cos_2sigma_m
+                    + big_b / 4.0
+                        * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)
+                            - big_b / 6.0
+                                * cos_2sigma_m
+                                * (-3.0 + 4.0 * sin_sigma * sin_sigma)
+                                * (-3.0 + 4.0 * cos_2sigma_m * cos_2sigma_m)));
+            return Some(b * big_a * (sigma - delta_sigma));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn models_agree_on_long_segment() {
+        // Cape Reinga to Bluff, roughly the length of the country.
+        let haversine = DistanceModel::Haversine.distance_km(-34.43, 172.68, -46.60, 168.34);
+        let vincenty = DistanceModel::Vincenty.distance_km(-34.43, 172.68, -46.60, 168.34);
+        assert!((haversine - vincenty).abs() / vincenty < 0.005, "{haversine} vs {vincenty}");
+        assert!((vincenty - 1403.0).abs() < 10.0, "got {vincenty}");
+    }
+
+    #[test]
+    fn polyline_length_sums_segments() {
+        let line = [vec![[0.0, 0.0], [0.0, 1.0], [0.0, 2.0]]];
+        assert!((polyline_length_km(&line, DistanceModel::Haversine) - 222.39).abs() < 0.1);
+        // One degree of longitude on the equator is 111.32 km on WGS84.
+        assert!((polyline_length_km(&line, DistanceModel::Vincenty) - 222.64).abs() < 0.1);
+    }
+
+    #[test]
+    fn polyline_length_skips_gaps_between_sections() {
+        // Two one-degree sections with a one-degree gap between them.
+        let line = [vec![[0.0, 0.0], [0.0, 1.0]], vec![[0.0, 2.0], [0.0, 3.0]]];
+        assert!((polyline_length_km(&line, DistanceModel::Haversine) - 222.39).abs() < 0.1);
+    }
+}
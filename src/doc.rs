@@ -1,22 +1,63 @@
 /// Department of Conservation tracks API integration.
+use std::time::Duration;
+
 use serde_json::Value;
 
-use crate::{Bbox, Difficulty, DogPolicy, Provider, Trail, TrailError};
+use crate::backoff::Backoff;
+use crate::{Bbox, Difficulty, DifficultyThresholds, DistanceSource, DogPolicy, DogSeason, Provider, Trail, TrailError, strip_macrons};
+
+pub(crate) const DOC_API_BASE: &str = "https://api.doc.govt.nz";
 
-const DOC_TRACKS_URL: &str = "https://api.doc.govt.nz/v1/tracks?coordinates=wgs84";
+/// How often to retry the DOC list request, and the wait before the first
+/// retry; each later wait doubles, less some jitter seeded by `seed`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ListRetry {
+    pub retries: u32,
+    pub backoff: Duration,
+    pub seed: Option<u64>,
+}
 
 /// Fetch all tracks from the DOC list endpoint (no detail calls).
-/// Returns lightweight Trail objects built from summary data only.
+/// Returns lightweight Trail objects built from summary data only, or `None`
+/// when the payload has an unexpected shape and shouldn't be cached.
+/// Network errors, 429s and 5xx responses are retried per `retry`.
 pub(crate) async fn fetch_doc_summaries(
     client: &reqwest::Client,
+    base_url: &str,
     api_key: &str,
-) -> Result<Vec<Trail>, TrailError> {
-    let response = client
-        .get(DOC_TRACKS_URL)
-        .header("x-api-key", api_key)
-        .send()
-        .await
-        .map_err(|err| TrailError(format!("DOC tracks request failed: {err}")))?;
+    timeout: Duration,
+    thresholds: DifficultyThresholds,
+    retry: ListRetry,
+    keep_raw: bool,
+) -> Result<Option<Vec<Trail>>, TrailError> {
+    let mut backoff = Backoff::new(retry.backoff, retry.seed);
+    let mut attempt = 0;
+    let response = loop {
+        attempt += 1;
+        let result = client
+            .get(format!("{}/v1/tracks?coordinates=wgs84", base_url))
+            .header("x-api-key", api_key)
+            .timeout(timeout)
+            .send()
+            .await;
+        let retryable = match &result {
+            Ok(response) => {
+                response.status().is_server_error() || response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(_) => true,
+        };
+        if !retryable || attempt > retry.retries {
+            break result.map_err(|err| crate::request_error("DOC tracks request", err, timeout))?;
+        }
+        let delay = backoff.delay(attempt);
+        tracing::warn!(
+            "DOC tracks request failed, retrying in {:?} (attempt {}/{})",
+            delay,
+            attempt,
+            retry.retries + 1
+        );
+        tokio::time::sleep(delay).await;
+    };
 
     if !response.status().is_success() {
         let status = response.status();
@@ -24,7 +65,7 @@ pub(crate) async fn fetch_doc_summaries(
             .text()
             .await
             .unwrap_or_else(|_| "<no body>".to_string());
-        return Err(TrailError(format!(
+        return Err(TrailError::Upstream(format!(
             "DOC tracks request failed with status {}: {}",
             status, body
         )));
@@ -33,41 +74,67 @@ pub(crate) async fn fetch_doc_summaries(
     let payload: Value = response
         .json()
         .await
-        .map_err(|err| TrailError(format!("DOC tracks response parse failed: {err}")))?;
+        .map_err(|err| TrailError::Upstream(format!("DOC tracks response parse failed: {err}")))?;
 
-    let items = extract_doc_items(&payload);
+    let Some(items) = extract_doc_items(&payload) else {
+        tracing::warn!("DOC tracks response had an unexpected shape, not caching");
+        return Ok(None);
+    };
     tracing::info!("DOC API returned {} tracks total", items.len());
 
     let trails: Vec<Trail> = items
         .iter()
-        .filter_map(|item| map_doc_summary(item))
+        .filter_map(|item| {
+            let mut trail = map_doc_summary(item, thresholds)?;
+            trail.raw = keep_raw.then(|| trimmed_raw(item));
+            Some(trail)
+        })
         .collect();
 
     tracing::info!("DOC: {} trails after mapping summaries", trails.len());
-    Ok(trails)
+    Ok(Some(trails))
 }
 
 /// Fetch the detail JSON for a single track.
 pub(crate) async fn fetch_doc_detail(
     client: &reqwest::Client,
+    base_url: &str,
     api_key: &str,
     track_id: &str,
+    timeout: Duration,
 ) -> Result<Value, TrailError> {
-    let url = format!("https://api.doc.govt.nz/v1/tracks/{}/detail?coordinates=wgs84", track_id);
+    fetch_doc_detail_if_found(client, base_url, api_key, track_id, timeout)
+        .await?
+        .ok_or_else(|| TrailError::Upstream(format!("DOC detail request failed: no track {track_id}")))
+}
+
+/// Like `fetch_doc_detail`, but `None` when DOC has no track with that id.
+pub(crate) async fn fetch_doc_detail_if_found(
+    client: &reqwest::Client,
+    base_url: &str,
+    api_key: &str,
+    track_id: &str,
+    timeout: Duration,
+) -> Result<Option<Value>, TrailError> {
+    let url = format!("{}/v1/tracks/{}/detail?coordinates=wgs84", base_url, urlencoding::encode(track_id));
     let response = client
         .get(url)
         .header("x-api-key", api_key)
+        .timeout(timeout)
         .send()
         .await
-        .map_err(|err| TrailError(format!("DOC detail request failed: {err}")))?;
+        .map_err(|err| crate::request_error("DOC detail request", err, timeout))?;
 
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
     if !response.status().is_success() {
         let status = response.status();
         let body = response
             .text()
             .await
             .unwrap_or_else(|_| "<no body>".to_string());
-        return Err(TrailError(format!(
+        return Err(TrailError::Upstream(format!(
             "DOC detail request failed with status {}: {}",
             status, body
         )));
@@ -76,58 +143,87 @@ pub(crate) async fn fetch_doc_detail(
     response
         .json::<Value>()
         .await
-        .map_err(|err| TrailError(format!("DOC detail response parse failed: {err}")))
+        .map(Some)
+        .map_err(|err| TrailError::Upstream(format!("DOC detail response parse failed: {err}")))
 }
 
-fn extract_doc_items(payload: &Value) -> Vec<Value> {
+/// A trail from a detail payload alone, for lookups that skip the track list.
+/// Detail payloads may leave out the id, so the requested one is kept.
+pub(crate) fn map_doc_detail(track_id: &str, detail: &Value, thresholds: DifficultyThresholds) -> Option<Trail> {
+    let mut trail = map_doc_summary(detail, thresholds)?;
+    trail.id = track_id.to_string();
+    enrich_with_detail(&mut trail, detail);
+    Some(trail)
+}
+
+/// A DOC record for `Trail::raw`: everything but the line, which is already
+/// on the trail and dwarfs the rest.
+pub(crate) fn trimmed_raw(item: &Value) -> Value {
+    let mut raw = item.clone();
+    if let Some(fields) = raw.as_object_mut() {
+        fields.remove("line");
+    }
+    raw
+}
+
+/// Keys DOC and the proxies in front of it use to wrap the track list.
+const DOC_LIST_KEYS: &[&str] = &["tracks", "results", "items"];
+
+/// The track list from a DOC payload, or `None` if it isn't where we expect.
+/// Accepts a bare array, a `tracks`/`results`/`items` envelope, or one of those
+/// nested one level down under `data`.
+fn extract_doc_items(payload: &Value) -> Option<Vec<Value>> {
     match payload {
-        Value::Array(items) => items.clone(),
-        Value::Object(map) => map
-            .get("tracks")
-            .and_then(|value| value.as_array())
-            .cloned()
-            .unwrap_or_default(),
-        _ => Vec::new(),
+        Value::Array(items) => Some(items.clone()),
+        Value::Object(_) => doc_list_envelope(payload)
+            .or_else(|| payload.get("data").and_then(doc_list_envelope)),
+        _ => None,
     }
 }
 
+fn doc_list_envelope(value: &Value) -> Option<Vec<Value>> {
+    DOC_LIST_KEYS
+        .iter()
+        .find_map(|key| value.get(*key).and_then(|list| list.as_array()))
+        .cloned()
+}
+
 fn extract_doc_id(item: &Value) -> Option<String> {
     item.get("assetId")?.as_str().map(|s| s.to_string())
 }
 
-fn map_doc_summary(summary: &Value) -> Option<Trail> {
+fn map_doc_summary(summary: &Value, thresholds: DifficultyThresholds) -> Option<Trail> {
     let name = doc_string(summary, &["name", "trackName", "title"])?;
 
     let (dog_policy, dog_notes) = doc_dog_policy_single(summary);
 
-    let location = doc_string(
-        summary,
-        &["locationString", "locationArray", "location", "region", "district", "place", "area"],
-    )
-    .unwrap_or_else(|| "New Zealand".to_string());
+    let areas = doc_admin_areas(summary);
+    let location = doc_location(summary, &areas).unwrap_or_else(|| "New Zealand".to_string());
 
     let surface = doc_string(summary, &["surface", "trackSurface", "terrain"])
         .unwrap_or_else(|| "Unknown".to_string());
 
-    let distance_km = doc_distance_km_single(summary).unwrap_or(0.0);
+    let reported_km = doc_distance_km_single(summary);
+    let distance_km = reported_km.unwrap_or(0.0);
 
     let difficulty = doc_difficulty_single(summary)
-        .unwrap_or_else(|| crate::map_difficulty(None, distance_km));
+        .unwrap_or_else(|| crate::map_difficulty(None, distance_km, thresholds));
 
     let map_url = doc_string(summary, &["staticLink", "url", "webUrl", "docUrl", "link"])
-        .unwrap_or_else(|| "https://www.doc.govt.nz".to_string());
+        .unwrap_or_else(|| doc_track_url(&name, &areas));
 
     let id = extract_doc_id(summary)
         .unwrap_or_else(|| name.to_lowercase().replace(' ', "-"));
 
-    let (trail_lat, trail_lon) = extract_lat_lon(summary).unwrap_or((0.0, 0.0));
-
+    // Without explicit coordinates, the middle of the line's bbox will do;
+    // with neither, the trail has no position rather than a made-up (0, 0).
+    let explicit = extract_lat_lon(summary);
     let line = extract_line_coords(summary).unwrap_or_default();
-    let line_bbox = extract_line_bbox(summary).unwrap_or(Bbox {
-        min_lat: trail_lat,
-        min_lon: trail_lon,
-        max_lat: trail_lat,
-        max_lon: trail_lon,
+    let line_bbox = extract_line_bbox(summary);
+    let position = explicit.or_else(|| line_bbox.map(|bbox| bbox.center()));
+    let line_bbox = line_bbox.unwrap_or_else(|| {
+        let (lat, lon) = position.unwrap_or_default();
+        Bbox { min_lat: lat, min_lon: lon, max_lat: lat, max_lon: lon }
     });
 
     Some(Trail {
@@ -136,19 +232,120 @@ fn map_doc_summary(summary: &Value) -> Option<Trail> {
         provider: Provider::DOC,
         location,
         distance_km,
+        distance_source: if reported_km.is_some() { DistanceSource::ProviderReported } else { DistanceSource::Unknown },
         elevation_m: None,
+        elevation_point_m: None,
+        net_elevation_m: None,
         difficulty,
         dog_policy,
         dog_notes,
+        surfaces: vec![(surface.clone(), 1.0)],
+        surface_label: String::new(),
         surface,
         map_url,
-        lat: trail_lat,
-        lon: trail_lon,
+        lat: position.map(|(lat, _)| lat),
+        lon: position.map(|(_, lon)| lon),
+        has_location: position.is_some(),
         line,
         line_bbox,
+        match_score: None,
+        region: areas.region,
+        district: areas.district,
+        place: areas.place,
+        hot_paw_risk: None,
+        slug: String::new(),
+        summary: String::new(),
+        raw: None,
+        water_crossing: doc_water_crossing(summary),
+        provider_name: String::new(),
+        difficulty_conflict: false,
+        thumbnail_url: doc_thumbnail(summary),
+        images: doc_images(summary),
+        warnings: Vec::new(),
+        data_checked: Some(crate::today_iso()),
+        data_outdated: false,
+        featured: doc_featured(summary),
+        popularity: doc_popularity(summary),
+        dog_seasons: doc_dog_seasons(summary),
+        name_generated: false,
     })
 }
 
+/// Best guess at a track's page when DOC gives no link, following DOC's site
+/// layout with whichever of region and place are known.
+fn doc_track_url(name: &str, areas: &AdminAreas) -> String {
+    let mut url = "https://www.doc.govt.nz/parks-and-recreation/".to_string();
+    if let Some(region) = &areas.region {
+        url.push_str(&format!("places-to-go/{}/", url_slug(region)));
+        if let Some(place) = &areas.place {
+            url.push_str(&format!("places/{}/", url_slug(place)));
+        }
+    }
+    url.push_str(&format!("things-to-do/tracks/{}/", url_slug(name)));
+    url
+}
+
+/// Lowercase, hyphen-separated path segment in DOC's URL style: macrons
+/// dropped and apostrophes removed, so "Arthur's Pass" is `arthurs-pass`.
+fn url_slug(text: &str) -> String {
+    strip_macrons(text)
+        .replace(['\'', '\u{2019}'], "")
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_ascii_lowercase())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Administrative areas DOC reports as separate keys.
+struct AdminAreas {
+    region: Option<String>,
+    district: Option<String>,
+    place: Option<String>,
+}
+
+fn doc_admin_areas(value: &Value) -> AdminAreas {
+    AdminAreas {
+        region: doc_string(value, &["region"]),
+        district: doc_string(value, &["district"]),
+        place: doc_string(value, &["place"]),
+    }
+}
+
+/// Display location: DOC's own location string if given, otherwise the
+/// administrative areas joined most-specific first.
+fn doc_location(value: &Value, areas: &AdminAreas) -> Option<String> {
+    if let Some(location) = doc_string(value, &["locationString", "locationArray", "location"]) {
+        return Some(location);
+    }
+    let parts: Vec<&str> = [&areas.place, &areas.district, &areas.region]
+        .into_iter()
+        .filter_map(|area| area.as_deref())
+        .collect();
+    if parts.is_empty() {
+        doc_string(value, &["area"])
+    } else {
+        Some(parts.join(", "))
+    }
+}
+
+/// Swap `detail`'s line for one of at most `max_points` points before it's
+/// cached, so every trail it fills in gets the short line.
+pub(crate) fn simplify_detail_line(detail: &mut Value, max_points: usize) {
+    let Some(line) = extract_line_coords(detail) else {
+        return;
+    };
+    let simplified = crate::simplify_sections(&line, max_points);
+    if simplified != line {
+        // Back to DOC's [lon, lat] order.
+        detail["line"] = simplified
+            .iter()
+            .map(|section| section.iter().map(|[lat, lon]| serde_json::json!([lon, lat])).collect())
+            .collect::<Vec<Value>>()
+            .into();
+    }
+}
+
 /// Enrich a trail with fields from the detail endpoint, filling in
 /// any data the summary was missing.
 pub(crate) fn enrich_with_detail(trail: &mut Trail, detail: &Value) {
@@ -156,26 +353,53 @@ pub(crate) fn enrich_with_detail(trail: &mut Trail, detail: &Value) {
     if let Some(name) = doc_string(detail, &["name", "trackName", "title"]) {
         trail.name = name;
     }
-    if let Some(loc) = doc_string(
-        detail,
-        &["locationString", "locationArray", "location", "region", "district", "place", "area"],
-    ) {
+    let areas = doc_admin_areas(detail);
+    if let Some(loc) = doc_location(detail, &areas) {
         trail.location = loc;
     }
+    if areas.region.is_some() {
+        trail.region = areas.region;
+    }
+    if areas.district.is_some() {
+        trail.district = areas.district;
+    }
+    if areas.place.is_some() {
+        trail.place = areas.place;
+    }
     if let Some(km) = doc_distance_km_single(detail) {
         if trail.distance_km == 0.0 || km > 0.0 {
             trail.distance_km = km;
+            trail.distance_source = DistanceSource::ProviderReported;
         }
     }
     if let Some(diff) = doc_difficulty_single(detail) {
         trail.difficulty = diff;
     }
+    if let Some(featured) = doc_featured(detail) {
+        trail.featured = Some(featured);
+    }
+    if let Some(popularity) = doc_popularity(detail) {
+        trail.popularity = Some(popularity);
+    }
     let (dog_policy, dog_notes) = doc_dog_policy_single(detail);
     if dog_policy != DogPolicy::Unknown {
         trail.dog_policy = dog_policy;
         trail.dog_notes = dog_notes;
     }
+    let dog_seasons = doc_dog_seasons(detail);
+    if !dog_seasons.is_empty() {
+        trail.dog_seasons = dog_seasons;
+    }
+    trail.water_crossing |= doc_water_crossing(detail);
+    if let Some(thumbnail) = doc_thumbnail(detail) {
+        trail.thumbnail_url = Some(thumbnail);
+    }
+    let images = doc_images(detail);
+    if !images.is_empty() {
+        trail.images = images;
+    }
     if let Some(surface) = doc_string(detail, &["surface", "trackSurface", "terrain"]) {
+        trail.surfaces = vec![(surface.clone(), 1.0)];
         trail.surface = surface;
     }
     if let Some(url) = doc_string(detail, &["staticLink", "url", "webUrl", "docUrl", "link"]) {
@@ -190,10 +414,11 @@ pub(crate) fn enrich_with_detail(trail: &mut Trail, detail: &Value) {
     if let Some(lb) = extract_line_bbox(detail) {
         trail.line_bbox = lb;
     }
-    if let Some((lat, lon)) = extract_lat_lon(detail) {
-        if trail.lat == 0.0 && trail.lon == 0.0 {
-            trail.lat = lat;
-            trail.lon = lon;
+    if let Some((lat, lon)) = extract_lat_lon(detail).or_else(|| extract_line_bbox(detail).map(|bbox| bbox.center())) {
+        if !trail.has_location {
+            trail.lat = Some(lat);
+            trail.lon = Some(lon);
+            trail.has_location = true;
         }
     }
 }
@@ -272,6 +497,9 @@ fn doc_distance_km_single(value: &Value) -> Option<f32> {
 }
 
 fn doc_difficulty_single(value: &Value) -> Option<Difficulty> {
+    if let Some(difficulty) = doc_walk_track_category(value) {
+        return Some(difficulty);
+    }
     let text = doc_string(value, &["difficulty", "grade", "trackGrade", "walkTrackCategory"])?;
     let lower = text.to_lowercase();
     if lower.contains("easy") {
@@ -285,6 +513,39 @@ fn doc_difficulty_single(value: &Value) -> Option<Difficulty> {
     }
 }
 
+/// Great Walks, or tracks DOC flags as featured. `Some(false)` when DOC gives
+/// a category or flag that isn't one; `None` when it gives neither.
+fn doc_featured(value: &Value) -> Option<bool> {
+    if let Some(flag) = doc_bool(value, &["featured", "isFeatured", "greatWalk", "isGreatWalk"]) {
+        return Some(flag);
+    }
+    let text = doc_string(value, &["walkTrackCategory"])?;
+    Some(text.split(", ").any(|category| category.eq_ignore_ascii_case("great walk")))
+}
+
+/// Popularity on a 0–1 scale from a DOC ranking: a `popularity` fraction, a
+/// `popularityScore` percentage, or yearly visitor numbers on a log scale
+/// where a million visitors is 1.
+fn doc_popularity(value: &Value) -> Option<f32> {
+    let score = doc_number(value, &["popularity"]).or_else(|| doc_number(value, &["popularityScore"]).map(|percent| percent / 100.0));
+    if let Some(score) = score {
+        return Some(score.clamp(0.0, 1.0) as f32);
+    }
+    let visitors = doc_number(value, &["annualVisitors", "visitorNumbers", "visitorCount"])?;
+    Some((visitors.max(1.0).log10() / 6.0).clamp(0.0, 1.0) as f32)
+}
+
+/// Map DOC's canonical `walkTrackCategory` values to a difficulty tier.
+fn doc_walk_track_category(value: &Value) -> Option<Difficulty> {
+    let text = doc_string(value, &["walkTrackCategory"])?;
+    text.split(", ").find_map(|category| match category.to_lowercase().as_str() {
+        "easy access short walk" | "short walk" => Some(Difficulty::Easy),
+        "walking track" | "great walk" => Some(Difficulty::Moderate),
+        "tramping track" | "route" => Some(Difficulty::Hard),
+        _ => None,
+    })
+}
+
 fn doc_dog_policy_single(value: &Value) -> (DogPolicy, Option<String>) {
     let allowed = doc_bool(value, &["dogsAllowed", "dogAllowed"]);
     let on_lead = doc_bool(value, &["dogsAllowedOnLead", "dogsOnLead"]);
@@ -318,6 +579,210 @@ fn doc_dog_policy_single(value: &Value) -> (DogPolicy, Option<String>) {
     )
 }
 
+/// Month names as DOC writes them, in full or cut to three or four letters.
+const MONTHS: [&str; 12] = [
+    "january", "february", "march", "april", "may", "june",
+    "july", "august", "september", "october", "november", "december",
+];
+
+/// Days before each month in a non-leap year.
+const DAYS_BEFORE_MONTH: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+/// Dated rules in DOC's dog text, such as "Dogs on a lead 1 Dec–28 Feb,
+/// prohibited otherwise". Each clause with a date range becomes a season;
+/// an "otherwise" or "at other times" clause fills the rest of the year.
+/// Empty when the text names no dates.
+fn doc_dog_seasons(value: &Value) -> Vec<DogSeason> {
+    let Some(text) = doc_string(value, &["dogsAllowed", "dogRules", "dogAccess"]) else {
+        return Vec::new();
+    };
+    let lower = text.to_lowercase().replace(['–', '—', '-'], " - ");
+    let mut seasons = Vec::new();
+    let mut otherwise = None;
+    for clause in lower.split([',', ';', '.']).flat_map(|clause| clause.split(" but ")) {
+        let words: Vec<&str> = clause
+            .split(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == ':')
+            .filter(|word| !word.is_empty())
+            .collect();
+        match (date_range(&words), clause_dog_policy(clause)) {
+            (Some((start, end)), Some(policy)) => seasons.push(DogSeason { start, end, policy }),
+            (None, Some(policy))
+                if ["otherwise", "other times", "rest of the year"].iter().any(|phrase| clause.contains(phrase)) =>
+            {
+                otherwise = Some(policy)
+            }
+            _ => {}
+        }
+    }
+    if let (Some(policy), false) = (otherwise, seasons.is_empty()) {
+        let mut covered = [false; 365];
+        for season in &seasons {
+            let (start, end) = (day_of_year(season.start), day_of_year(season.end));
+            let mut day = start;
+            loop {
+                covered[day] = true;
+                if day == end {
+                    break;
+                }
+                day = (day + 1) % 365;
+            }
+        }
+        // Walk the year from a covered day so a gap over the new year stays whole.
+        let first = covered.iter().position(|covered| *covered).unwrap_or(0);
+        let mut gap_start = None;
+        for step in 1..=365 {
+            let day = (first + step) % 365;
+            match (covered[day], gap_start) {
+                (false, None) => gap_start = Some(day),
+                (true, Some(start)) => {
+                    let end = (day + 364) % 365;
+                    seasons.push(DogSeason {
+                        start: month_day(start),
+                        end: month_day(end),
+                        policy: policy.clone(),
+                    });
+                    gap_start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    seasons
+}
+
+/// The first "1 Dec - 28 Feb" or "December to February" range in `words`.
+/// A bare month starts on its first day and ends on its last.
+fn date_range(words: &[&str]) -> Option<((u32, u32), (u32, u32))> {
+    (0..words.len()).find_map(|index| {
+        let (start_day, start_month, next) = date_at(words, index)?;
+        let separator = words.get(next)?;
+        if !["-", "to", "until", "till"].contains(separator) {
+            return None;
+        }
+        let (end_day, end_month, _) = date_at(words, next + 1)?;
+        Some(((start_month, start_day.unwrap_or(1)), (end_month, end_day.unwrap_or(days_in_month(end_month)))))
+    })
+}
+
+/// A "day month" or bare month at `index`: the day if given, the month, and
+/// the index after it.
+fn date_at(words: &[&str], index: usize) -> Option<(Option<u32>, u32, usize)> {
+    let word = words.get(index)?;
+    if let Ok(day) = word.trim_end_matches(|c: char| c.is_alphabetic()).parse::<u32>() {
+        let month = month_number(words.get(index + 1)?)?;
+        // Seasons use a 365-day year, so 29 Feb (or a slip like 31 Apr) ends the month.
+        return (1..=31).contains(&day).then_some((Some(day.min(days_in_month(month))), month, index + 2));
+    }
+    Some((None, month_number(word)?, index + 1))
+}
+
+fn month_number(word: &str) -> Option<u32> {
+    if word.len() < 3 {
+        return None;
+    }
+    MONTHS
+        .iter()
+        .position(|month| month.starts_with(word))
+        .map(|index| index as u32 + 1)
+}
+
+/// The dog rule a clause of DOC text states, if it states one.
+fn clause_dog_policy(clause: &str) -> Option<DogPolicy> {
+    // Hyphens were spaced out for date ranges; close them up for "off-lead".
+    let clause = clause.replace(" - ", " ");
+    if ["off lead", "off leash"].iter().any(|phrase| clause.contains(phrase)) {
+        return Some(DogPolicy::Allowed);
+    }
+    if clause.contains("lead") || clause.contains("leash") {
+        return Some(DogPolicy::Partial);
+    }
+    if ["no dogs", "prohibited", "not permitted", "not allowed", "banned"]
+        .iter()
+        .any(|phrase| clause.contains(phrase))
+    {
+        return Some(DogPolicy::NotAllowed);
+    }
+    if ["allowed", "permitted", "welcome"].iter().any(|phrase| clause.contains(phrase)) {
+        return Some(DogPolicy::Allowed);
+    }
+    None
+}
+
+/// File types DOC serves pictures as.
+const IMAGE_EXTENSIONS: &[&str] = &[".jpg", ".jpeg", ".png", ".webp", ".gif"];
+
+/// `url` if it's an https link to an image file, judged by its extension.
+fn image_url(url: &str) -> Option<String> {
+    let url = url.trim();
+    let path = url.split(['?', '#']).next().unwrap_or_default().to_lowercase();
+    (url.starts_with("https://") && IMAGE_EXTENSIONS.iter().any(|extension| path.ends_with(extension)))
+        .then(|| url.to_string())
+}
+
+/// The card-sized `introductionThumbnail`, when it's a usable image.
+fn doc_thumbnail(value: &Value) -> Option<String> {
+    doc_string(value, &["introductionThumbnail"]).and_then(|url| image_url(&url))
+}
+
+/// Gallery pictures: the full `introductionImage` and any `images` or
+/// `gallery` entries, given as URLs or `{ "url": ... }` objects. The thumbnail
+/// and duplicates are left out.
+fn doc_images(value: &Value) -> Vec<String> {
+    let thumbnail = doc_thumbnail(value);
+    let listed = ["images", "gallery"]
+        .iter()
+        .filter_map(|key| value.get(*key)?.as_array())
+        .flatten()
+        .filter_map(|entry| entry.as_str().or_else(|| entry.get("url")?.as_str()));
+    let mut images: Vec<String> = Vec::new();
+    for url in value.get("introductionImage").and_then(Value::as_str).into_iter().chain(listed) {
+        if let Some(url) = image_url(url)
+            && Some(&url) != thumbnail.as_ref()
+            && !images.contains(&url)
+        {
+            images.push(url);
+        }
+    }
+    images
+}
+
+/// Phrases in DOC's track text that mean walking through water.
+const WATER_CROSSING_PHRASES: &[&str] = &["unbridged", "river crossing", "stream crossing"];
+
+/// Whole words that do; "ford" alone would match "Stafford".
+const WATER_CROSSING_WORDS: &[&str] = &["ford", "fords", "fording"];
+
+/// Whether DOC's description or warnings mention a ford or unbridged crossing.
+fn doc_water_crossing(value: &Value) -> bool {
+    let text = ["introduction", "description", "warnings", "walkingAndTrampingWarnings"]
+        .iter()
+        .filter_map(|key| doc_string(value, &[key]))
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    WATER_CROSSING_PHRASES.iter().any(|phrase| text.contains(phrase))
+        || text
+            .split(|c: char| !c.is_alphabetic())
+            .any(|word| WATER_CROSSING_WORDS.contains(&word))
+}
+
+/// Zero-based day of a non-leap year; 29 Feb shares 1 Mar's slot.
+fn day_of_year((month, day): (u32, u32)) -> usize {
+    let month = month.clamp(1, 12);
+    ((DAYS_BEFORE_MONTH[month as usize - 1] + day.max(1) - 1) as usize).min(364)
+}
+
+fn days_in_month(month: u32) -> u32 {
+    let next = DAYS_BEFORE_MONTH.get(month as usize).copied().unwrap_or(365);
+    next - DAYS_BEFORE_MONTH[month as usize - 1]
+}
+
+fn month_day(day_of_year: usize) -> (u32, u32) {
+    let day_of_year = day_of_year as u32;
+    let month = DAYS_BEFORE_MONTH.iter().rposition(|before| *before <= day_of_year).unwrap_or(0);
+    (month as u32 + 1, day_of_year - DAYS_BEFORE_MONTH[month] + 1)
+}
+
 fn doc_bool(value: &Value, keys: &[&str]) -> Option<bool> {
     for key in keys {
         if let Some(field) = value.get(*key) {
@@ -426,16 +891,19 @@ pub(crate) fn filter_doc_by_bbox(trails: &[Trail], view: Bbox) -> Vec<Trail> {
     filtered_trails
 }
 
-/// Extract line coordinates as `[[lat, lon], ...]` from the DOC `line` field.
-fn extract_line_coords(value: &Value) -> Option<Vec<[f64; 2]>> {
+/// Extract line coordinates as one `[[lat, lon], ...]` list per segment of
+/// the DOC `line` field. Segments stay apart: a track's sections can be
+/// disjoint.
+fn extract_line_coords(value: &Value) -> Option<Vec<Vec<[f64; 2]>>> {
     let line = value.get("line")?.as_array()?;
-    let mut coords = Vec::new();
+    let mut segments = Vec::new();
 
     for segment in line {
         let points = match segment.as_array() {
             Some(pts) => pts.as_slice(),
             None => continue,
         };
+        let mut coords = Vec::new();
         for point in points {
             if let Some(pair) = point.as_array() {
                 // [lon, lat] GeoJSON order → [lat, lon] for Leaflet
@@ -446,7 +914,324 @@ fn extract_line_coords(value: &Value) -> Option<Vec<[f64; 2]>> {
                 }
             }
         }
+        if !coords.is_empty() {
+            segments.push(coords);
+        }
+    }
+
+    if segments.is_empty() { None } else { Some(segments) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn category(name: &str) -> Option<Difficulty> {
+        doc_difficulty_single(&json!({ "walkTrackCategory": name }))
+    }
+
+    #[test]
+    fn maps_walk_track_categories() {
+        assert_eq!(category("Easy access short walk"), Some(Difficulty::Easy));
+        assert_eq!(category("Short walk"), Some(Difficulty::Easy));
+        assert_eq!(category("Walking track"), Some(Difficulty::Moderate));
+        assert_eq!(category("Great Walk"), Some(Difficulty::Moderate));
+        assert_eq!(category("Tramping track"), Some(Difficulty::Hard));
+        assert_eq!(category("Route"), Some(Difficulty::Hard));
+    }
+
+    #[test]
+    fn maps_walk_track_category_arrays() {
+        let value = json!({ "walkTrackCategory": ["Walking track"] });
+        assert_eq!(doc_difficulty_single(&value), Some(Difficulty::Moderate));
+    }
+
+    #[test]
+    fn api_distances_are_provider_reported() {
+        let thresholds = DifficultyThresholds::default();
+        let reported = map_doc_summary(&json!({ "assetId": "1", "name": "Riverside Walk", "distance": "4.5 km" }), thresholds).unwrap();
+        assert_eq!(reported.distance_km, 4.5);
+        assert_eq!(reported.distance_source, DistanceSource::ProviderReported);
+
+        let mut trail = map_doc_summary(&json!({ "assetId": "2", "name": "Hill Track" }), thresholds).unwrap();
+        assert_eq!(trail.distance_source, DistanceSource::Unknown);
+        enrich_with_detail(&mut trail, &json!({ "distance": 3.0 }));
+        assert_eq!(trail.distance_source, DistanceSource::ProviderReported);
     }
 
-    if coords.is_empty() { None } else { Some(coords) }
+    #[test]
+    fn maps_great_walks_and_featured_flags() {
+        let great_walk = map_doc_summary(&json!({
+            "assetId": "1", "name": "Abel Tasman Coast Track", "walkTrackCategory": ["Great Walk"]
+        }), DifficultyThresholds::default())
+        .unwrap();
+        assert_eq!(great_walk.featured, Some(true));
+        let normal = map_doc_summary(&json!({
+            "assetId": "2", "name": "Riverside Walk", "walkTrackCategory": ["Short walk"]
+        }), DifficultyThresholds::default())
+        .unwrap();
+        assert_eq!(normal.featured, Some(false));
+
+        let mut trail = normal;
+        enrich_with_detail(&mut trail, &json!({ "featured": true }));
+        assert_eq!(trail.featured, Some(true));
+        assert_eq!(doc_featured(&json!({ "name": "Unclassified" })), None);
+    }
+
+    #[test]
+    fn maps_popularity_rankings() {
+        assert_eq!(doc_popularity(&json!({ "popularity": 0.75 })), Some(0.75));
+        assert_eq!(doc_popularity(&json!({ "popularityScore": 40 })), Some(0.4));
+        // A 1% score is quiet, not the busiest.
+        assert_eq!(doc_popularity(&json!({ "popularityScore": 1 })), Some(0.01));
+        assert_eq!(doc_popularity(&json!({ "annualVisitors": 1000 })), Some(0.5));
+        assert_eq!(doc_popularity(&json!({ "name": "Quiet Track" })), None);
+
+        let trail = map_doc_summary(
+            &json!({ "assetId": "1", "name": "Tongariro Alpine Crossing", "visitorCount": 1_000_000 }),
+            DifficultyThresholds::default(),
+        )
+        .unwrap();
+        assert_eq!(trail.popularity, Some(1.0));
+    }
+
+    #[test]
+    fn unexpected_list_shape_is_not_an_empty_list() {
+        assert_eq!(extract_doc_items(&json!([])).map(|items| items.len()), Some(0));
+        assert_eq!(extract_doc_items(&json!({ "tracks": [] })).map(|items| items.len()), Some(0));
+        assert!(extract_doc_items(&json!({ "data": [{ "assetId": "1" }] })).is_none());
+        assert!(extract_doc_items(&json!("service unavailable")).is_none());
+    }
+
+    #[test]
+    fn unwraps_nested_list_envelopes() {
+        let track = json!({ "assetId": "1" });
+        let count = |payload: Value| extract_doc_items(&payload).map(|items| items.len());
+        assert_eq!(count(json!({ "results": [track.clone()] })), Some(1));
+        assert_eq!(count(json!({ "items": [track.clone(), track.clone()] })), Some(2));
+        assert_eq!(count(json!({ "data": { "tracks": [track.clone()] } })), Some(1));
+        assert_eq!(count(json!({ "data": { "results": [] } })), Some(0));
+        assert_eq!(count(json!({ "data": { "items": [track.clone()] } })), Some(1));
+        assert_eq!(count(json!({ "data": { "data": { "tracks": [track] } } })), None);
+        assert_eq!(count(json!({ "results": "none" })), None);
+    }
+
+    #[test]
+    fn line_bbox_is_none_for_empty_line() {
+        assert!(extract_line_bbox(&json!({ "line": [] })).is_none());
+        assert!(extract_line_bbox(&json!({ "line": [[]] })).is_none());
+        assert!(extract_line_bbox(&json!({})).is_none());
+    }
+
+    #[test]
+    fn line_bbox_handles_single_point() {
+        let bbox = extract_line_bbox(&json!({ "line": [[[172.6, -43.5]]] })).unwrap();
+        assert_eq!(bbox, Bbox { min_lat: -43.5, min_lon: 172.6, max_lat: -43.5, max_lon: 172.6 });
+    }
+
+    #[test]
+    fn line_bbox_spans_multiple_segments() {
+        let value = json!({
+            "line": [
+                [[172.6, -43.5], [172.7, -43.4]],
+                [[172.5, -43.6]],
+            ]
+        });
+        let bbox = extract_line_bbox(&value).unwrap();
+        assert_eq!(bbox, Bbox { min_lat: -43.6, min_lon: 172.5, max_lat: -43.4, max_lon: 172.7 });
+    }
+
+    #[test]
+    fn line_bbox_skips_malformed_entries() {
+        let value = json!({
+            "line": [
+                "not a segment",
+                [null, [172.6], "x", [172.6, -43.5]],
+            ]
+        });
+        let bbox = extract_line_bbox(&value).unwrap();
+        assert_eq!(bbox, Bbox { min_lat: -43.5, min_lon: 172.6, max_lat: -43.5, max_lon: 172.6 });
+        assert!(extract_line_bbox(&json!({ "line": ["oops", [null]] })).is_none());
+    }
+
+    #[test]
+    fn maps_admin_areas_to_separate_fields() {
+        let summary = json!({
+            "assetId": "doc-9",
+            "name": "Mount Vernon",
+            "region": ["Canterbury"],
+            "district": "Christchurch City",
+            "place": "Port Hills",
+        });
+        let trail = map_doc_summary(&summary, DifficultyThresholds::default()).unwrap();
+        assert_eq!(trail.region.as_deref(), Some("Canterbury"));
+        assert_eq!(trail.district.as_deref(), Some("Christchurch City"));
+        assert_eq!(trail.place.as_deref(), Some("Port Hills"));
+        assert_eq!(trail.location, "Port Hills, Christchurch City, Canterbury");
+
+        let summary = json!({ "name": "Rakaia Gorge", "locationString": "Mid Canterbury", "region": "Canterbury" });
+        let trail = map_doc_summary(&summary, DifficultyThresholds::default()).unwrap();
+        assert_eq!(trail.location, "Mid Canterbury");
+        assert_eq!(trail.region.as_deref(), Some("Canterbury"));
+        assert_eq!(trail.place, None);
+    }
+
+    #[test]
+    fn builds_track_url_without_a_link() {
+        let summary = json!({
+            "assetId": "doc-3",
+            "name": "Avalanche Peak Track",
+            "region": "Canterbury",
+            "place": "Arthur's Pass National Park",
+        });
+        let trail = map_doc_summary(&summary, DifficultyThresholds::default()).unwrap();
+        assert_eq!(
+            trail.map_url,
+            "https://www.doc.govt.nz/parks-and-recreation/places-to-go/canterbury/places/arthurs-pass-national-park/things-to-do/tracks/avalanche-peak-track/"
+        );
+
+        let summary = json!({ "assetId": "doc-5", "name": "Ōtari Loop", "region": "Wellington" });
+        let trail = map_doc_summary(&summary, DifficultyThresholds::default()).unwrap();
+        assert_eq!(
+            trail.map_url,
+            "https://www.doc.govt.nz/parks-and-recreation/places-to-go/wellington/things-to-do/tracks/otari-loop/"
+        );
+
+        let trail = map_doc_summary(&json!({ "assetId": "doc-4", "name": "Te Ara Koa" }), DifficultyThresholds::default()).unwrap();
+        assert_eq!(trail.map_url, "https://www.doc.govt.nz/parks-and-recreation/things-to-do/tracks/te-ara-koa/");
+    }
+
+    #[test]
+    fn leaves_tracks_without_coordinates_unplaced() {
+        let summary = json!({ "assetId": "doc-6", "name": "Somewhere Track" });
+        let trail = map_doc_summary(&summary, DifficultyThresholds::default()).unwrap();
+        assert_eq!((trail.lat, trail.lon), (None, None));
+        assert!(!trail.has_location);
+
+        // A line's bbox is enough to place the marker at its middle.
+        let summary = json!({
+            "assetId": "doc-7",
+            "name": "Line Only Track",
+            "line": [[[172.60, -43.50], [172.62, -43.54]]],
+        });
+        let trail = map_doc_summary(&summary, DifficultyThresholds::default()).unwrap();
+        assert!(trail.has_location);
+        assert!((trail.lat.unwrap() + 43.52).abs() < 1e-9);
+        assert!((trail.lon.unwrap() - 172.61).abs() < 1e-9);
+    }
+
+    #[test]
+    fn keeps_disjoint_line_segments_apart() {
+        let summary = json!({
+            "assetId": "doc-5",
+            "name": "Two Halves Track",
+            "line": [
+                [[172.60, -43.50], [172.61, -43.50]],
+                [[172.70, -43.50], [172.71, -43.50]],
+            ],
+        });
+        let trail = map_doc_summary(&summary, DifficultyThresholds::default()).unwrap();
+        assert_eq!(
+            trail.line,
+            vec![vec![[-43.50, 172.60], [-43.50, 172.61]], vec![[-43.50, 172.70], [-43.50, 172.71]]]
+        );
+        let km = crate::polyline_length_km(&trail.line, crate::DistanceModel::Haversine);
+        let one_section = crate::polyline_length_km(&trail.line[..1], crate::DistanceModel::Haversine);
+        assert!((km - 2.0 * one_section).abs() < 1e-9, "{km} km includes the gap");
+    }
+
+    #[test]
+    fn falls_back_to_substring_heuristic() {
+        let value = json!({ "difficulty": "Advanced tramping" });
+        assert_eq!(doc_difficulty_single(&value), Some(Difficulty::Hard));
+    }
+
+    #[test]
+    fn parses_seasonal_dog_rules() {
+        let value = json!({ "dogsAllowed": "Dogs on a lead 1 Dec–28 Feb, prohibited otherwise to protect nesting dotterel." });
+        assert_eq!(
+            doc_dog_seasons(&value),
+            vec![
+                DogSeason { start: (12, 1), end: (2, 28), policy: DogPolicy::Partial },
+                DogSeason { start: (3, 1), end: (11, 30), policy: DogPolicy::NotAllowed },
+            ]
+        );
+
+        let value = json!({ "dogsAllowed": "No dogs from 1 September to 31 January (lambing and nesting). Dogs allowed on a lead at other times." });
+        assert_eq!(
+            doc_dog_seasons(&value),
+            vec![
+                DogSeason { start: (9, 1), end: (1, 31), policy: DogPolicy::NotAllowed },
+                DogSeason { start: (2, 1), end: (8, 31), policy: DogPolicy::Partial },
+            ]
+        );
+
+        let value = json!({ "dogsAllowed": "Dogs on a leash only. Other pets on conservation land rules." });
+        assert!(doc_dog_seasons(&value).is_empty());
+    }
+
+    #[test]
+    fn off_lead_clauses_allow_dogs() {
+        let value = json!({ "dogsAllowed": "Dogs off lead 1 May - 30 Sep, on a lead at other times." });
+        assert_eq!(
+            doc_dog_seasons(&value),
+            vec![
+                DogSeason { start: (5, 1), end: (9, 30), policy: DogPolicy::Allowed },
+                DogSeason { start: (10, 1), end: (4, 30), policy: DogPolicy::Partial },
+            ]
+        );
+
+        let value = json!({ "dogsAllowed": "Off-leash dogs welcome April to September; dogs on a leash otherwise." });
+        assert_eq!(doc_dog_seasons(&value)[0], DogSeason { start: (4, 1), end: (9, 30), policy: DogPolicy::Allowed });
+    }
+
+    #[test]
+    fn leap_day_ends_february() {
+        let value = json!({ "dogsAllowed": "Dogs on a lead 1 Dec - 29 Feb, prohibited otherwise." });
+        assert_eq!(
+            doc_dog_seasons(&value),
+            vec![
+                DogSeason { start: (12, 1), end: (2, 28), policy: DogPolicy::Partial },
+                DogSeason { start: (3, 1), end: (11, 30), policy: DogPolicy::NotAllowed },
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_unbridged_crossings_from_the_description() {
+        let mut trail = map_doc_summary(&json!({ "assetId": "9", "name": "Otehake Track" }), DifficultyThresholds::default()).unwrap();
+        assert!(!trail.water_crossing);
+        enrich_with_detail(
+            &mut trail,
+            &json!({ "introduction": "A remote route with several unbridged river crossings that are impassable after rain." }),
+        );
+        assert!(trail.water_crossing);
+
+        let dry = json!({ "introduction": "Follows the Stafford stream on a benched track past an old mill." });
+        assert!(!doc_water_crossing(&dry));
+    }
+
+    #[test]
+    fn picks_the_thumbnail_for_cards_and_the_rest_for_the_gallery() {
+        let summary = json!({
+            "assetId": "10",
+            "name": "Lake Matheson Walk",
+            "introductionThumbnail": "https://www.doc.govt.nz/thumbs/matheson-square.jpg?w=200",
+            "introductionImage": "https://www.doc.govt.nz/images/matheson-full.jpg",
+            "images": [
+                "https://www.doc.govt.nz/thumbs/matheson-square.jpg?w=200",
+                { "url": "https://www.doc.govt.nz/images/matheson-jetty.png" },
+                "http://www.doc.govt.nz/images/insecure.jpg",
+                "https://www.doc.govt.nz/documents/track-brochure.pdf"
+            ]
+        });
+        let trail = map_doc_summary(&summary, DifficultyThresholds::default()).unwrap();
+        assert_eq!(trail.thumbnail_url.as_deref(), Some("https://www.doc.govt.nz/thumbs/matheson-square.jpg?w=200"));
+        assert_eq!(
+            trail.images,
+            ["https://www.doc.govt.nz/images/matheson-full.jpg", "https://www.doc.govt.nz/images/matheson-jetty.png"]
+        );
+    }
 }
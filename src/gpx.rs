@@ -0,0 +1,101 @@
+//! GPX 1.1 export for loading trails onto a GPS watch.
+use crate::Trail;
+
+/// Most trails one GPX download will hold.
+pub const MAX_GPX_TRAILS: usize = 200;
+
+/// One GPX document for many trails: a `<trk>` for each trail with a line, one
+/// `<trkseg>` per section, and a `<wpt>` for each trail with only a position.
+/// Trails with neither are left out.
+pub fn trails_to_gpx(trails: &[Trail]) -> String {
+    let mut gpx = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <gpx version=\"1.1\" creator=\"dogtrails\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+    );
+    // The schema wants every waypoint before the first track.
+    for trail in trails.iter().filter(|trail| point_count(trail) < 2) {
+        if let (Some(lat), Some(lon)) = (trail.lat, trail.lon) {
+            gpx.push_str(&waypoint(trail, lat, lon));
+        }
+    }
+    for trail in trails.iter().filter(|trail| point_count(trail) >= 2) {
+        gpx.push_str(&track(trail));
+    }
+    gpx.push_str("</gpx>\n");
+    gpx
+}
+
+fn waypoint(trail: &Trail, lat: f64, lon: f64) -> String {
+    format!("<wpt lat=\"{}\" lon=\"{}\">\n{}</wpt>\n", lat, lon, metadata(trail))
+}
+
+fn point_count(trail: &Trail) -> usize {
+    trail.line.iter().map(Vec::len).sum()
+}
+
+fn track(trail: &Trail) -> String {
+    let segments: String = trail
+        .line
+        .iter()
+        .map(|segment| {
+            let points: String = segment
+                .iter()
+                .map(|point| format!("<trkpt lat=\"{}\" lon=\"{}\"/>\n", point[0], point[1]))
+                .collect();
+            format!("<trkseg>\n{}</trkseg>\n", points)
+        })
+        .collect();
+    format!("<trk>\n{}{}</trk>\n", metadata(trail), segments)
+}
+
+/// Name, description and source link shared by tracks and waypoints.
+fn metadata(trail: &Trail) -> String {
+    let mut desc = format!("{:.1} km, {:?}, dogs {:?}", trail.distance_km, trail.difficulty, trail.dog_policy);
+    if let Some(notes) = &trail.dog_notes {
+        desc.push_str(". ");
+        desc.push_str(notes);
+    }
+    format!(
+        "<name>{}</name>\n<desc>{}</desc>\n<link href=\"{}\"/>\n",
+        escape(&trail.name),
+        escape(&desc),
+        escape(&trail.map_url)
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trail(id: &str, name: &str, line: Vec<Vec<[f64; 2]>>) -> Trail {
+        Trail { line, ..crate::tests::test_trail(id, name) }
+    }
+
+    #[test]
+    fn writes_tracks_and_waypoints_for_each_trail() {
+        let trails = vec![
+            trail("t1", "Maitai Loop", vec![vec![[-41.3, 173.2], [-41.31, 173.21]]]),
+            trail("t2", "Centre of NZ", Vec::new()),
+            trail("t3", "Rocks & Roots", vec![vec![[-41.2, 173.1], [-41.21, 173.11]], vec![[-41.22, 173.12]]]),
+        ];
+
+        let gpx = trails_to_gpx(&trails);
+        assert_eq!(gpx.matches("<trk>").count(), 2);
+        assert_eq!(gpx.matches("<wpt ").count(), 1);
+        assert_eq!(gpx.matches("<trkseg>").count(), 3);
+        assert_eq!(gpx.matches("<trkpt ").count(), 5);
+        assert!(gpx.contains("<name>Maitai Loop</name>"));
+        assert!(gpx.contains("<name>Rocks &amp; Roots</name>"));
+        assert!(gpx.contains("<wpt lat=\"-41.3\" lon=\"173.2\">\n<name>Centre of NZ</name>"));
+        assert!(gpx.find("<wpt ").unwrap() < gpx.find("<trk>").unwrap());
+        assert!(gpx.contains("<link href=\"https://example.test/?a=1&amp;b=2\"/>"));
+    }
+}
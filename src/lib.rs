@@ -1,14 +1,31 @@
+#![allow(clippy::collapsible_if)]
+
+mod backoff;
+mod dem;
+mod distance;
 mod doc;
+mod gpx;
+mod metrics;
+mod notes;
 mod overpass;
+mod page;
 
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::sync::RwLock;
 
-#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub use dem::ProfilePoint;
+pub use metrics::{Metrics, MetricsSnapshot};
+pub use distance::{polyline_length_km, DistanceModel};
+pub use gpx::{trails_to_gpx, MAX_GPX_TRAILS};
+pub use notes::{DogNoteEntry, DogNotes};
+pub use page::{render_page, render_trail};
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum Difficulty {
     Easy,
@@ -16,7 +33,18 @@ pub enum Difficulty {
     Hard,
 }
 
-#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+impl Difficulty {
+    /// Position on the Easy–Hard scale, 0 to 2.
+    pub fn tier(&self) -> i32 {
+        match self {
+            Difficulty::Easy => 0,
+            Difficulty::Moderate => 1,
+            Difficulty::Hard => 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum DogPolicy {
     Allowed,
@@ -26,12 +54,55 @@ pub enum DogPolicy {
     Unknown,
 }
 
+/// Where a trail's `distance_km` came from, so an estimate can be shown as one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DistanceSource {
+    /// The provider gave the length.
+    ProviderReported,
+    /// Measured along the line we have, which may be sparse.
+    ComputedFromGeometry,
+    #[default]
+    Unknown,
+}
+
+/// A stretch of the year with its own dog rule, such as "on lead 1 Dec–28 Feb".
+/// Days are (month, day) and inclusive; a season may run over the new year.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DogSeason {
+    pub start: (u32, u32),
+    pub end: (u32, u32),
+    pub policy: DogPolicy,
+}
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Provider {
     DOC,
     OpenStreetMap,
 }
 
+impl Provider {
+    /// Run by a land manager rather than tagged by the community.
+    pub fn is_official(&self) -> bool {
+        match self {
+            Provider::DOC => true,
+            Provider::OpenStreetMap => false,
+        }
+    }
+
+    /// How pages name the provider; the serialized value stays "DOC" or
+    /// "OpenStreetMap". Overridable per service with `with_display_name`.
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Provider::DOC => "NZ DOC",
+            Provider::OpenStreetMap => "OpenStreetMap",
+        }
+    }
+}
+
+/// Tie-break order for equal-scoring trails from different providers.
+pub const DEFAULT_PROVIDER_PRIORITY: &[Provider] = &[Provider::DOC, Provider::OpenStreetMap];
+
 impl std::fmt::Display for Provider {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -48,19 +119,121 @@ pub struct Trail {
     pub provider: Provider,
     pub location: String,
     pub distance_km: f32,
+    #[serde(default)]
+    pub distance_source: DistanceSource,
+    /// Elevation gain along the trail.
     pub elevation_m: Option<f32>,
+    /// Height above sea level of a single point (OSM `ele`), not a gain.
+    #[serde(default)]
+    pub elevation_point_m: Option<f32>,
+    /// End height minus start height, from the DEM profile; negative when the
+    /// trail finishes lower than it starts.
+    #[serde(default)]
+    pub net_elevation_m: Option<f32>,
     pub difficulty: Difficulty,
     pub dog_policy: DogPolicy,
     pub dog_notes: Option<String>,
+    /// Dominant surface; see `surfaces` for the full breakdown.
     pub surface: String,
+    /// Fraction of distance on each surface, largest first.
+    #[serde(default)]
+    pub surfaces: Vec<(String, f32)>,
+    /// `surfaces` as display text, e.g. "70% gravel, 30% grass", or just
+    /// `surface` for a single-surface trail.
+    #[serde(default)]
+    pub surface_label: String,
     pub map_url: String,
-    pub lat: f64,
-    pub lon: f64,
-    /// Track line as `[[lat, lon], ...]` for map display.
+    /// Marker position; `None` when the provider gave no coordinates or line.
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    /// Whether `lat`/`lon` are known, so clients can skip unplaceable trails.
     #[serde(default)]
-    pub line: Vec<[f64; 2]>,
+    pub has_location: bool,
+    /// Track line for map display: one `[[lat, lon], ...]` polyline per
+    /// section, so disjoint sections aren't joined up.
+    #[serde(default)]
+    pub line: Vec<Vec<[f64; 2]>>,
     #[serde(skip)]
     pub line_bbox: Bbox,
+    /// How well the trail fits the query, 0–100 with higher better, relative to
+    /// the rest of the result set. Set by `filter_trails`.
+    #[serde(default)]
+    pub match_score: Option<u8>,
+    /// The provider had no name for this trail, so `name` was synthesized.
+    #[serde(default)]
+    pub name_generated: bool,
+    /// The provider's own region (e.g. DOC's "Canterbury"), falling back to
+    /// the named area from `REGIONS` containing the trail's position.
+    #[serde(default)]
+    pub region: Option<String>,
+    /// The provider's district or council area, e.g. "Christchurch City".
+    #[serde(default)]
+    pub district: Option<String>,
+    /// The provider's park or local place name, e.g. "Port Hills".
+    #[serde(default)]
+    pub place: Option<String>,
+    /// Sealed surface that can burn paws in the configured summer months.
+    /// `None` when the surface is unknown.
+    #[serde(default)]
+    pub hot_paw_risk: Option<bool>,
+    /// Short shareable id from `trail_slug`; resolves via `/t/:slug`.
+    #[serde(default)]
+    pub slug: String,
+    /// When the data was last confirmed: OSM's `check_date` tag, or the fetch
+    /// date for DOC. `YYYY`, `YYYY-MM` or `YYYY-MM-DD`.
+    #[serde(default)]
+    pub data_checked: Option<String>,
+    /// `data_checked` is older than the service's outdated threshold.
+    #[serde(default)]
+    pub data_outdated: bool,
+    /// A DOC Great Walk or other featured track. `None` when the provider
+    /// doesn't say.
+    #[serde(default)]
+    pub featured: Option<bool>,
+    /// Relative popularity from 0 (quiet) to 1 (busiest), when a provider ranks it.
+    #[serde(default)]
+    pub popularity: Option<f32>,
+    /// Dated dog rules parsed from the provider's text; when one covers today
+    /// it decides `dog_policy`.
+    #[serde(default)]
+    pub dog_seasons: Vec<DogSeason>,
+    /// One-line description from `trail_summary`, e.g. "Easy 5.2 km gravel
+    /// loop, dogs allowed, near Wellington".
+    #[serde(default)]
+    pub summary: String,
+    /// The provider's own data, for debugging the mapping: OSM tags, or the
+    /// DOC record without its line. Only kept with `with_raw_debug`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<serde_json::Value>,
+    /// The provider's text mentions a ford or unbridged river crossing, which
+    /// can sweep a dog away in high water.
+    #[serde(default)]
+    pub water_crossing: bool,
+    /// `provider` as pages should show it, from the service's display names.
+    #[serde(default)]
+    pub provider_name: String,
+    /// Merged providers rated this trail two tiers apart (Easy against Hard),
+    /// so `difficulty` is worth double-checking.
+    #[serde(default)]
+    pub difficulty_conflict: bool,
+    /// Small picture for result cards: DOC's `introductionThumbnail`.
+    #[serde(default)]
+    pub thumbnail_url: Option<String>,
+    /// Larger pictures for a detail gallery, thumbnail excluded.
+    #[serde(default)]
+    pub images: Vec<String>,
+    /// Cautions for result cards, from `trail_warnings`. Pages render these
+    /// rather than keeping their own copy of the wording.
+    #[serde(default)]
+    pub warnings: Vec<TrailWarning>,
+}
+
+/// One caution on a result card. `kind` is a stable key that pages also use
+/// as a CSS class, such as `hot-paw`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TrailWarning {
+    pub kind: String,
+    pub message: String,
 }
 
 #[derive(Clone, Deserialize)]
@@ -87,6 +260,17 @@ pub enum Length {
     Long,
 }
 
+/// Where in the distance range ranking aims: the shortest end, the midpoint,
+/// or the longest end.
+#[derive(Clone, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LengthBias {
+    Shortest,
+    #[default]
+    Balanced,
+    Longest,
+}
+
 #[derive(Clone, Deserialize, Default)]
 pub struct TrailQuery {
     pub min_km: Option<f32>,
@@ -95,14 +279,79 @@ pub struct TrailQuery {
     pub dog: Option<DogFilter>,
     pub effort: Option<Effort>,
     pub length: Option<Length>,
+    /// Rank toward the short or long end of the distance range.
+    pub length_bias: Option<LengthBias>,
+    /// Drop trails with no usable position, matching what the map can draw.
+    pub with_coordinates: Option<bool>,
+    /// Loosen the dog and distance filters when too few trails match.
+    pub relax: Option<bool>,
     pub min_lat: Option<f64>,
     pub min_lon: Option<f64>,
     pub max_lat: Option<f64>,
     pub max_lon: Option<f64>,
+    /// Results to skip, for paging.
+    pub offset: Option<usize>,
+    /// Page size; all results when absent.
+    pub limit: Option<usize>,
+    /// Only trails that pass `is_family_friendly`.
+    pub family_friendly: Option<bool>,
+    /// Only DOC Great Walks and other featured tracks.
+    pub featured_only: Option<bool>,
+    /// Only trails from official providers; community OSM tags are left out.
+    pub trusted_only: Option<bool>,
+    /// Strip macrons from names and places for clients that can't show them.
+    pub ascii: Option<bool>,
+    /// Skip trails close to a better-ranked one; overrides the service default.
+    pub diversify: Option<bool>,
+    /// Attach each trail's `raw` provider data, when the service allows it.
+    pub debug: Option<bool>,
+    /// Drop trails farther than this from the bbox centre.
+    pub max_distance_from_center_km: Option<f32>,
+}
+
+impl TrailQuery {
+    /// Every query parameter the API understands, including `strict` itself.
+    pub const PARAMS: &'static [&'static str] = &[
+        "min_km",
+        "max_km",
+        "difficulty",
+        "dog",
+        "effort",
+        "length",
+        "length_bias",
+        "with_coordinates",
+        "relax",
+        "min_lat",
+        "min_lon",
+        "max_lat",
+        "max_lon",
+        "offset",
+        "limit",
+        "family_friendly",
+        "featured_only",
+        "trusted_only",
+        "ascii",
+        "max_distance_from_center_km",
+        "diversify",
+        "debug",
+        "strict",
+    ];
+
+    /// Keys in `params` that `TrailQuery` would silently ignore, sorted.
+    pub fn unknown_params<'a>(params: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+        let mut unknown: Vec<String> = params
+            .into_iter()
+            .filter(|key| !Self::PARAMS.contains(&key.as_str()))
+            .cloned()
+            .collect();
+        unknown.sort();
+        unknown
+    }
 }
 
 #[derive(Clone, Serialize)]
 pub struct ProviderInfo {
+    pub provider: Provider,
     pub name: String,
     pub api_status: String,
     pub notes: String,
@@ -113,12 +362,14 @@ impl ProviderInfo {
     pub fn default_providers() -> Vec<Self> {
         vec![
             ProviderInfo {
+                provider: Provider::DOC,
                 name: "NZ Department of Conservation (DOC)".to_string(),
                 api_status: "Public API (key required)".to_string(),
                 notes: "Set DOC_API_KEY to enable DOC track data.".to_string(),
                 website: "https://www.doc.govt.nz".to_string(),
             },
             ProviderInfo {
+                provider: Provider::OpenStreetMap,
                 name: "OpenStreetMap Overpass".to_string(),
                 api_status: "Public API".to_string(),
                 notes: "Uses public OSM data with dog access tags when present.".to_string(),
@@ -128,7 +379,73 @@ impl ProviderInfo {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// Aggregate view of a filtered result set for `/api/trails/summary`.
+#[derive(Clone, Serialize)]
+pub struct TrailSummary {
+    pub total: usize,
+    pub by_length: LengthHistogram,
+}
+
+/// Trail counts per distance bucket, aligned with the `Length` presets.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LengthHistogram {
+    pub under_2_km: usize,
+    pub short: usize,
+    pub medium: usize,
+    pub long: usize,
+    pub over_24_km: usize,
+    pub unknown: usize,
+}
+
+/// Side-by-side view of a few trails for `/api/trails/compare`.
+#[derive(Clone, Serialize)]
+pub struct TrailComparison {
+    pub trails: Vec<Trail>,
+    /// Requested ids that were not in any cache.
+    pub missing: Vec<String>,
+    pub diff: ComparisonDiff,
+}
+
+/// Body of the trails-changed webhook: ids a cache refresh added and removed.
+#[derive(Clone, PartialEq, Serialize)]
+pub struct TrailChanges {
+    pub provider: Provider,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Trails walked one after another for `/api/trails/chain`, in walking order.
+#[derive(Clone, Serialize)]
+pub struct TrailChain {
+    pub trails: Vec<Trail>,
+    pub total_km: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ComparisonDiff {
+    /// Longest minus shortest known distance.
+    pub distance_km_spread: f32,
+    /// Highest minus lowest elevation, when at least one trail has one.
+    pub elevation_m_spread: Option<f32>,
+    pub same_difficulty: bool,
+}
+
+/// Distance cutoffs used to grade a trail when the provider gives no grade.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DifficultyThresholds {
+    /// Longest trail still graded `Easy`.
+    pub easy_max_km: f32,
+    /// Longest trail still graded `Moderate`; anything longer is `Hard`.
+    pub moderate_max_km: f32,
+}
+
+impl Default for DifficultyThresholds {
+    fn default() -> Self {
+        Self { easy_max_km: 6.0, moderate_max_km: 14.0 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Bbox {
     pub min_lat: f64,
     pub min_lon: f64,
@@ -147,37 +464,240 @@ impl Default for Bbox {
     }
 }
 
+/// Known areas for grouping results, checked in order; first match wins.
+pub const REGIONS: &[(&str, Bbox)] = &[
+    ("Auckland", Bbox { min_lat: -37.10, min_lon: 174.50, max_lat: -36.60, max_lon: 175.10 }),
+    ("Hamilton", Bbox { min_lat: -37.85, min_lon: 175.15, max_lat: -37.70, max_lon: 175.35 }),
+    ("Tauranga", Bbox { min_lat: -37.80, min_lon: 176.00, max_lat: -37.60, max_lon: 176.35 }),
+    ("Kapiti Coast", Bbox { min_lat: -41.05, min_lon: 174.85, max_lat: -40.70, max_lon: 175.25 }),
+    ("Wellington", Bbox { min_lat: -41.40, min_lon: 174.60, max_lat: -41.05, max_lon: 175.10 }),
+    ("Nelson", Bbox { min_lat: -41.40, min_lon: 173.10, max_lat: -41.20, max_lon: 173.40 }),
+    ("Christchurch", Bbox { min_lat: -43.70, min_lon: 172.40, max_lat: -43.35, max_lon: 172.85 }),
+    ("Queenstown", Bbox { min_lat: -45.10, min_lon: 168.50, max_lat: -44.90, max_lon: 168.90 }),
+    ("Dunedin", Bbox { min_lat: -45.95, min_lon: 170.30, max_lat: -45.75, max_lon: 170.75 }),
+];
+
+/// The first region in `REGIONS` whose box contains the point.
+pub fn region_for(lat: f64, lon: f64) -> Option<&'static str> {
+    REGIONS
+        .iter()
+        .find(|(_, bbox)| bbox.contains(lat, lon))
+        .map(|(name, _)| *name)
+}
+
 impl Bbox {
-    pub fn from_query(query: &TrailQuery) -> Option<Self> {
-        Some(Self {
-            min_lat: query.min_lat?,
-            min_lon: query.min_lon?,
-            max_lat: query.max_lat?,
-            max_lon: query.max_lon?,
-        })
+    /// A grid of boxes covering this one, none more than `span` degrees on a side.
+    pub fn split(&self, span: f64) -> Vec<Bbox> {
+        let (rows, cols) = self.grid(span);
+        let lat_step = (self.max_lat - self.min_lat) / rows as f64;
+        let lon_step = (self.max_lon - self.min_lon) / cols as f64;
+        let mut tiles = Vec::with_capacity(rows * cols);
+        for row in 0..rows {
+            for col in 0..cols {
+                tiles.push(Bbox {
+                    min_lat: self.min_lat + lat_step * row as f64,
+                    min_lon: self.min_lon + lon_step * col as f64,
+                    max_lat: if row + 1 == rows { self.max_lat } else { self.min_lat + lat_step * (row + 1) as f64 },
+                    max_lon: if col + 1 == cols { self.max_lon } else { self.min_lon + lon_step * (col + 1) as f64 },
+                });
+            }
+        }
+        tiles
+    }
+
+    /// Rows and columns `split` would cut this box into.
+    fn grid(&self, span: f64) -> (usize, usize) {
+        // The epsilon keeps float noise from adding a sliver of a row or column.
+        let count = |extent: f64| ((extent / span - 1e-9).ceil() as usize).max(1);
+        (count(self.max_lat - self.min_lat), count(self.max_lon - self.min_lon))
+    }
+
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        lat >= self.min_lat && lat <= self.max_lat && lon >= self.min_lon && lon <= self.max_lon
+    }
+
+    /// The query's box: `None` when it gives no corners, and an error when it
+    /// gives some but not all four, rather than quietly searching the default.
+    pub fn from_query(query: &TrailQuery) -> Result<Option<Self>, TrailError> {
+        match (query.min_lat, query.min_lon, query.max_lat, query.max_lon) {
+            (Some(min_lat), Some(min_lon), Some(max_lat), Some(max_lon)) => {
+                Ok(Some(Self { min_lat, min_lon, max_lat, max_lon }))
+            }
+            (None, None, None, None) => Ok(None),
+            _ => Err(TrailError::InvalidQuery(
+                "bbox needs all of min_lat, min_lon, max_lat and max_lon, or none of them".to_string(),
+            )),
+        }
+    }
+
+    /// Middle of the box as `(lat, lon)`.
+    pub fn center(&self) -> (f64, f64) {
+        ((self.min_lat + self.max_lat) / 2.0, (self.min_lon + self.max_lon) / 2.0)
     }
 }
 
 #[derive(Debug)]
-pub struct TrailError(pub String);
+pub enum TrailError {
+    /// The service cannot run with the configuration it was given.
+    Config(String),
+    /// A provider request failed or returned something unusable.
+    Upstream(String),
+    /// A provider didn't answer in time.
+    Network(String),
+    /// The search parameters don't make sense together.
+    InvalidQuery(String),
+}
 
 impl std::fmt::Display for TrailError {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        formatter.write_str(&self.0)
+        match self {
+            TrailError::Config(message) => write!(formatter, "configuration error: {message}"),
+            TrailError::Upstream(message) | TrailError::Network(message) | TrailError::InvalidQuery(message) => {
+                formatter.write_str(message)
+            }
+        }
     }
 }
 
 impl std::error::Error for TrailError {}
 
+/// A failed send to a provider. Timeouts become `Network` and name the limit
+/// so they stand out from refusals and resets.
+pub(crate) fn request_error(what: &str, err: reqwest::Error, timeout: Duration) -> TrailError {
+    if err.is_timeout() {
+        TrailError::Network(format!("{what} timed out after {timeout:?}"))
+    } else {
+        TrailError::Upstream(format!("{what} failed: {err}"))
+    }
+}
+
 pub struct TrailService {
     client: reqwest::Client,
     overpass_urls: Vec<String>,
-    overpass_cache: RwLock<Option<OverpassCacheEntry>>,
-    overpass_semaphore: tokio::sync::Semaphore,
+    overpass_cache: Arc<RwLock<Option<OverpassCacheEntry>>>,
+    overpass_semaphore: Arc<tokio::sync::Semaphore>,
+    overpass_stale_grace: Duration,
+    /// Boxes with a background refresh in flight, so each is refreshed once.
+    overpass_refreshing: Arc<RwLock<Vec<Bbox>>>,
+    /// Largest Overpass query side in degrees; bigger boxes are split into tiles.
+    overpass_tile_deg: Option<f64>,
+    /// Most Overpass tiles fetched at once for one search.
+    tile_concurrency: usize,
+    /// Shared cap on outbound HTTP requests across every provider.
+    outbound: Arc<tokio::sync::Semaphore>,
+    max_outbound_requests: usize,
+    metrics: Arc<Metrics>,
+    /// Per-request limit for Overpass and DOC calls.
+    request_timeout: Duration,
+    difficulty_thresholds: DifficultyThresholds,
+    overpass_health: RwLock<HashMap<String, MirrorHealth>>,
     doc_summary_cache: RwLock<Option<DocSummaryCache>>,
     doc_detail_cache: RwLock<HashMap<String, Value>>,
+    /// Consecutive detail failures per DOC track and when the last one was.
+    doc_detail_failures: RwLock<HashMap<String, (u32, Instant)>>,
+    doc_detail_failure_limit: u32,
+    doc_detail_cooldown: Duration,
     doc_semaphore: tokio::sync::Semaphore,
+    doc_summary_ttl: Duration,
+    doc_list_retry: doc::ListRetry,
+    backoff_seed: Option<u64>,
     doc_api_key: String,
+    doc_base_url: String,
+    include_unnamed: bool,
+    distance_model: DistanceModel,
+    hard_terrain_caution: bool,
+    summer_months: Vec<u32>,
+    /// POSTed a `TrailChanges` when a refresh adds or removes trails.
+    change_webhook: Option<String>,
+    surface_classes: SurfaceClasses,
+    diversify_by_default: bool,
+    raw_debug: bool,
+    diversify_spacing_km: f64,
+    outdated_after_years: i64,
+    max_line_points: usize,
+    /// Decimal places kept in handed-out coordinates.
+    coordinate_decimals: u32,
+    dog_notes: DogNotes,
+    dem_url: Option<String>,
+    lazy_doc_details: bool,
+    /// Cache DOC tracks without their `line`; `trail()` attaches it from detail.
+    slim_doc_cache: bool,
+    provider_priority: Vec<Provider>,
+    score_weights: ScoreWeights,
+    filter_pipeline: FilterPipeline,
+    provider_rules: Vec<(Provider, ProviderRule)>,
+    display_names: Vec<(Provider, String)>,
+    profile_cache: RwLock<HashMap<String, Vec<ProfilePoint>>>,
+}
+
+const HARD_TERRAIN_CAUTION: &str = "Hard terrain — assess your dog's fitness.";
+
+const WATER_CROSSING_WARNING: &str = "Water crossing — fords or unbridged rivers can be dangerous for dogs after rain.";
+
+const DIFFICULTY_CONFLICT_WARNING: &str = "Sources disagree on how hard this trail is — check before you go.";
+
+const HOT_PAW_WARNING: &str = "Hot surface — check it with your hand before walking your dog.";
+
+const DEFAULT_MAX_OUTBOUND_REQUESTS: usize = 4;
+
+/// Two tiles at a time leaves outbound slots free for DOC and DEM calls.
+const DEFAULT_TILE_CONCURRENCY: usize = 2;
+
+/// Most tiles one Overpass fetch is split into. A large box with a small
+/// `OVERPASS_TILE_DEG` gets wider tiles instead of thousands of queries.
+const MAX_OVERPASS_TILES: usize = 16;
+
+/// Six decimal places is about 0.1 m, finer than any trail data we get.
+const DEFAULT_COORDINATE_DECIMALS: u32 = 6;
+
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the DOC track list is trusted before it's fetched again.
+const DOC_SUMMARY_TTL: Duration = Duration::from_secs(60 * 60 * 12);
+
+/// Retries for the DOC track list request before a search gives up on DOC.
+const DEFAULT_DOC_LIST_RETRIES: u32 = 2;
+const DOC_LIST_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Failed detail fetches in a row before a DOC track is left summary-only.
+const DEFAULT_DETAIL_FAILURE_LIMIT: u32 = 3;
+/// How long a track that hit the limit is skipped before detail is tried again.
+const DETAIL_FAILURE_COOLDOWN: Duration = Duration::from_secs(30 * 60);
+
+/// First and longest waits between failed background DOC refreshes.
+const DOC_REFRESH_MIN_BACKOFF: Duration = Duration::from_secs(30);
+const DOC_REFRESH_MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+const OVERPASS_TTL: Duration = Duration::from_secs(600);
+
+/// Where the Overpass part of a response came from, for `X-Cache`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CacheStatus {
+    Hit,
+    Stale,
+    Miss,
+}
+
+impl CacheStatus {
+    pub fn as_header(&self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "HIT",
+            CacheStatus::Stale => "STALE",
+            CacheStatus::Miss => "MISS",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CacheInfo {
+    pub status: CacheStatus,
+    pub age_secs: u64,
+}
+
+impl CacheInfo {
+    fn new(status: CacheStatus, age: Duration) -> Self {
+        Self { status, age_secs: age.as_secs() }
+    }
 }
 
 struct OverpassCacheEntry {
@@ -186,6 +706,14 @@ struct OverpassCacheEntry {
     trails: Vec<Trail>,
 }
 
+/// Latest probe result for one Overpass mirror.
+#[derive(Clone, Copy)]
+enum MirrorHealth {
+    /// Rolling latency estimate in milliseconds.
+    Healthy(f64),
+    Failed,
+}
+
 /// Cached list of summary-only Trail objects from /v1/tracks.
 struct DocSummaryCache {
     fetched_at: Instant,
@@ -194,337 +722,4332 @@ struct DocSummaryCache {
 
 impl TrailService {
     pub fn new(overpass_urls: Vec<String>, doc_api_key: String) -> Result<Self, TrailError> {
+        if overpass_urls.is_empty() && doc_api_key.trim().is_empty() {
+            return Err(TrailError::Config(
+                "no providers configured: set OVERPASS_URL or DOC_API_KEY".to_string(),
+            ));
+        }
         let client = reqwest::Client::builder()
             .user_agent("dogtrails/0.1 (https://example.local)")
             .build()
-            .map_err(|err| TrailError(format!("failed to build http client: {err}")))?;
+            .map_err(|err| TrailError::Config(format!("failed to build http client: {err}")))?;
         Ok(Self {
             client,
             overpass_urls,
-            overpass_cache: RwLock::new(None),
-            overpass_semaphore: tokio::sync::Semaphore::new(1),
+            overpass_cache: Arc::new(RwLock::new(None)),
+            overpass_semaphore: Arc::new(tokio::sync::Semaphore::new(1)),
+            overpass_refreshing: Arc::new(RwLock::new(Vec::new())),
+            overpass_stale_grace: Duration::from_secs(300),
+            overpass_tile_deg: None,
+            tile_concurrency: DEFAULT_TILE_CONCURRENCY,
+            outbound: Arc::new(tokio::sync::Semaphore::new(DEFAULT_MAX_OUTBOUND_REQUESTS)),
+            max_outbound_requests: DEFAULT_MAX_OUTBOUND_REQUESTS,
+            metrics: Arc::new(Metrics::default()),
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            difficulty_thresholds: DifficultyThresholds::default(),
+            overpass_health: RwLock::new(HashMap::new()),
             doc_summary_cache: RwLock::new(None),
             doc_detail_cache: RwLock::new(HashMap::new()),
+            doc_detail_failures: RwLock::new(HashMap::new()),
+            doc_detail_failure_limit: DEFAULT_DETAIL_FAILURE_LIMIT,
+            doc_detail_cooldown: DETAIL_FAILURE_COOLDOWN,
             doc_semaphore: tokio::sync::Semaphore::new(1),
+            doc_summary_ttl: DOC_SUMMARY_TTL,
+            doc_list_retry: doc::ListRetry {
+                retries: DEFAULT_DOC_LIST_RETRIES,
+                backoff: DOC_LIST_RETRY_BACKOFF,
+                seed: None,
+            },
+            backoff_seed: None,
             doc_api_key,
+            doc_base_url: doc::DOC_API_BASE.to_string(),
+            include_unnamed: false,
+            distance_model: DistanceModel::default(),
+            hard_terrain_caution: true,
+            summer_months: vec![12, 1, 2],
+            change_webhook: None,
+            surface_classes: SurfaceClasses::default(),
+            diversify_by_default: false,
+            raw_debug: false,
+            diversify_spacing_km: DEFAULT_DIVERSIFY_SPACING_KM,
+            outdated_after_years: 3,
+            coordinate_decimals: DEFAULT_COORDINATE_DECIMALS,
+            max_line_points: 500,
+            dog_notes: DogNotes::default(),
+            dem_url: None,
+            lazy_doc_details: false,
+            slim_doc_cache: false,
+            provider_priority: DEFAULT_PROVIDER_PRIORITY.to_vec(),
+            score_weights: ScoreWeights::default(),
+            filter_pipeline: FilterPipeline::default(),
+            provider_rules: Vec::new(),
+            display_names: Vec::new(),
+            profile_cache: RwLock::new(HashMap::new()),
         })
     }
 
-    pub async fn fetch_trails(&self, query: &TrailQuery) -> Result<Vec<Trail>, TrailError> {
-        let bbox = Bbox::from_query(query).unwrap_or_default();
-        let mut combined = self.fetch_overpass_cached(bbox).await?;
-        combined.extend(self.fetch_doc_cached(bbox).await?);
-        
-        Ok(combined)
+    /// Enable elevation profiles using an Open-Elevation compatible DEM service.
+    pub fn with_dem_url(mut self, dem_url: impl Into<String>) -> Self {
+        self.dem_url = Some(dem_url.into());
+        self
     }
 
-    async fn fetch_overpass_cached(&self, bbox: Bbox) -> Result<Vec<Trail>, TrailError> {
-        let ttl = Duration::from_secs(600);
+    /// Most outbound requests (Overpass, DOC, DEM) allowed in flight at once.
+    pub fn with_max_outbound_requests(mut self, max_outbound_requests: usize) -> Self {
+        self.max_outbound_requests = max_outbound_requests.max(1);
+        self.outbound = Arc::new(tokio::sync::Semaphore::new(self.max_outbound_requests));
+        self
+    }
 
-        if let Some(cached) = self.overpass_cache.read().await.as_ref() {
-            if cached.bbox == bbox && cached.fetched_at.elapsed() < ttl {
-                return Ok(cached.trails.clone());
-            }
-        }
+    /// How long one Overpass or DOC request may take before it fails as timed out.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
 
-        // Only allow one in-flight Overpass request at a time
-        let permit = match self.overpass_semaphore.try_acquire() {
-            Ok(permit) => permit,
-            Err(_) => {
-                // Another request is in-flight; serve stale cache if available
-                if let Some(cached) = self.overpass_cache.read().await.as_ref() {
-                    tracing::debug!("overpass request in-flight, serving cached data");
-                    return Ok(cached.trails.clone());
-                }
-                // No cache at all; wait for the permit
-                self.overpass_semaphore.acquire().await
-                    .map_err(|_| TrailError("semaphore closed".to_string()))?
-            }
-        };
+    /// Distance cutoffs for trails the provider doesn't grade, for OSM and DOC alike.
+    pub fn with_difficulty_thresholds(mut self, difficulty_thresholds: DifficultyThresholds) -> Self {
+        self.difficulty_thresholds = difficulty_thresholds;
+        self
+    }
 
-        // Re-check cache after acquiring permit (another request may have just finished)
-        if let Some(cached) = self.overpass_cache.read().await.as_ref() {
-            if cached.bbox == bbox && cached.fetched_at.elapsed() < ttl {
-                drop(permit);
-                return Ok(cached.trails.clone());
-            }
-        }
+    /// Start from existing counters, e.g. ones resumed from a snapshot log.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Arc::new(metrics);
+        self
+    }
 
-        let trails = overpass::fetch_overpass_with_fallback(&self.client, &self.overpass_urls, bbox).await?;
-        let mut cache = self.overpass_cache.write().await;
-        *cache = Some(OverpassCacheEntry {
-            fetched_at: Instant::now(),
-            bbox,
-            trails: trails.clone(),
-        });
-        drop(permit);
-        Ok(trails)
+    /// Counters shared with the HTTP layer.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
     }
 
-    async fn fetch_doc_cached(&self, bbox: Bbox) -> Result<Vec<Trail>, TrailError> {
-        let ttl = Duration::from_secs(60 * 60 * 12);
+    /// Outbound requests currently holding a slot.
+    pub fn outbound_in_flight(&self) -> usize {
+        self.max_outbound_requests.saturating_sub(self.outbound.available_permits())
+    }
 
-        // 1. Ensure the summary list is cached.
-        {
-            let needs_fetch = match self.doc_summary_cache.read().await.as_ref() {
-                Some(cached) => cached.fetched_at.elapsed() >= ttl,
-                None => true,
-            };
-            if needs_fetch {
-                // Only allow one in-flight DOC summary request at a time
-                let permit = match self.doc_semaphore.try_acquire() {
-                    Ok(permit) => permit,
-                    Err(_) => {
-                        if let Some(cached) = self.doc_summary_cache.read().await.as_ref() {
-                            tracing::debug!("DOC summary request in-flight, serving cached data");
-                            let visible = doc::filter_doc_by_bbox(&cached.trails, bbox);
-                            return self.enrich_visible(&self.doc_api_key, visible).await;
-                        }
-                        self.doc_semaphore.acquire().await
-                            .map_err(|_| TrailError("semaphore closed".to_string()))?
-                    }
-                };
+    async fn outbound_permit(&self) -> Result<tokio::sync::OwnedSemaphorePermit, TrailError> {
+        self.outbound
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| TrailError::Upstream("semaphore closed".to_string()))
+    }
 
-                // Re-check after acquiring permit
-                let still_needs = match self.doc_summary_cache.read().await.as_ref() {
-                    Some(cached) => cached.fetched_at.elapsed() >= ttl,
-                    None => true,
-                };
-                if still_needs {
-                    let trails = doc::fetch_doc_summaries(&self.client, &self.doc_api_key).await?;
-                    *self.doc_summary_cache.write().await = Some(DocSummaryCache {
-                        fetched_at: Instant::now(),
-                        trails,
-                    });
-                }
-                drop(permit);
-            }
-        }
+    /// Split Overpass queries into tiles no more than `tile_deg` degrees on a side.
+    pub fn with_overpass_tiles(mut self, tile_deg: f64) -> Self {
+        self.overpass_tile_deg = (tile_deg > 0.0).then_some(tile_deg);
+        self
+    }
 
-        // 2. Filter by bbox.
-        let visible = {
-            let guard = self.doc_summary_cache.read().await;
-            let cache = guard.as_ref().unwrap();
-            doc::filter_doc_by_bbox(&cache.trails, bbox)
-        };
+    /// Most Overpass tiles fetched at once; each still takes an outbound slot.
+    pub fn with_tile_concurrency(mut self, tile_concurrency: usize) -> Self {
+        self.tile_concurrency = tile_concurrency.max(1);
+        self
+    }
 
-        // 3. Enrich visible trails with cached details.
-        self.enrich_visible(&self.doc_api_key, visible).await
+    /// How long past its TTL the Overpass cache may still be served while a
+    /// background refresh runs.
+    pub fn with_overpass_stale_grace(mut self, grace: Duration) -> Self {
+        self.overpass_stale_grace = grace;
+        self
     }
 
-    /// Fetch and cache detail for each visible trail, enriching it in place.
-    async fn enrich_visible(&self, api_key: &str, mut trails: Vec<Trail>) -> Result<Vec<Trail>, TrailError> {
-        for trail in &mut trails {
-            // Check detail cache
-            if let Some(detail) = self.doc_detail_cache.read().await.get(&trail.id) {
-                doc::enrich_with_detail(trail, detail);
-                continue;
-            }
+    /// Order in which providers win score ties; unlisted providers go last.
+    pub fn with_provider_priority(mut self, provider_priority: Vec<Provider>) -> Self {
+        self.provider_priority = provider_priority;
+        self
+    }
 
-            // Fetch detail and cache it
-            match doc::fetch_doc_detail(&self.client, api_key, &trail.id).await {
-                Ok(detail) => {
-                    doc::enrich_with_detail(trail, &detail);
-                    self.doc_detail_cache.write().await.insert(trail.id.clone(), detail);
-                }
-                Err(err) => {
-                    tracing::warn!("DOC detail fetch failed for {}: {}", trail.id, err);
-                }
-            }
-        }
-        Ok(trails)
+    pub fn provider_priority(&self) -> &[Provider] {
+        &self.provider_priority
     }
-}
 
-pub fn filter_trails(trails: &[Trail], query: &TrailQuery) -> Vec<Trail> {
-    let dog_filter = query.dog.clone().unwrap_or(DogFilter::AllowedOrPartial);
-    let range = derive_distance_range(query);
-    let effort = query.effort.clone();
+    /// Weights for ranking search results; see `filter_trails_weighted`.
+    pub fn with_score_weights(mut self, score_weights: ScoreWeights) -> Self {
+        self.score_weights = score_weights;
+        self
+    }
 
-    let mut matches: Vec<(Trail, f32)> = trails
-        .iter()
-        .cloned()
-        .filter(|trail| dog_policy_allows(trail, &dog_filter))
-        .filter(|trail| match query.difficulty {
-            Some(ref difficulty) => &trail.difficulty == difficulty,
-            None => true,
-        })
-        .filter(|trail| within_distance(trail.distance_km, &range))
-        .map(|trail| {
-            let score = score_trail(&trail, &range, effort.as_ref());
-            (trail, score)
-        })
-        .collect();
+    pub fn score_weights(&self) -> &ScoreWeights {
+        &self.score_weights
+    }
 
-    matches.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
-    matches
-        .into_iter()
-        .map(|(trail, _)| trail)
-        .collect()
-}
+    /// Notify `url` when a cache refresh brings new or removed trails.
+    pub fn with_change_webhook(mut self, url: impl Into<String>) -> Self {
+        self.change_webhook = Some(url.into());
+        self
+    }
 
-pub(crate) fn map_difficulty(sac_scale: Option<&String>, distance_km: f32) -> Difficulty {
-    if let Some(scale) = sac_scale {
-        return match scale.as_str() {
-            "hiking" => Difficulty::Easy,
-            "mountain_hiking" => Difficulty::Moderate,
-            "demanding_mountain_hiking" | "alpine_hiking" => Difficulty::Hard,
-            _ => Difficulty::Moderate,
-        };
+    /// Which surfaces count as natural or hard, for local tagging conventions.
+    pub fn with_surface_classes(mut self, surface_classes: SurfaceClasses) -> Self {
+        self.surface_classes = surface_classes;
+        self
     }
 
-    if distance_km <= 6.0 {
-        Difficulty::Easy
-    } else if distance_km <= 14.0 {
-        Difficulty::Moderate
-    } else {
-        Difficulty::Hard
+    pub fn surface_classes(&self) -> &SurfaceClasses {
+        &self.surface_classes
     }
-}
 
-fn dog_policy_allows(trail: &Trail, filter: &DogFilter) -> bool {
-    match filter {
-        DogFilter::AllowedOnly => {
-            trail.dog_policy == DogPolicy::Allowed || trail.dog_policy == DogPolicy::Unknown
+    /// Whether searches without a `diversify` parameter are spread out, and
+    /// how close two trails can be before the lower-ranked one is dropped.
+    pub fn with_diversify(mut self, by_default: bool, spacing_km: f64) -> Self {
+        self.diversify_by_default = by_default;
+        self.diversify_spacing_km = spacing_km;
+        self
+    }
+
+    /// Keep each trail's source tags or JSON so `debug=true` searches can show
+    /// them. Off by default: it costs memory and exposes upstream data as is.
+    pub fn with_raw_debug(mut self, raw_debug: bool) -> Self {
+        self.raw_debug = raw_debug;
+        self
+    }
+
+    /// `trails` with `raw` dropped unless both the service and the query ask
+    /// for it.
+    pub fn apply_debug(&self, mut trails: Vec<Trail>, query: &TrailQuery) -> Vec<Trail> {
+        if !(self.raw_debug && query.debug.unwrap_or(false)) {
+            for trail in &mut trails {
+                trail.raw = None;
+            }
+        }
+        trails
+    }
+
+    /// Ranked `trails` thinned out by `diversify_trails` when the query or the
+    /// service default asks for it.
+    pub fn diversify(&self, trails: Vec<Trail>, query: &TrailQuery) -> Vec<Trail> {
+        if query.diversify.unwrap_or(self.diversify_by_default) {
+            diversify_trails(trails, self.diversify_spacing_km)
+        } else {
+            trails
+        }
+    }
+
+    pub fn with_filter_pipeline(mut self, filter_pipeline: FilterPipeline) -> Self {
+        self.filter_pipeline = filter_pipeline;
+        self
+    }
+
+    /// Show `provider` as `name` instead of its `display_name`.
+    pub fn with_display_name(mut self, provider: Provider, name: impl Into<String>) -> Self {
+        self.display_names.retain(|(existing, _)| *existing != provider);
+        self.display_names.push((provider, name.into()));
+        self
+    }
+
+    /// `provider`'s configured display name, or its default.
+    pub fn display_name(&self, provider: &Provider) -> &str {
+        self.display_names
+            .iter()
+            .find(|(existing, _)| existing == provider)
+            .map_or(provider.display_name(), |(_, name)| name.as_str())
+    }
+
+    /// An extra check for `provider`'s trails, applied by the per-provider pipeline.
+    pub fn with_provider_rule(mut self, provider: Provider, rule: ProviderRule) -> Self {
+        self.provider_rules.push((provider, rule));
+        self
+    }
+
+    /// Rank `trails` for `query` through the configured pipeline, with this
+    /// service's provider priority and score weights.
+    pub fn filter_trails(&self, trails: &[Trail], query: &TrailQuery) -> Vec<Trail> {
+        match self.filter_pipeline {
+            FilterPipeline::Combined => self.rank(trails.iter(), query),
+            FilterPipeline::PerProvider => {
+                // Each provider's own rules first, then one shared filter and ranking pass.
+                let filtered = self.rank(trails.iter().filter(|trail| self.passes_provider_rules(trail, query)), query);
+                for provider in [Provider::DOC, Provider::OpenStreetMap] {
+                    let count = |trails: &[Trail]| trails.iter().filter(|trail| trail.provider == provider).count();
+                    tracing::debug!("{}: {} of {} trails kept", provider, count(&filtered), count(trails));
+                }
+                filtered
+            }
+        }
+    }
+
+    /// `provider`'s share of `trails` through its own rules and the shared filters.
+    pub fn filter_provider_trails(&self, provider: &Provider, trails: &[Trail], query: &TrailQuery) -> Vec<Trail> {
+        let own = trails
+            .iter()
+            .filter(|trail| trail.provider == *provider && self.passes_provider_rules(trail, query));
+        self.rank(own, query)
+    }
+
+    /// `filter_trails_relaxed` through this service's pipeline, so relaxed
+    /// results still obey the provider rules.
+    pub fn filter_trails_relaxed(&self, trails: &[Trail], query: &TrailQuery, min_results: usize) -> (Vec<Trail>, bool) {
+        relax_filter(trails, query, min_results, |trails, query| self.filter_trails(trails, query))
+    }
+
+    fn passes_provider_rules(&self, trail: &Trail, query: &TrailQuery) -> bool {
+        self.provider_rules
+            .iter()
+            .filter(|(provider, _)| *provider == trail.provider)
+            .all(|(_, rule)| rule(trail, query))
+    }
+
+    fn rank<'a>(&self, trails: impl IntoIterator<Item = &'a Trail>, query: &TrailQuery) -> Vec<Trail> {
+        rank_trails(trails, query, &self.provider_priority, &self.score_weights, &self.surface_classes)
+    }
+
+    /// Serve DOC searches from the summary list alone; detail is fetched per
+    /// trail by `trail()` instead of for every visible track.
+    pub fn with_lazy_doc_details(mut self, lazy_doc_details: bool) -> Self {
+        self.lazy_doc_details = lazy_doc_details;
+        self
+    }
+
+    /// Keep the nationwide DOC list without polylines to cap memory. Bbox
+    /// filtering still works from `line_bbox`; a trail's line comes back with
+    /// its detail, at the cost of a detail fetch.
+    pub fn with_slim_doc_cache(mut self, slim_doc_cache: bool) -> Self {
+        self.slim_doc_cache = slim_doc_cache;
+        self
+    }
+
+    /// Replace built-in dog notes with operator-supplied wording where it matches.
+    pub fn with_dog_notes(mut self, dog_notes: DogNotes) -> Self {
+        self.dog_notes = dog_notes;
+        self
+    }
+
+    /// Cap each trail's `line` at this many points, simplifying as needed.
+    pub fn with_max_line_points(mut self, max_line_points: usize) -> Self {
+        self.max_line_points = max_line_points;
+        self
+    }
+
+    /// Round handed-out positions and line points to this many decimal places.
+    /// Cached trails keep full precision.
+    pub fn with_coordinate_decimals(mut self, coordinate_decimals: u32) -> Self {
+        self.coordinate_decimals = coordinate_decimals;
+        self
+    }
+
+    /// Point DOC requests at a different API root (e.g. a local mock).
+    pub fn with_doc_base_url(mut self, doc_base_url: impl Into<String>) -> Self {
+        self.doc_base_url = doc_base_url.into();
+        self
+    }
+
+    /// Retry a failed DOC list request `retries` times; 2 by default.
+    pub fn with_doc_list_retries(mut self, retries: u32) -> Self {
+        self.doc_list_retry.retries = retries;
+        self
+    }
+
+    /// Wait before the first DOC list retry, doubling after that; 1s by default.
+    pub fn with_doc_list_backoff(mut self, backoff: Duration) -> Self {
+        self.doc_list_retry.backoff = backoff;
+        self
+    }
+
+    /// Seed the jitter on DOC and Overpass retry waits, and on the background
+    /// DOC refresher's waits after a failure, so they come out the same every
+    /// run. Without it each retry sequence is seeded at random.
+    pub fn with_backoff_seed(mut self, seed: u64) -> Self {
+        self.doc_list_retry.seed = Some(seed);
+        self.backoff_seed = Some(seed);
+        self
+    }
+
+    /// Stop fetching detail for a DOC track after `limit` failures in a row,
+    /// serving it summary-only until the cooldown has passed; 3 by default.
+    pub fn with_doc_detail_failure_limit(mut self, limit: u32) -> Self {
+        self.doc_detail_failure_limit = limit.max(1);
+        self
+    }
+
+    /// How long a DOC track that hit the failure limit goes without detail
+    /// requests; 30 minutes by default.
+    pub fn with_doc_detail_cooldown(mut self, cooldown: Duration) -> Self {
+        self.doc_detail_cooldown = cooldown;
+        self
+    }
+
+    /// How long the DOC track list is cached; 12 hours by default.
+    pub fn with_doc_summary_ttl(mut self, doc_summary_ttl: Duration) -> Self {
+        self.doc_summary_ttl = doc_summary_ttl;
+        self
+    }
+
+    /// Add a fitness caution to the dog notes of hard trails that allow dogs.
+    pub fn with_hard_terrain_caution(mut self, hard_terrain_caution: bool) -> Self {
+        self.hard_terrain_caution = hard_terrain_caution;
+        self
+    }
+
+    /// Months (1–12) in which sealed surfaces are flagged as too hot for paws.
+    pub fn with_summer_months(mut self, summer_months: Vec<u32>) -> Self {
+        self.summer_months = summer_months;
+        self
+    }
+
+    /// Years after `data_checked` before a trail is flagged as possibly outdated.
+    pub fn with_outdated_after_years(mut self, years: i64) -> Self {
+        self.outdated_after_years = years;
+        self
+    }
+
+    /// Formula for trail lengths computed from geometry.
+    pub fn with_distance_model(mut self, distance_model: DistanceModel) -> Self {
+        self.distance_model = distance_model;
+        self
+    }
+
+    fn overpass_options(&self) -> overpass::OverpassOptions {
+        overpass::OverpassOptions {
+            include_unnamed: self.include_unnamed,
+            distance_model: self.distance_model,
+            timeout: self.request_timeout,
+            difficulty_thresholds: self.difficulty_thresholds,
+            backoff_seed: self.backoff_seed,
+            keep_raw: self.raw_debug,
+            max_line_points: self.max_line_points,
+        }
+    }
+
+    /// Keep unnamed OSM ways, giving them a generated name, instead of dropping them.
+    pub fn with_include_unnamed(mut self, include_unnamed: bool) -> Self {
+        self.include_unnamed = include_unnamed;
+        self
+    }
+
+    /// Provider metadata with `api_status` reflecting this service's
+    /// configuration and, once `probe_overpass_mirrors` has run, its result.
+    pub async fn provider_info(&self) -> Vec<ProviderInfo> {
+        let health = self.overpass_health.read().await;
+        let probed: Vec<&MirrorHealth> = self.overpass_urls.iter().filter_map(|url| health.get(url)).collect();
+        let healthy = probed.iter().filter(|mirror| matches!(mirror, MirrorHealth::Healthy(_))).count();
+        let failed = probed.len() - healthy;
+        let mut providers = ProviderInfo::default_providers();
+        for provider in &mut providers {
+            provider.api_status = match provider.provider {
+                Provider::DOC if self.doc_api_key.trim().is_empty() => "no key".to_string(),
+                Provider::DOC => "enabled".to_string(),
+                Provider::OpenStreetMap if !probed.is_empty() && healthy == 0 => "unavailable (every mirror failed the last probe)".to_string(),
+                Provider::OpenStreetMap if !probed.is_empty() => format!("enabled ({healthy} healthy, {failed} failed)"),
+                Provider::OpenStreetMap => match self.overpass_urls.len() {
+                    0 => "no mirrors configured".to_string(),
+                    1 => "enabled (1 mirror)".to_string(),
+                    count => format!("enabled ({count} mirrors)"),
+                },
+            };
+        }
+        providers
+    }
+
+    /// Resolve a `trail_slug` against every cached trail, then load it as
+    /// `trail()` does, fetching DOC detail if it isn't cached yet.
+    pub async fn trail_by_slug(&self, slug: &str) -> Result<Option<Trail>, TrailError> {
+        let matches = |trail: &&Trail| trail_slug(&trail.provider, &trail.id) == slug;
+        let mut id = self
+            .doc_summary_cache
+            .read()
+            .await
+            .as_ref()
+            .and_then(|cached| cached.trails.iter().find(matches).map(|trail| trail.id.clone()));
+        if id.is_none() {
+            id = self
+                .overpass_cache
+                .read()
+                .await
+                .as_ref()
+                .and_then(|cached| cached.trails.iter().find(matches).map(|trail| trail.id.clone()));
+        }
+        match id {
+            Some(id) => self.trail(&id).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a trail by id in the DOC and Overpass caches. Nothing is
+    /// fetched, except that with `slim_doc_cache` a DOC track's missing line
+    /// is filled in from its detail.
+    pub async fn find_by_id(&self, id: &str) -> Option<Trail> {
+        let mut trail = self.cached_trail_raw(id).await?;
+        if self.slim_doc_cache && trail.line.is_empty() {
+            if let Err(err) = self.load_doc_detail(&mut trail).await {
+                tracing::warn!("DOC detail for {} failed: {}", id, err);
+            }
+        }
+        self.finish_trail(&mut trail);
+        Some(trail)
+    }
+
+    /// Fetch, cache and apply DOC detail for `trail` unless it's already cached
+    /// or the track is cooling down after repeated failures.
+    async fn load_doc_detail(&self, trail: &mut Trail) -> Result<(), TrailError> {
+        if trail.provider != Provider::DOC
+            || self.doc_detail_cache.read().await.contains_key(&trail.id)
+            || self.doc_detail_cooling_down(&trail.id).await
+        {
+            return Ok(());
+        }
+        let outbound = self.outbound_permit().await?;
+        let fetched = doc::fetch_doc_detail(&self.client, &self.doc_base_url, &self.doc_api_key, &trail.id, self.request_timeout).await;
+        drop(outbound);
+        let mut detail = match fetched {
+            Ok(detail) => detail,
+            Err(err) => {
+                self.metrics.record_upstream_error(&Provider::DOC);
+                self.record_doc_detail_failure(&trail.id).await;
+                return Err(err);
+            }
+        };
+        doc::simplify_detail_line(&mut detail, self.max_line_points);
+        doc::enrich_with_detail(trail, &detail);
+        self.doc_detail_failures.write().await.remove(&trail.id);
+        self.doc_detail_cache.write().await.insert(trail.id.clone(), detail);
+        Ok(())
+    }
+
+    /// Whether `id` has hit the detail failure limit and its cooldown hasn't ended.
+    async fn doc_detail_cooling_down(&self, id: &str) -> bool {
+        self.doc_detail_failures
+            .read()
+            .await
+            .get(id)
+            .is_some_and(|(count, last)| *count >= self.doc_detail_failure_limit && last.elapsed() < self.doc_detail_cooldown)
+    }
+
+    /// Count a failed detail fetch for `id` towards the failure limit.
+    async fn record_doc_detail_failure(&self, id: &str) {
+        let mut failures = self.doc_detail_failures.write().await;
+        let entry = failures.entry(id.to_string()).or_insert((0, Instant::now()));
+        *entry = (entry.0 + 1, Instant::now());
+        // Forget failures older than the cooldown so tracks that
+        // recovered, or left the cache, don't pile up here.
+        failures.retain(|_, (_, last)| last.elapsed() < self.doc_detail_cooldown);
+    }
+
+    /// A single trail by id, fetching and caching DOC detail if it hasn't been
+    /// loaded yet. A failed detail fetch is logged and the summary trail
+    /// returned. `Ok(None)` means the id isn't in any cache.
+    pub async fn trail(&self, id: &str) -> Result<Option<Trail>, TrailError> {
+        let Some(mut trail) = self.cached_trail_raw(id).await else {
+            return Ok(None);
+        };
+        if let Err(err) = self.load_doc_detail(&mut trail).await {
+            tracing::warn!("DOC detail for {} failed: {}", id, err);
+        }
+        if let Some(dem_url) = self.dem_url.as_deref()
+            && !trail.line.is_empty()
+        {
+            match self.trail_profile(dem_url, &trail).await {
+                Ok(profile) => apply_elevation_change(&mut trail, &profile),
+                Err(err) => tracing::warn!("DEM profile failed for {}: {}", id, err),
+            }
+        }
+        self.finish_trail(&mut trail);
+        Ok(Some(trail))
+    }
+
+    /// DOC cache first, then Overpass, taking the first match in each. Ids
+    /// are provider-prefixed so a collision means a bug upstream; it's logged
+    /// and resolved the same way every time.
+    async fn cached_trail_raw(&self, id: &str) -> Option<Trail> {
+        let mut matches: Vec<Trail> = Vec::new();
+        if let Some(cached) = self.doc_summary_cache.read().await.as_ref() {
+            matches.extend(cached.trails.iter().filter(|trail| trail.id == id).cloned());
+        }
+        if let Some(cached) = self.overpass_cache.read().await.as_ref() {
+            matches.extend(cached.trails.iter().filter(|trail| trail.id == id).cloned());
+        }
+        if matches.len() > 1 {
+            tracing::warn!("{} cached trails share id {}, using the first", matches.len(), id);
+        }
+        let mut trail = matches.into_iter().next()?;
+        if trail.provider == Provider::DOC
+            && let Some(detail) = self.doc_detail_cache.read().await.get(id)
+        {
+            doc::enrich_with_detail(&mut trail, detail);
+        }
+        Some(trail)
+    }
+
+    /// Elevation profile along a cached trail's line, sampled from the DEM
+    /// service and cached per trail. `Ok(None)` means the trail isn't cached.
+    pub async fn elevation_profile(&self, id: &str) -> Result<Option<Vec<ProfilePoint>>, TrailError> {
+        let Some(dem_url) = self.dem_url.as_deref() else {
+            return Err(TrailError::Config("no elevation source configured".to_string()));
+        };
+        if let Some(profile) = self.profile_cache.read().await.get(id) {
+            return Ok(Some(profile.clone()));
+        }
+        let Some(trail) = self.find_by_id(id).await else {
+            return Ok(None);
+        };
+        self.trail_profile(dem_url, &trail).await.map(Some)
+    }
+
+    /// `trail`'s profile from `profile_cache`, sampling the DEM and caching
+    /// the result on a miss.
+    async fn trail_profile(&self, dem_url: &str, trail: &Trail) -> Result<Vec<ProfilePoint>, TrailError> {
+        if let Some(profile) = self.profile_cache.read().await.get(&trail.id) {
+            return Ok(profile.clone());
+        }
+        let line: Vec<[f64; 2]> = trail.line.iter().flatten().copied().collect();
+        if line.len() < 2 {
+            return Ok(Vec::new());
+        }
+
+        // Distance walked, so the gap between sections adds nothing.
+        let mut cumulative_km = Vec::with_capacity(line.len());
+        let mut total_km = 0.0f64;
+        for segment in &trail.line {
+            for (index, b) in segment.iter().enumerate() {
+                if let Some(a) = index.checked_sub(1).map(|previous| segment[previous]) {
+                    total_km += self.distance_model.distance_km(a[0], a[1], b[0], b[1]);
+                }
+                cumulative_km.push(total_km);
+            }
+        }
+        let indexes = dem::sample_indexes(line.len(), 100);
+        let points: Vec<[f64; 2]> = indexes.iter().map(|index| line[*index]).collect();
+        let outbound = self.outbound_permit().await?;
+        let elevations = dem::fetch_elevations(&self.client, dem_url, &points).await?;
+        drop(outbound);
+        let profile: Vec<ProfilePoint> = indexes
+            .iter()
+            .zip(elevations)
+            .map(|(index, elevation_m)| ProfilePoint {
+                distance_km: cumulative_km[*index] as f32,
+                elevation_m,
+            })
+            .collect();
+        self.profile_cache.write().await.insert(trail.id.clone(), profile.clone());
+        Ok(profile)
+    }
+
+    /// Response-time adjustments applied to every trail handed out.
+    fn finish_trail(&self, trail: &mut Trail) {
+        round_coordinates(trail, self.coordinate_decimals);
+        trail.slug = trail_slug(&trail.provider, &trail.id);
+        let (year, month, day) = civil_today();
+        if let Some(policy) = seasonal_dog_policy(&trail.dog_seasons, month, day) {
+            if policy != trail.dog_policy {
+                trail.dog_notes = Some(dog_seasons_note(&trail.dog_seasons));
+                trail.dog_policy = policy;
+            }
+        }
+        trail.surfaces = merge_surfaces(&trail.surfaces);
+        trail.surface_label = describe_surfaces(trail);
+        trail.hot_paw_risk = hot_paw_risk(&trail.surface, month, &self.summer_months, &self.surface_classes);
+        trail.data_outdated = data_outdated(trail.data_checked.as_deref(), year, self.outdated_after_years);
+        if trail.region.is_none() {
+            trail.region = trail
+                .lat
+                .zip(trail.lon)
+                .and_then(|(lat, lon)| region_for(lat, lon))
+                .map(|region| region.to_string());
+        }
+        if let Some(note) = self.dog_notes.lookup(trail) {
+            trail.dog_notes = Some(note.to_string());
+        }
+        trail.summary = trail_summary(trail);
+        trail.provider_name = self.display_name(&trail.provider).to_string();
+        // "Allowed" on a tramping-grade track can still mean river crossings or exposure.
+        if self.hard_terrain_caution && trail.difficulty == Difficulty::Hard && trail.dog_policy == DogPolicy::Allowed {
+            trail.dog_notes = Some(match trail.dog_notes.take() {
+                Some(notes) => format!("{notes} {HARD_TERRAIN_CAUTION}"),
+                None => HARD_TERRAIN_CAUTION.to_string(),
+            });
+        }
+        trail.warnings = trail_warnings(trail);
+    }
+
+    /// Compare cached trails by id, listing any ids that aren't cached.
+    pub async fn compare(&self, ids: &[String]) -> TrailComparison {
+        let mut trails = Vec::new();
+        let mut missing = Vec::new();
+        for id in ids {
+            match self.find_by_id(id).await {
+                Some(trail) => trails.push(trail),
+                None => missing.push(id.clone()),
+            }
+        }
+        compare_trails(trails, missing)
+    }
+
+    pub async fn fetch_trails(&self, query: &TrailQuery) -> Result<Vec<Trail>, TrailError> {
+        Ok(self.fetch_trails_with_cache_info(query).await?.0)
+    }
+
+    /// `fetch_trails` plus the Overpass cache status and age; `None` when
+    /// Overpass isn't configured.
+    pub async fn fetch_trails_with_cache_info(
+        &self,
+        query: &TrailQuery,
+    ) -> Result<(Vec<Trail>, Option<CacheInfo>), TrailError> {
+        let bbox = Bbox::from_query(query)?.unwrap_or_default();
+        let mut combined = Vec::new();
+        let mut cache_info = None;
+        if !self.overpass_urls.is_empty() {
+            let (trails, info) = self
+                .fetch_overpass_cached(bbox)
+                .await
+                .inspect_err(|_| self.metrics.record_upstream_error(&Provider::OpenStreetMap))?;
+            self.metrics.record_cache(&Provider::OpenStreetMap, info.status != CacheStatus::Miss);
+            combined.extend(trails);
+            cache_info = Some(info);
+        }
+        if !self.doc_api_key.trim().is_empty() {
+            match self.fetch_doc_cached(bbox).await {
+                Ok(trails) => combined.extend(trails),
+                // With Overpass answering, a DOC outage only thins the results.
+                Err(err) if !self.overpass_urls.is_empty() => {
+                    tracing::warn!("DOC unavailable, searching without it: {}", err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let mut combined = dedupe_trails(combined, &self.provider_priority);
+        let profiles = self.profile_cache.read().await;
+        for trail in &mut combined {
+            if let Some(profile) = profiles.get(&trail.id) {
+                apply_elevation_change(trail, profile);
+            }
+            self.finish_trail(trail);
+        }
+        drop(profiles);
+
+        Ok((combined, cache_info))
+    }
+
+    async fn fetch_overpass_cached(&self, bbox: Bbox) -> Result<(Vec<Trail>, CacheInfo), TrailError> {
+        if let Some(cached) = self.overpass_cache.read().await.as_ref()
+            && cached.bbox == bbox
+        {
+            let age = cached.fetched_at.elapsed();
+            if age < OVERPASS_TTL {
+                return Ok((cached.trails.clone(), CacheInfo::new(CacheStatus::Hit, age)));
+            }
+            // Past TTL but within grace: answer from cache and refresh behind it.
+            if age < OVERPASS_TTL + self.overpass_stale_grace {
+                let refresh = {
+                    let mut refreshing = self.overpass_refreshing.write().await;
+                    let idle = !refreshing.contains(&bbox);
+                    if idle {
+                        refreshing.push(bbox);
+                    }
+                    idle
+                };
+                if refresh {
+                    let client = self.client.clone();
+                    let overpass_urls = self.ranked_overpass_urls().await;
+                    let options = self.overpass_options();
+                    let tiles = self.overpass_tiles(bbox);
+                    let tile_concurrency = self.tile_concurrency;
+                    let change_webhook = self.change_webhook.clone();
+                    let cache = self.overpass_cache.clone();
+                    let outbound = self.outbound.clone();
+                    let metrics = self.metrics.clone();
+                    let refreshing = self.overpass_refreshing.clone();
+                    tokio::spawn(async move {
+                        let fetched = fetch_overpass_tiles(
+                            client.clone(),
+                            overpass_urls,
+                            tiles,
+                            options,
+                            tile_concurrency,
+                            outbound,
+                        )
+                        .await;
+                        match fetched {
+                            Ok(trails) => {
+                                let mut cache = cache.write().await;
+                                if let Some(previous) = cache.as_ref()
+                                    && previous.bbox == bbox
+                                {
+                                    let changes = trail_changes(Provider::OpenStreetMap, &previous.trails, &trails);
+                                    announce_changes(&client, change_webhook.as_deref(), changes);
+                                }
+                                *cache = Some(OverpassCacheEntry {
+                                    fetched_at: Instant::now(),
+                                    bbox,
+                                    trails,
+                                });
+                            }
+                            Err(err) => {
+                                metrics.record_upstream_error(&Provider::OpenStreetMap);
+                                tracing::warn!("background overpass refresh failed: {}", err);
+                            }
+                        }
+                        refreshing.write().await.retain(|refreshed| *refreshed != bbox);
+                    });
+                }
+                return Ok((cached.trails.clone(), CacheInfo::new(CacheStatus::Stale, age)));
+            }
+        }
+
+        // Only allow one in-flight Overpass request at a time
+        let permit = match self.overpass_semaphore.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                // Another request is in-flight; serve stale cache for this box if available
+                if let Some(cached) = self.overpass_cache.read().await.as_ref()
+                    && cached.bbox == bbox
+                {
+                    tracing::debug!("overpass request in-flight, serving cached data");
+                    let info = CacheInfo::new(CacheStatus::Stale, cached.fetched_at.elapsed());
+                    return Ok((cached.trails.clone(), info));
+                }
+                // No cache at all; wait for the permit
+                self.overpass_semaphore.acquire().await
+                    .map_err(|_| TrailError::Upstream("semaphore closed".to_string()))?
+            }
+        };
+
+        // Re-check cache after acquiring permit (another request may have just finished)
+        if let Some(cached) = self.overpass_cache.read().await.as_ref() {
+            if cached.bbox == bbox && cached.fetched_at.elapsed() < OVERPASS_TTL {
+                drop(permit);
+                let info = CacheInfo::new(CacheStatus::Hit, cached.fetched_at.elapsed());
+                return Ok((cached.trails.clone(), info));
+            }
+        }
+
+        let overpass_urls = self.ranked_overpass_urls().await;
+        let trails = fetch_overpass_tiles(
+            self.client.clone(),
+            overpass_urls,
+            self.overpass_tiles(bbox),
+            self.overpass_options(),
+            self.tile_concurrency,
+            self.outbound.clone(),
+        )
+        .await?;
+        let mut cache = self.overpass_cache.write().await;
+        if let Some(previous) = cache.as_ref()
+            && previous.bbox == bbox
+        {
+            let changes = trail_changes(Provider::OpenStreetMap, &previous.trails, &trails);
+            announce_changes(&self.client, self.change_webhook.as_deref(), changes);
+        }
+        *cache = Some(OverpassCacheEntry {
+            fetched_at: Instant::now(),
+            bbox,
+            trails: trails.clone(),
+        });
+        drop(permit);
+        Ok((trails, CacheInfo::new(CacheStatus::Miss, Duration::ZERO)))
+    }
+
+    /// `bbox` as the tiles Overpass is queried with: itself unless tiling is
+    /// on, and never more than `MAX_OVERPASS_TILES`.
+    fn overpass_tiles(&self, bbox: Bbox) -> Vec<Bbox> {
+        let Some(mut span) = self.overpass_tile_deg else {
+            return vec![bbox];
+        };
+        while {
+            let (rows, cols) = bbox.grid(span);
+            rows.saturating_mul(cols) > MAX_OVERPASS_TILES
+        } {
+            span *= 2.0;
+        }
+        bbox.split(span)
+    }
+
+    /// Probe every Overpass mirror and fold the latency into its rolling
+    /// estimate. A failed probe marks the mirror unhealthy until it answers again.
+    pub async fn probe_overpass_mirrors(&self) {
+        for url in &self.overpass_urls {
+            let Ok(outbound) = self.outbound_permit().await else {
+                return;
+            };
+            let result = overpass::probe_latency(&self.client, url).await;
+            drop(outbound);
+            let mut health = self.overpass_health.write().await;
+            let next = match (result, health.get(url)) {
+                (Ok(latency), Some(MirrorHealth::Healthy(estimate))) => {
+                    MirrorHealth::Healthy(estimate * 0.7 + latency.as_secs_f64() * 1000.0 * 0.3)
+                }
+                (Ok(latency), _) => MirrorHealth::Healthy(latency.as_secs_f64() * 1000.0),
+                (Err(err), _) => {
+                    tracing::warn!("overpass probe failed for {}: {}", url, err);
+                    MirrorHealth::Failed
+                }
+            };
+            health.insert(url.clone(), next);
+        }
+    }
+
+    /// Mirrors in try order: probed-healthy fastest first, then unprobed in
+    /// configured order, then those whose last probe failed.
+    async fn ranked_overpass_urls(&self) -> Vec<String> {
+        let health = self.overpass_health.read().await;
+        let mut ranked: Vec<(u8, f64, &String)> = self
+            .overpass_urls
+            .iter()
+            .map(|url| match health.get(url) {
+                Some(MirrorHealth::Healthy(estimate)) => (0, *estimate, url),
+                None => (1, 0.0, url),
+                Some(MirrorHealth::Failed) => (2, 0.0, url),
+            })
+            .collect();
+        ranked.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        ranked.into_iter().map(|(_, _, url)| url.clone()).collect()
+    }
+
+    async fn fetch_doc_cached(&self, bbox: Bbox) -> Result<Vec<Trail>, TrailError> {
+        // 1. Ensure the summary list is cached.
+        self.ensure_doc_summaries().await?;
+
+        // 2. Filter by bbox.
+        let visible = {
+            let guard = self.doc_summary_cache.read().await;
+            let Some(cache) = guard.as_ref() else {
+                return Ok(Vec::new());
+            };
+            doc::filter_doc_by_bbox(&cache.trails, bbox)
+        };
+
+        // 3. Enrich visible trails with details, fetching only in eager mode.
+        if self.lazy_doc_details {
+            let details = self.doc_detail_cache.read().await;
+            let mut visible = visible;
+            for trail in &mut visible {
+                if let Some(detail) = details.get(&trail.id) {
+                    doc::enrich_with_detail(trail, detail);
+                }
+            }
+            return Ok(visible);
+        }
+        self.enrich_visible(&self.doc_api_key, visible).await
+    }
+
+    /// One trail straight from its provider by OSM way id (optionally
+    /// `osm-` prefixed) or DOC asset id, without an area search. `Ok(None)`
+    /// when the provider has no such trail.
+    pub async fn lookup(&self, provider: &Provider, reference: &str) -> Result<Option<Trail>, TrailError> {
+        let trail = match provider {
+            Provider::OpenStreetMap => {
+                if self.overpass_urls.is_empty() {
+                    return Err(TrailError::Config("OVERPASS_URL is not set".to_string()));
+                }
+                let Ok(way_id) = reference.trim_start_matches("osm-").parse::<u64>() else {
+                    return Err(TrailError::InvalidQuery(format!("ref must be a numeric OSM way id, not {reference}")));
+                };
+                let overpass_urls = self.ranked_overpass_urls().await;
+                let outbound = self.outbound_permit().await?;
+                let trail = overpass::fetch_overpass_way(&self.client, &overpass_urls, way_id, self.overpass_options())
+                    .await
+                    .inspect_err(|_| self.metrics.record_upstream_error(&Provider::OpenStreetMap))?;
+                drop(outbound);
+                trail
+            }
+            Provider::DOC => {
+                if self.doc_api_key.trim().is_empty() {
+                    return Err(TrailError::Config("DOC_API_KEY is not set".to_string()));
+                }
+                let outbound = self.outbound_permit().await?;
+                let detail = doc::fetch_doc_detail_if_found(
+                    &self.client,
+                    &self.doc_base_url,
+                    &self.doc_api_key,
+                    reference,
+                    self.request_timeout,
+                )
+                .await
+                .inspect_err(|_| self.metrics.record_upstream_error(&Provider::DOC))?;
+                drop(outbound);
+                detail.and_then(|detail| {
+                    let mut trail = doc::map_doc_detail(reference, &detail, self.difficulty_thresholds)?;
+                    trail.raw = self.raw_debug.then(|| doc::trimmed_raw(&detail));
+                    Some(trail)
+                })
+            }
+        };
+        Ok(trail.map(|mut trail| {
+            self.finish_trail(&mut trail);
+            trail
+        }))
+    }
+
+    /// Every cached DOC track, unfiltered and without detail enrichment.
+    /// Fetches the list first if the cache is cold or expired.
+    pub async fn doc_tracks_all(&self) -> Result<Vec<Trail>, TrailError> {
+        if self.doc_api_key.trim().is_empty() {
+            return Err(TrailError::Config("DOC_API_KEY is not set".to_string()));
+        }
+        self.ensure_doc_summaries().await?;
+        Ok(self
+            .doc_summary_cache
+            .read()
+            .await
+            .as_ref()
+            .map(|cache| cache.trails.clone())
+            .unwrap_or_default())
+    }
+
+    /// Refresh the DOC summary cache if it's missing or past its TTL. While
+    /// another refresh is in flight, stale data is left in place and used.
+    async fn ensure_doc_summaries(&self) -> Result<(), TrailError> {
+        let ttl = self.doc_summary_ttl;
+
+        let needs_fetch = match self.doc_summary_cache.read().await.as_ref() {
+            Some(cached) => cached.fetched_at.elapsed() >= ttl,
+            None => true,
+        };
+        self.metrics.record_cache(&Provider::DOC, !needs_fetch);
+        if !needs_fetch {
+            return Ok(());
+        }
+
+        // Only allow one in-flight DOC summary request at a time
+        let permit = match self.doc_semaphore.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                if self.doc_summary_cache.read().await.is_some() {
+                    tracing::debug!("DOC summary request in-flight, serving cached data");
+                    return Ok(());
+                }
+                self.doc_semaphore.acquire().await
+                    .map_err(|_| TrailError::Upstream("semaphore closed".to_string()))?
+            }
+        };
+
+        // Re-check after acquiring permit
+        let still_needs = match self.doc_summary_cache.read().await.as_ref() {
+            Some(cached) => cached.fetched_at.elapsed() >= ttl,
+            None => true,
+        };
+        if still_needs {
+            self.store_doc_summaries().await?;
+        }
+        drop(permit);
+        Ok(())
+    }
+
+    /// Fetch the DOC track list and cache it, returning whether it was cached.
+    /// An unexpected payload shape isn't cached so the next request retries.
+    async fn store_doc_summaries(&self) -> Result<bool, TrailError> {
+        let outbound = self.outbound_permit().await?;
+        let fetched = doc::fetch_doc_summaries(
+            &self.client,
+            &self.doc_base_url,
+            &self.doc_api_key,
+            self.request_timeout,
+            self.difficulty_thresholds,
+            self.doc_list_retry,
+            self.raw_debug,
+        )
+        .await
+        .inspect_err(|_| self.metrics.record_upstream_error(&Provider::DOC));
+        drop(outbound);
+        let Some(mut trails) = fetched? else {
+            return Ok(false);
+        };
+        for trail in &mut trails {
+            trail.line = if self.slim_doc_cache {
+                Vec::new()
+            } else {
+                simplify_sections(&trail.line, self.max_line_points)
+            };
+        }
+        let mut cache = self.doc_summary_cache.write().await;
+        if let Some(previous) = cache.as_ref() {
+            let changes = trail_changes(Provider::DOC, &previous.trails, &trails);
+            announce_changes(&self.client, self.change_webhook.as_deref(), changes);
+        }
+        *cache = Some(DocSummaryCache {
+            fetched_at: Instant::now(),
+            trails,
+        });
+        Ok(true)
+    }
+
+    /// Keep the DOC track list warm by refetching it `lead` before it expires,
+    /// so searches never wait on the full list. `lead` is capped at half the
+    /// TTL, so successful refreshes are at least that far apart. Failures retry
+    /// with exponential backoff. Runs until the task is dropped.
+    pub async fn run_doc_refresher(&self, lead: Duration) {
+        let lead = lead.min(self.doc_summary_ttl / 2);
+        let mut backoff = backoff::Backoff::new(DOC_REFRESH_MIN_BACKOFF, self.backoff_seed);
+        let mut failures = 0u32;
+        loop {
+            let age = self
+                .doc_summary_cache
+                .read()
+                .await
+                .as_ref()
+                .map(|cached| cached.fetched_at.elapsed());
+            let wait = age
+                .map(|age| self.doc_summary_ttl.saturating_sub(lead).saturating_sub(age))
+                .unwrap_or_default();
+            tokio::time::sleep(wait).await;
+
+            let refreshed = match self.doc_semaphore.acquire().await {
+                Ok(_permit) => self.store_doc_summaries().await,
+                Err(_) => return,
+            };
+            match refreshed {
+                Ok(true) => {
+                    failures = 0;
+                    continue;
+                }
+                Ok(false) => tracing::warn!("background DOC refresh got an unexpected payload"),
+                Err(err) => tracing::warn!("background DOC refresh failed: {}", err),
+            }
+            failures = failures.saturating_add(1);
+            tokio::time::sleep(backoff.delay(failures).min(DOC_REFRESH_MAX_BACKOFF)).await;
+        }
+    }
+
+    /// Fetch and cache detail for each visible trail, enriching it in place.
+    /// Tracks whose detail keeps failing are skipped until their cooldown ends.
+    async fn enrich_visible(&self, api_key: &str, mut trails: Vec<Trail>) -> Result<Vec<Trail>, TrailError> {
+        for trail in &mut trails {
+            // Check detail cache
+            if let Some(detail) = self.doc_detail_cache.read().await.get(&trail.id) {
+                doc::enrich_with_detail(trail, detail);
+                continue;
+            }
+            if self.doc_detail_cooling_down(&trail.id).await {
+                continue;
+            }
+
+            // Fetch detail and cache it
+            let outbound = self.outbound_permit().await?;
+            let fetched = doc::fetch_doc_detail(&self.client, &self.doc_base_url, api_key, &trail.id, self.request_timeout).await;
+            drop(outbound);
+            match fetched {
+                Ok(mut detail) => {
+                    doc::simplify_detail_line(&mut detail, self.max_line_points);
+                    doc::enrich_with_detail(trail, &detail);
+                    self.doc_detail_failures.write().await.remove(&trail.id);
+                    self.doc_detail_cache.write().await.insert(trail.id.clone(), detail);
+                }
+                Err(err) => {
+                    self.metrics.record_upstream_error(&Provider::DOC);
+                    tracing::warn!("DOC detail fetch failed for {}: {}", trail.id, err);
+                    self.record_doc_detail_failure(&trail.id).await;
+                }
+            }
+        }
+        Ok(trails)
+    }
+}
+
+/// Ids in `new` but not `old`, and in `old` but not `new`, each sorted.
+pub fn trail_changes(provider: Provider, old: &[Trail], new: &[Trail]) -> TrailChanges {
+    let old_ids: std::collections::HashSet<&str> = old.iter().map(|trail| trail.id.as_str()).collect();
+    let new_ids: std::collections::HashSet<&str> = new.iter().map(|trail| trail.id.as_str()).collect();
+    let mut added: Vec<String> = new_ids.difference(&old_ids).map(|id| id.to_string()).collect();
+    let mut removed: Vec<String> = old_ids.difference(&new_ids).map(|id| id.to_string()).collect();
+    added.sort();
+    removed.sort();
+    TrailChanges { provider, added, removed }
+}
+
+/// POST `changes` to the webhook in the background when there are any.
+/// Best effort: a failed delivery is logged and not retried.
+fn announce_changes(client: &reqwest::Client, webhook: Option<&str>, changes: TrailChanges) {
+    let Some(webhook) = webhook else { return };
+    if changes.added.is_empty() && changes.removed.is_empty() {
+        return;
+    }
+    let request = client.post(webhook).json(&changes).timeout(Duration::from_secs(10));
+    tokio::spawn(async move {
+        match request.send().await.and_then(|response| response.error_for_status()) {
+            Ok(_) => tracing::info!(
+                "announced {} trail changes: {} added, {} removed",
+                changes.provider,
+                changes.added.len(),
+                changes.removed.len()
+            ),
+            Err(err) => tracing::warn!("trails-changed webhook failed: {}", err),
+        }
+    });
+}
+
+/// Fetch each tile from Overpass, at most `tile_concurrency` at a time and
+/// each holding an outbound slot. A tile's task is only spawned once it has
+/// a slot. Ways crossing a tile edge come back from both tiles; the first
+/// copy, in tile order, is kept.
+async fn fetch_overpass_tiles(
+    client: reqwest::Client,
+    overpass_urls: Vec<String>,
+    tiles: Vec<Bbox>,
+    options: overpass::OverpassOptions,
+    tile_concurrency: usize,
+    outbound: Arc<tokio::sync::Semaphore>,
+) -> Result<Vec<Trail>, TrailError> {
+    let overpass_urls = Arc::new(overpass_urls);
+    let slots = Arc::new(tokio::sync::Semaphore::new(tile_concurrency.max(1)));
+    let closed = |_| TrailError::Upstream("semaphore closed".to_string());
+    let mut tasks = tokio::task::JoinSet::new();
+    for (index, tile) in tiles.into_iter().enumerate() {
+        let slot = slots.clone().acquire_owned().await.map_err(closed)?;
+        let client = client.clone();
+        let overpass_urls = overpass_urls.clone();
+        let outbound = outbound.clone();
+        tasks.spawn(async move {
+            let _slot = slot;
+            let _outbound = outbound.acquire_owned().await.map_err(closed)?;
+            overpass::fetch_overpass_with_fallback(&client, &overpass_urls, tile, options)
+                .await
+                .map(|trails| (index, trails))
+        });
+    }
+
+    let mut fetched = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        let result = joined.map_err(|err| TrailError::Upstream(format!("overpass tile task failed: {err}")))?;
+        fetched.push(result?);
+    }
+    fetched.sort_by_key(|(index, _)| *index);
+    let mut seen = std::collections::HashSet::new();
+    Ok(fetched
+        .into_iter()
+        .flat_map(|(_, trails)| trails)
+        .filter(|trail| seen.insert(trail.id.clone()))
+        .collect())
+}
+
+/// Round `lat`/`lon` and every line point to `decimals` places.
+fn round_coordinates(trail: &mut Trail, decimals: u32) {
+    let scale = 10f64.powi(decimals.min(15) as i32);
+    let round = |value: f64| (value * scale).round() / scale;
+    trail.lat = trail.lat.map(round);
+    trail.lon = trail.lon.map(round);
+    for point in trail.line.iter_mut().flatten() {
+        *point = [round(point[0]), round(point[1])];
+    }
+}
+
+/// Net change from a DEM profile, plus gain when the provider gave none.
+fn apply_elevation_change(trail: &mut Trail, profile: &[ProfilePoint]) {
+    let Some((gain, net)) = dem::elevation_change(profile) else {
+        return;
+    };
+    trail.net_elevation_m = Some(net);
+    if trail.elevation_m.is_none() {
+        trail.elevation_m = Some(gain);
+    }
+}
+
+/// Short, stable slug for a trail: 40 bits of an FNV-1a hash of provider and
+/// id, in base 36. Unlike `DefaultHasher`, stays the same across builds.
+pub fn trail_slug(provider: &Provider, id: &str) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in format!("{provider}:{id}").bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    let mut value = hash >> 24;
+    let mut slug = Vec::with_capacity(8);
+    for _ in 0..8 {
+        slug.push(b"0123456789abcdefghijklmnopqrstuvwxyz"[(value % 36) as usize]);
+        value /= 36;
+    }
+    slug.reverse();
+    String::from_utf8(slug).unwrap_or_default()
+}
+
+/// Sealed surfaces that soak up sun and get hot enough to burn paws.
+const DEFAULT_HARD_SURFACES: &[&str] = &["asphalt", "concrete", "paved", "tarmac", "sett", "paving_stones"];
+
+/// Unsealed surfaces easy on small legs and paws: natural ground and gravel.
+const DEFAULT_NATURAL_SURFACES: &[&str] = &[
+    "ground", "dirt", "earth", "grass", "natural", "compacted", "gravel", "fine_gravel", "pebblestone",
+];
+
+/// Which surface tags count as soft and natural and which as hard and
+/// sealed, shared by every surface-based feature.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SurfaceClasses {
+    /// Matched exactly, ignoring case.
+    pub natural: Vec<String>,
+    /// Matched as the whole tag or one word of it, ignoring case, so "paved"
+    /// also covers "paved_road" but not "unpaved".
+    pub hard: Vec<String>,
+}
+
+impl Default for SurfaceClasses {
+    fn default() -> Self {
+        Self {
+            natural: DEFAULT_NATURAL_SURFACES.iter().map(|surface| surface.to_string()).collect(),
+            hard: DEFAULT_HARD_SURFACES.iter().map(|surface| surface.to_string()).collect(),
+        }
+    }
+}
+
+impl SurfaceClasses {
+    pub fn is_natural(&self, surface: &str) -> bool {
+        surface_in(surface, &self.natural)
+    }
+
+    pub fn is_hard(&self, surface: &str) -> bool {
+        surface_in(surface, &self.hard)
+    }
+}
+
+/// Whether `surface` is one of `class`, as the whole tag or one word of it:
+/// `paved` covers `paved_road` but not `unpaved`.
+fn surface_in(surface: &str, class: &[String]) -> bool {
+    let surface = surface.trim().to_lowercase();
+    !surface.is_empty()
+        && class.iter().any(|name| {
+            surface == *name || surface.split(|c: char| !c.is_alphanumeric()).any(|word| word == name)
+        })
+}
+
+/// Whether `surface` is a hot-paw risk in `month`; `None` if the surface is unknown.
+pub fn hot_paw_risk(surface: &str, month: u32, summer_months: &[u32], surfaces: &SurfaceClasses) -> Option<bool> {
+    let trimmed = surface.trim();
+    if trimmed.is_empty() || trimmed.eq_ignore_ascii_case("unknown") {
+        return None;
+    }
+    Some(surfaces.is_hard(trimmed) && summer_months.contains(&month))
+}
+
+/// The dog policy of the season covering `month`/`day`, if any does.
+pub fn seasonal_dog_policy(seasons: &[DogSeason], month: u32, day: u32) -> Option<DogPolicy> {
+    // Seasons are parsed onto a 365-day year, where February ends on the 28th.
+    let today = if (month, day) == (2, 29) { (2, 28) } else { (month, day) };
+    seasons
+        .iter()
+        .find(|season| {
+            if season.start <= season.end {
+                season.start <= today && today <= season.end
+            } else {
+                today >= season.start || today <= season.end
+            }
+        })
+        .map(|season| season.policy.clone())
+}
+
+const MONTH_ABBREVIATIONS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Every season's rule as one note, such as "Dogs on a lead 1 Dec–28 Feb.
+/// No dogs 1 Mar–30 Nov.", for when today's season overrides the base policy
+/// and its notes.
+fn dog_seasons_note(seasons: &[DogSeason]) -> String {
+    let date = |(month, day): (u32, u32)| {
+        format!("{day} {}", MONTH_ABBREVIATIONS[(month.clamp(1, 12) - 1) as usize])
+    };
+    seasons
+        .iter()
+        .map(|season| {
+            let rule = match season.policy {
+                DogPolicy::Allowed => "Dogs allowed",
+                DogPolicy::Partial => "Dogs on a lead",
+                DogPolicy::NotAllowed => "No dogs",
+                DogPolicy::HuntingPermit => "Dogs with a hunting permit only",
+                DogPolicy::Unknown => "Dog rules unknown",
+            };
+            format!("{rule} {}–{}.", date(season.start), date(season.end))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether a `data_checked` date is more than `after_years` before `current_year`.
+/// Unknown or unparseable dates are never flagged.
+pub fn data_outdated(checked: Option<&str>, current_year: i64, after_years: i64) -> bool {
+    checked
+        .and_then(|checked| checked.get(..4))
+        .and_then(|year| year.parse::<i64>().ok())
+        .is_some_and(|year| current_year - year > after_years)
+}
+
+/// The cautions a card should show for `trail`, in display order.
+pub fn trail_warnings(trail: &Trail) -> Vec<TrailWarning> {
+    let mut warnings = Vec::new();
+    if trail.water_crossing {
+        warnings.push(TrailWarning {
+            kind: "water-crossing".to_string(),
+            message: WATER_CROSSING_WARNING.to_string(),
+        });
+    }
+    if trail.difficulty_conflict {
+        warnings.push(TrailWarning {
+            kind: "difficulty-conflict".to_string(),
+            message: DIFFICULTY_CONFLICT_WARNING.to_string(),
+        });
+    }
+    if trail.hot_paw_risk == Some(true) {
+        warnings.push(TrailWarning {
+            kind: "hot-paw".to_string(),
+            message: HOT_PAW_WARNING.to_string(),
+        });
+    }
+    warnings
+}
+
+/// Today's New Zealand date as `YYYY-MM-DD`.
+pub(crate) fn today_iso() -> String {
+    let (year, month, day) = civil_today();
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Current (year, month, day) in New Zealand. Seasons and dated dog rules
+/// are NZ dates, and the UTC date lags behind for half of each NZ day.
+fn civil_today() -> (i64, u32, u32) {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0);
+    nz_civil_date(now)
+}
+
+/// New Zealand (year, month, day) at `unix_secs`: UTC+12, or UTC+13 from the
+/// last Sunday of September to the first Sunday of April. Both changes
+/// happen at 02:00 standard time.
+fn nz_civil_date(unix_secs: i64) -> (i64, u32, u32) {
+    let standard = unix_secs + 12 * 3600;
+    let (year, _, _) = civil_from_days(standard.div_euclid(86_400));
+    let weekday = |days: i64| (days + 4).rem_euclid(7);
+    let april_first = days_from_civil(year, 4, 1);
+    let daylight_ends = (april_first + (7 - weekday(april_first)) % 7) * 86_400 + 2 * 3600;
+    let september_last = days_from_civil(year, 9, 30);
+    let daylight_starts = (september_last - weekday(september_last)) * 86_400 + 2 * 3600;
+    let daylight = standard < daylight_ends || standard >= daylight_starts;
+    let local = if daylight { standard + 3600 } else { standard };
+    civil_from_days(local.div_euclid(86_400))
+}
+
+/// Days since 1970-01-01 for a civil date, from Howard Hinnant's date
+/// algorithms.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = i64::from((month + 9) % 12);
+    let day_of_year = (153 * month_index + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Civil (year, month, day) for days since 1970-01-01, the inverse of
+/// `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let day_of_era = (days + 719_468).rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = (if month_index < 10 { month_index + 3 } else { month_index - 9 }) as u32;
+    let era = (days + 719_468).div_euclid(146_097);
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+pub fn filter_trails(trails: &[Trail], query: &TrailQuery) -> Vec<Trail> {
+    filter_trails_by_priority(trails, query, DEFAULT_PROVIDER_PRIORITY)
+}
+
+/// `filter_trails` with equal scores ordered by `priority`, then by id.
+pub fn filter_trails_by_priority(trails: &[Trail], query: &TrailQuery, priority: &[Provider]) -> Vec<Trail> {
+    filter_trails_weighted(trails, query, priority, &ScoreWeights::default(), &SurfaceClasses::default())
+}
+
+/// `filter_trails_by_priority` with custom scoring weights and surface classes.
+pub fn filter_trails_weighted(
+    trails: &[Trail],
+    query: &TrailQuery,
+    priority: &[Provider],
+    weights: &ScoreWeights,
+    surfaces: &SurfaceClasses,
+) -> Vec<Trail> {
+    rank_trails(trails, query, priority, weights, surfaces)
+}
+
+/// `filter_trails_weighted` over any run of trail references, so callers can
+/// narrow the trails first without cloning them.
+fn rank_trails<'a>(
+    trails: impl IntoIterator<Item = &'a Trail>,
+    query: &TrailQuery,
+    priority: &[Provider],
+    weights: &ScoreWeights,
+    surfaces: &SurfaceClasses,
+) -> Vec<Trail> {
+    let dog_filter = query.dog.clone().unwrap_or(DogFilter::AllowedOrPartial);
+    let range = derive_distance_range(query);
+    let effort = query.effort.clone();
+    // The map's centre stands in for where the user starts.
+    let start = Bbox::from_query(query).ok().flatten().map(|bbox| bbox.center());
+
+    // Filter, score and sort on references, then copy out what's kept.
+    let kept: Vec<&Trail> = trails
+        .into_iter()
+        .filter(|trail| dog_policy_allows(trail, &dog_filter))
+        .filter(|trail| match query.difficulty {
+            Some(ref difficulty) => &trail.difficulty == difficulty,
+            None => true,
+        })
+        .filter(|trail| within_distance(trail.distance_km, &range))
+        .filter(|trail| !query.with_coordinates.unwrap_or(false) || has_coordinates(trail))
+        .filter(|trail| !query.family_friendly.unwrap_or(false) || is_family_friendly(trail, surfaces))
+        .filter(|trail| !query.featured_only.unwrap_or(false) || trail.featured == Some(true))
+        .filter(|trail| !query.trusted_only.unwrap_or(false) || trail.provider.is_official())
+        .filter(|trail| within_center_cutoff(trail, start, query.max_distance_from_center_km))
+        .collect();
+    let unknown_distance = weights.neutral_unknown_distance.then(|| average_distance_penalty(&kept, &range));
+    let mut matches: Vec<(&Trail, f32)> = kept
+        .into_iter()
+        .map(|trail| (trail, score_trail(trail, &range, effort.as_ref(), start, weights, unknown_distance)))
+        .collect();
+
+    // Break score ties by featured tracks first, then provider priority, then
+    // id, so equal-scoring trails come out in a stable order.
+    let rank = |trail: &Trail| {
+        priority
+            .iter()
+            .position(|provider| *provider == trail.provider)
+            .unwrap_or(priority.len())
+    };
+    matches.sort_by(|a, b| {
+        a.1.partial_cmp(&b.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| (b.0.featured == Some(true)).cmp(&(a.0.featured == Some(true))))
+            .then_with(|| rank(a.0).cmp(&rank(b.0)))
+            .then_with(|| a.0.id.cmp(&b.0.id))
+    });
+    // Min-max normalise penalties so the best match in the set scores 100.
+    let best = matches.first().map(|(_, score)| *score).unwrap_or(0.0);
+    let worst = matches.last().map(|(_, score)| *score).unwrap_or(0.0);
+    matches
+        .into_iter()
+        .map(|(trail, score)| {
+            let fraction = if worst > best { (worst - score) / (worst - best) } else { 1.0 };
+            Trail {
+                match_score: Some((fraction * 100.0).round().clamp(0.0, 100.0) as u8),
+                ..trail.clone()
+            }
+        })
+        .collect()
+}
+
+/// `text` with macrons dropped: "Ōtari" becomes "Otari".
+pub fn strip_macrons(text: &str) -> String {
+    text.chars()
+        .filter(|c| *c != '\u{0304}')
+        .map(|c| match c {
+            'ā' => 'a',
+            'ē' => 'e',
+            'ī' => 'i',
+            'ō' => 'o',
+            'ū' => 'u',
+            'Ā' => 'A',
+            'Ē' => 'E',
+            'Ī' => 'I',
+            'Ō' => 'O',
+            'Ū' => 'U',
+            other => other,
+        })
+        .collect()
+}
+
+/// With `ascii=true`, names and places in `trails` without macrons. Works on
+/// the response copies, so cached trails keep theirs.
+pub fn apply_ascii(mut trails: Vec<Trail>, query: &TrailQuery) -> Vec<Trail> {
+    if !query.ascii.unwrap_or(false) {
+        return trails;
+    }
+    for trail in &mut trails {
+        trail.name = strip_macrons(&trail.name);
+        trail.location = strip_macrons(&trail.location);
+        trail.summary = strip_macrons(&trail.summary);
+        for place in [&mut trail.region, &mut trail.district, &mut trail.place].into_iter().flatten() {
+            *place = strip_macrons(place);
+        }
+    }
+    trails
+}
+
+/// Default gap `diversify_trails` keeps between trails.
+pub const DEFAULT_DIVERSIFY_SPACING_KM: f64 = 0.5;
+
+/// Ranked `trails`, skipping any that lie within `spacing_km` of a better-ranked
+/// trail already kept, so one busy hillside doesn't fill the page. Trails
+/// without coordinates are always kept.
+pub fn diversify_trails(trails: Vec<Trail>, spacing_km: f64) -> Vec<Trail> {
+    let mut picked: Vec<(f64, f64)> = Vec::new();
+    trails
+        .into_iter()
+        .filter(|trail| {
+            let Some((lat, lon)) = trail.lat.zip(trail.lon) else {
+                return true;
+            };
+            if picked
+                .iter()
+                .any(|&(other_lat, other_lon)| distance::haversine_km(lat, lon, other_lat, other_lon) < spacing_km)
+            {
+                return false;
+            }
+            picked.push((lat, lon));
+            true
+        })
+        .collect()
+}
+
+/// The `offset`/`limit` window of already-filtered results.
+pub fn paginate(trails: Vec<Trail>, query: &TrailQuery) -> Vec<Trail> {
+    trails
+        .into_iter()
+        .skip(query.offset.unwrap_or(0))
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect()
+}
+
+/// Like `filter_trails`, but when fewer than `min_results` trails match, first
+/// loosens `AllowedOnly` to `AllowedOrPartial` and then doubles the distance
+/// range. The flag is `true` when a relaxed pass produced the results.
+pub fn filter_trails_relaxed(
+    trails: &[Trail],
+    query: &TrailQuery,
+    min_results: usize,
+) -> (Vec<Trail>, bool) {
+    relax_filter(trails, query, min_results, filter_trails)
+}
+
+/// `filter_trails_relaxed` with custom provider priority, scoring weights and
+/// surface classes.
+pub fn filter_trails_relaxed_weighted(
+    trails: &[Trail],
+    query: &TrailQuery,
+    min_results: usize,
+    priority: &[Provider],
+    weights: &ScoreWeights,
+    surfaces: &SurfaceClasses,
+) -> (Vec<Trail>, bool) {
+    relax_filter(trails, query, min_results, |trails, query| {
+        filter_trails_weighted(trails, query, priority, weights, surfaces)
+    })
+}
+
+/// The relax steps of `filter_trails_relaxed` around any `filter`.
+fn relax_filter(
+    trails: &[Trail],
+    query: &TrailQuery,
+    min_results: usize,
+    filter: impl Fn(&[Trail], &TrailQuery) -> Vec<Trail>,
+) -> (Vec<Trail>, bool) {
+    let strict = filter(trails, query);
+    if strict.len() >= min_results {
+        return (strict, false);
+    }
+
+    let mut relaxed = query.clone();
+    if matches!(relaxed.dog, Some(DogFilter::AllowedOnly)) {
+        relaxed.dog = Some(DogFilter::AllowedOrPartial);
+        let matches = filter(trails, &relaxed);
+        if matches.len() >= min_results {
+            return (matches, true);
+        }
+    }
+
+    let (min_km, max_km, _) = derive_distance_range(&relaxed);
+    relaxed.min_km = Some(min_km.map(|min| min / 2.0).unwrap_or(0.0));
+    relaxed.max_km = max_km.map(|max| max * 2.0);
+    let matches = filter(trails, &relaxed);
+    if matches.len() > strict.len() {
+        (matches, true)
+    } else {
+        (strict, false)
+    }
+}
+
+/// Combine per-segment `(surface, km)` pairs into distance fractions, largest first.
+pub fn merge_surfaces(segments: &[(String, f32)]) -> Vec<(String, f32)> {
+    let total: f32 = segments.iter().map(|(_, km)| km.max(0.0)).sum();
+    let mut merged: Vec<(String, f32)> = Vec::new();
+    for (surface, km) in segments {
+        match merged.iter_mut().find(|(name, _)| name == surface) {
+            Some((_, sum)) => *sum += km.max(0.0),
+            None => merged.push((surface.clone(), km.max(0.0))),
+        }
+    }
+    if total > 0.0 {
+        for (_, share) in &mut merged {
+            *share /= total;
+        }
+    }
+    merged.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    merged
+}
+
+/// Merge two records of the same trail from different providers. For each
+/// field the more informative value wins — a known surface over "Unknown",
+/// `Some` over `None` — and where both are known the provider earlier in
+/// `priority` wins. Id, name and map link come from the preferred record.
+/// Difficulties at opposite ends set `difficulty_conflict`.
+pub fn merge_duplicate(a: &Trail, b: &Trail, priority: &[Provider]) -> Trail {
+    let rank = |trail: &Trail| priority.iter().position(|provider| *provider == trail.provider).unwrap_or(priority.len());
+    let (first, second) = if rank(b) < rank(a) { (b, a) } else { (a, b) };
+    let mut merged = first.clone();
+    if merged.surface == "Unknown" && second.surface != "Unknown" {
+        merged.surface = second.surface.clone();
+        merged.surfaces = second.surfaces.clone();
+        merged.surface_label = second.surface_label.clone();
+        merged.hot_paw_risk = second.hot_paw_risk;
+    }
+    if merged.dog_policy == DogPolicy::Unknown && second.dog_policy != DogPolicy::Unknown {
+        merged.dog_policy = second.dog_policy.clone();
+        merged.dog_notes = second.dog_notes.clone();
+        merged.dog_seasons = second.dog_seasons.clone();
+    }
+    if merged.distance_km == 0.0 && second.distance_km > 0.0 {
+        merged.distance_km = second.distance_km;
+        merged.distance_source = second.distance_source;
+    }
+    if !merged.has_location && second.has_location {
+        (merged.lat, merged.lon, merged.has_location) = (second.lat, second.lon, true);
+    }
+    if merged.line.is_empty() {
+        merged.line = second.line.clone();
+        merged.line_bbox = second.line_bbox;
+    }
+    merged.elevation_m = merged.elevation_m.or(second.elevation_m);
+    merged.elevation_point_m = merged.elevation_point_m.or(second.elevation_point_m);
+    merged.net_elevation_m = merged.net_elevation_m.or(second.net_elevation_m);
+    merged.region = merged.region.or_else(|| second.region.clone());
+    merged.district = merged.district.or_else(|| second.district.clone());
+    merged.place = merged.place.or_else(|| second.place.clone());
+    merged.data_checked = merged.data_checked.or_else(|| second.data_checked.clone());
+    merged.featured = merged.featured.or(second.featured);
+    merged.popularity = merged.popularity.or(second.popularity);
+    merged.water_crossing |= second.water_crossing;
+    merged.thumbnail_url = merged.thumbnail_url.or_else(|| second.thumbnail_url.clone());
+    if merged.images.is_empty() {
+        merged.images = second.images.clone();
+    }
+    merged.difficulty_conflict = first.difficulty_conflict
+        || second.difficulty_conflict
+        || (first.difficulty.tier() - second.difficulty.tier()).abs() > 1;
+    merged
+}
+
+/// How far apart two providers' records of one trail may be placed.
+const DUPLICATE_MAX_KM: f64 = 1.0;
+
+/// Words providers add to or leave off the same trail's name.
+const DUPLICATE_NAME_FILLER: &[&str] = &["track", "walk", "walkway", "trail", "the"];
+
+/// `name` reduced for matching across providers: no macrons, case,
+/// punctuation or filler words, so "Ōtari Loop Track" matches "Otari loop".
+fn duplicate_key(name: &str) -> String {
+    strip_macrons(name)
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !DUPLICATE_NAME_FILLER.contains(word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `trails` with each trail that two providers both list merged into one
+/// record by `merge_duplicate`. Records match on their name and lie within
+/// `DUPLICATE_MAX_KM` of each other; generated names never match.
+pub fn dedupe_trails(trails: Vec<Trail>, priority: &[Provider]) -> Vec<Trail> {
+    let mut kept: Vec<(String, Trail)> = Vec::with_capacity(trails.len());
+    for trail in trails {
+        let key = if trail.name_generated { String::new() } else { duplicate_key(&trail.name) };
+        let duplicate = kept.iter_mut().find(|(other_key, other)| {
+            !key.is_empty()
+                && *other_key == key
+                && other.provider != trail.provider
+                && match (other.lat.zip(other.lon), trail.lat.zip(trail.lon)) {
+                    (Some((lat, lon)), Some((other_lat, other_lon))) => {
+                        distance::haversine_km(lat, lon, other_lat, other_lon) <= DUPLICATE_MAX_KM
+                    }
+                    _ => false,
+                }
+        });
+        match duplicate {
+            Some((_, other)) => *other = merge_duplicate(other, &trail, priority),
+            None => kept.push((key, trail)),
+        }
+    }
+    kept.into_iter().map(|(_, trail)| trail).collect()
+}
+
+/// Render a trail's surface fractions as e.g. "70% gravel, 30% grass", or
+/// just its `surface` when there's no breakdown.
+pub fn describe_surfaces(trail: &Trail) -> String {
+    if trail.surfaces.len() < 2 {
+        return trail.surface.clone();
+    }
+    trail
+        .surfaces
+        .iter()
+        .map(|(surface, share)| format!("{:.0}% {}", share * 100.0, surface))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn compare_trails(trails: Vec<Trail>, missing: Vec<String>) -> TrailComparison {
+    let distances: Vec<f32> = trails
+        .iter()
+        .map(|trail| trail.distance_km)
+        .filter(|km| *km > 0.0)
+        .collect();
+    let elevations: Vec<f32> = trails.iter().filter_map(|trail| trail.elevation_m).collect();
+    let spread = |values: &[f32]| {
+        let max = values.iter().cloned().fold(f32::MIN, f32::max);
+        let min = values.iter().cloned().fold(f32::MAX, f32::min);
+        max - min
+    };
+    let diff = ComparisonDiff {
+        distance_km_spread: if distances.is_empty() { 0.0 } else { spread(&distances) },
+        elevation_m_spread: if elevations.is_empty() { None } else { Some(spread(&elevations)) },
+        same_difficulty: trails
+            .windows(2)
+            .all(|pair| pair[0].difficulty == pair[1].difficulty),
+    };
+    TrailComparison { trails, missing, diff }
+}
+
+/// Trail ends closer than this count as joined for `chain_trails`.
+const CHAIN_LINK_KM: f64 = 0.2;
+
+/// Most trails `chain_trails` links up. The walk is cubic in the number of
+/// trails, so a large search only chains its best-ranked ones.
+const CHAIN_MAX_CANDIDATES: usize = 50;
+
+/// Link trails whose ends meet into an outing near `target_km`. From each
+/// starting trail, greedily walk on to the unused trail with an end nearest
+/// the current end until the target is reached or nothing joins. The chain of
+/// two or more trails whose total is closest to the target wins; `None` when
+/// no two trails meet. Only the first `CHAIN_MAX_CANDIDATES` trails are used.
+pub fn chain_trails(trails: &[Trail], target_km: f32) -> Option<TrailChain> {
+    let trails = &trails[..trails.len().min(CHAIN_MAX_CANDIDATES)];
+    let ends: Vec<Option<([f64; 2], [f64; 2])>> = trails
+        .iter()
+        .map(|trail| {
+            let first = trail.line.first().and_then(|segment| segment.first())?;
+            let last = trail.line.last().and_then(|segment| segment.last())?;
+            Some((*first, *last))
+        })
+        .collect();
+    let gap = |a: [f64; 2], b: [f64; 2]| distance::haversine_km(a[0], a[1], b[0], b[1]);
+
+    let mut best: Option<(Vec<usize>, f32)> = None;
+    for start in 0..trails.len() {
+        let Some((_, mut end)) = ends[start] else { continue };
+        let mut chain = vec![start];
+        let mut total = trails[start].distance_km;
+        while total < target_km {
+            // The nearest joining end, and the far end we'd walk on from.
+            let next = (0..trails.len())
+                .filter(|index| !chain.contains(index))
+                .filter_map(|index| {
+                    let (first, last) = ends[index]?;
+                    [(gap(end, first), last), (gap(end, last), first)]
+                        .into_iter()
+                        .filter(|(km, _)| *km <= CHAIN_LINK_KM)
+                        .min_by(|a, b| a.0.total_cmp(&b.0))
+                        .map(|(km, far)| (index, km, far))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let Some((index, _, far)) = next else { break };
+            chain.push(index);
+            total += trails[index].distance_km;
+            end = far;
+        }
+        let closer = best
+            .as_ref()
+            .is_none_or(|(_, best_total)| (total - target_km).abs() < (best_total - target_km).abs());
+        if chain.len() > 1 && closer {
+            best = Some((chain, total));
+        }
+    }
+    best.map(|(chain, total_km)| TrailChain {
+        trails: chain.into_iter().map(|index| trails[index].clone()).collect(),
+        total_km,
+    })
+}
+
+pub fn summarize_trails(trails: &[Trail]) -> TrailSummary {
+    let mut by_length = LengthHistogram::default();
+    for trail in trails {
+        let km = trail.distance_km;
+        let bucket = if km <= 0.0 {
+            &mut by_length.unknown
+        } else if km < 2.0 {
+            &mut by_length.under_2_km
+        } else if km < 6.0 {
+            &mut by_length.short
+        } else if km < 12.0 {
+            &mut by_length.medium
+        } else if km <= 24.0 {
+            &mut by_length.long
+        } else {
+            &mut by_length.over_24_km
+        };
+        *bucket += 1;
+    }
+    TrailSummary {
+        total: trails.len(),
+        by_length,
+    }
+}
+
+/// Douglas–Peucker simplification of a line in sections down to at most
+/// `max_points` points in total. One tolerance, in degrees, applies to every
+/// section and doubles until the result fits. Each section keeps its
+/// endpoints; if those alone don't fit, the sections with the most points
+/// are kept.
+pub(crate) fn simplify_sections(line: &[Vec<[f64; 2]>], max_points: usize) -> Vec<Vec<[f64; 2]>> {
+    let max_points = max_points.max(2);
+    if line.iter().map(Vec::len).sum::<usize>() <= max_points {
+        return line.to_vec();
+    }
+    let mut by_size: Vec<usize> = (0..line.len()).collect();
+    by_size.sort_by_key(|index| std::cmp::Reverse(line[*index].len()));
+    let mut kept = vec![false; line.len()];
+    let mut endpoints = 0;
+    for index in by_size {
+        let floor = line[index].len().min(2);
+        if endpoints + floor <= max_points {
+            endpoints += floor;
+            kept[index] = true;
+        }
+    }
+    let sections: Vec<&Vec<[f64; 2]>> = line.iter().zip(kept).filter_map(|(section, kept)| kept.then_some(section)).collect();
+    let mut tolerance = 1e-6;
+    loop {
+        let simplified: Vec<Vec<[f64; 2]>> = sections.iter().map(|section| douglas_peucker(section, tolerance)).collect();
+        if simplified.iter().map(Vec::len).sum::<usize>() <= max_points {
+            return simplified;
+        }
+        tolerance *= 2.0;
+    }
+}
+
+fn douglas_peucker(line: &[[f64; 2]], tolerance: f64) -> Vec<[f64; 2]> {
+    if line.len() <= 2 {
+        return line.to_vec();
+    }
+    let mut keep = vec![false; line.len()];
+    keep[0] = true;
+    keep[line.len() - 1] = true;
+    mark_douglas_peucker(line, 0, line.len() - 1, tolerance, &mut keep);
+    line.iter()
+        .zip(keep)
+        .filter_map(|(point, kept)| kept.then_some(*point))
+        .collect()
+}
+
+fn mark_douglas_peucker(line: &[[f64; 2]], start: usize, end: usize, tolerance: f64, keep: &mut [bool]) {
+    if end <= start + 1 {
+        return;
+    }
+    let (mut farthest, mut max_distance) = (start, 0.0);
+    for index in start + 1..end {
+        let distance = perpendicular_distance(line[index], line[start], line[end]);
+        if distance > max_distance {
+            farthest = index;
+            max_distance = distance;
+        }
+    }
+    if max_distance > tolerance {
+        keep[farthest] = true;
+        mark_douglas_peucker(line, start, farthest, tolerance, keep);
+        mark_douglas_peucker(line, farthest, end, tolerance, keep);
+    }
+}
+
+fn perpendicular_distance(point: [f64; 2], start: [f64; 2], end: [f64; 2]) -> f64 {
+    let (dx, dy) = (end[0] - start[0], end[1] - start[1]);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length == 0.0 {
+        return ((point[0] - start[0]).powi(2) + (point[1] - start[1]).powi(2)).sqrt();
+    }
+    (dy * (point[0] - start[0]) - dx * (point[1] - start[1])).abs() / length
+}
+
+pub(crate) fn map_difficulty(
+    sac_scale: Option<&String>,
+    distance_km: f32,
+    thresholds: DifficultyThresholds,
+) -> Difficulty {
+    if let Some(scale) = sac_scale {
+        return match scale.as_str() {
+            "hiking" => Difficulty::Easy,
+            "mountain_hiking" => Difficulty::Moderate,
+            "demanding_mountain_hiking" | "alpine_hiking" => Difficulty::Hard,
+            _ => Difficulty::Moderate,
+        };
+    }
+
+    if distance_km <= thresholds.easy_max_km {
+        Difficulty::Easy
+    } else if distance_km <= thresholds.moderate_max_km {
+        Difficulty::Moderate
+    } else {
+        Difficulty::Hard
+    }
+}
+
+fn has_coordinates(trail: &Trail) -> bool {
+    (trail.lat.is_some() && trail.lon.is_some()) || !trail.line.is_empty()
+}
+
+/// Whether `trail` lies within `max_km` of `center`. Passes when there is no
+/// cutoff, no centre, or the trail has no position to measure.
+fn within_center_cutoff(trail: &Trail, center: Option<(f64, f64)>, max_km: Option<f32>) -> bool {
+    let (Some(max_km), Some((center_lat, center_lon)), Some(lat), Some(lon)) = (max_km, center, trail.lat, trail.lon)
+    else {
+        return true;
+    };
+    distance::haversine_km(center_lat, center_lon, lat, lon) <= f64::from(max_km)
+}
+
+/// Longest trail `family_friendly` keeps.
+const FAMILY_MAX_KM: f32 = 6.0;
+
+/// Kid- and dog-friendly outing: `Easy` difficulty, at most 6 km (unknown
+/// lengths pass, as with other distance filters), `DogPolicy::Allowed`, a
+/// natural surface, and a loop by `is_loop` when the trail has a line.
+pub fn is_family_friendly(trail: &Trail, surfaces: &SurfaceClasses) -> bool {
+    trail.difficulty == Difficulty::Easy
+        && within_distance(trail.distance_km, &(None, Some(FAMILY_MAX_KM), None))
+        && trail.dog_policy == DogPolicy::Allowed
+        && surfaces.is_natural(&trail.surface)
+        && (trail.line.is_empty() || is_loop(trail))
+}
+
+fn dog_policy_allows(trail: &Trail, filter: &DogFilter) -> bool {
+    match filter {
+        DogFilter::AllowedOnly => {
+            trail.dog_policy == DogPolicy::Allowed || trail.dog_policy == DogPolicy::Unknown
         }
         DogFilter::AllowedOrPartial => {
             trail.dog_policy == DogPolicy::Allowed
                 || trail.dog_policy == DogPolicy::Partial
                 || trail.dog_policy == DogPolicy::Unknown
         }
-        DogFilter::Any => true,
+        DogFilter::Any => true,
+    }
+}
+
+/// The query's (min, max, target) km, with the target moved to the end of
+/// the range that `length_bias` asks for when that end is bounded.
+fn derive_distance_range(query: &TrailQuery) -> (Option<f32>, Option<f32>, Option<f32>) {
+    let (min_km, max_km, target) = base_distance_range(query);
+    let target = match query.length_bias.clone().unwrap_or_default() {
+        LengthBias::Shortest => min_km.or(target),
+        LengthBias::Balanced => target,
+        LengthBias::Longest => max_km.or(target),
+    };
+    (min_km, max_km, target)
+}
+
+fn base_distance_range(query: &TrailQuery) -> (Option<f32>, Option<f32>, Option<f32>) {
+    let min_km = query.min_km;
+    let max_km = query.max_km;
+    if min_km.is_some() || max_km.is_some() {
+        let target = min_km
+            .zip(max_km)
+            .map(|(min, max)| (min + max) / 2.0);
+        return (min_km, max_km, target);
+    }
+    // The family cap is the range unless a length was asked for too.
+    if query.family_friendly.unwrap_or(false) && query.length.is_none() {
+        return (None, Some(FAMILY_MAX_KM), None);
+    }
+
+    match query.length.clone().unwrap_or(Length::Medium) {
+        Length::Short => (Some(2.0), Some(6.0), Some(4.0)),
+        Length::Medium => (Some(6.0), Some(12.0), Some(9.0)),
+        Length::Long => (Some(12.0), Some(24.0), Some(16.0)),
+    }
+}
+
+fn within_distance(distance_km: f32, range: &(Option<f32>, Option<f32>, Option<f32>)) -> bool {
+    if distance_km == 0.0 {
+        return true;
+    }
+    let (min_km, max_km, _) = range;
+    if let Some(min) = min_km {
+        if distance_km < *min {
+            return false;
+        }
+    }
+    if let Some(max) = max_km {
+        if distance_km > *max {
+            return false;
+        }
+    }
+    true
+}
+
+/// Whether a search filters every provider's trails together or each
+/// provider's on its own first.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FilterPipeline {
+    /// Combine providers, then filter once.
+    #[default]
+    Combined,
+    /// Filter each provider's trails with its own rules, then combine and rank.
+    PerProvider,
+}
+
+/// A provider-specific check a trail must pass under `FilterPipeline::PerProvider`.
+pub type ProviderRule = Arc<dyn Fn(&Trail, &TrailQuery) -> bool + Send + Sync>;
+
+/// A built-in provider rule by the name `PROVIDER_RULES` uses: `named` drops
+/// trails with a generated name, `no_hard` drops hard trails, `with_line`
+/// drops trails without a polyline and `located` those without coordinates.
+pub fn named_provider_rule(name: &str) -> Option<ProviderRule> {
+    let rule: ProviderRule = match name {
+        "named" => Arc::new(|trail, _| !trail.name_generated),
+        "no_hard" => Arc::new(|trail, _| trail.difficulty != Difficulty::Hard),
+        "with_line" => Arc::new(|trail, _| !trail.line.is_empty()),
+        "located" => Arc::new(|trail, _| has_coordinates(trail)),
+        _ => return None,
+    };
+    Some(rule)
+}
+
+/// How much each ranking penalty counts in `score_trail`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoreWeights {
+    /// Per step between the trail's difficulty and the one the effort asks for.
+    pub effort: f32,
+    /// For trails whose name was generated.
+    pub generated_name: f32,
+    /// For trails whose surface is "Unknown"; 0 leaves them unpenalised.
+    pub unknown_surface: f32,
+    /// Scaled by how far a trail is from the most popular; unranked trails
+    /// count as middling, since no ranking says nothing about them. 0 ignores
+    /// popularity.
+    pub popularity: f32,
+    /// For trails that aren't a loop passing the search centre, scaled by how
+    /// far the loop strays from it; 0 ignores shape and start point.
+    pub near_loop: f32,
+    /// Give trails of unknown length the average distance penalty of the
+    /// known ones instead of measuring 0 km against the target.
+    pub neutral_unknown_distance: bool,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self {
+            effort: 2.0,
+            generated_name: 1.0,
+            unknown_surface: 0.0,
+            popularity: 0.0,
+            near_loop: 0.0,
+            neutral_unknown_distance: true,
+        }
+    }
+}
+
+/// Ends closer than this make a line a loop.
+const LOOP_CLOSE_KM: f64 = 0.1;
+
+/// Loops whose nearest point is this far from the start get no preference.
+const NEAR_LOOP_KM: f64 = 2.0;
+
+/// Whether a trail's line ends where it starts, so walking it brings you back
+/// to the car.
+pub fn is_loop(trail: &Trail) -> bool {
+    let first = trail.line.first().and_then(|segment| segment.first());
+    let last = trail.line.last().and_then(|segment| segment.last());
+    match (first, last) {
+        (Some(first), Some(last)) => {
+            let points: usize = trail.line.iter().map(Vec::len).sum();
+            points > 2 && distance::haversine_km(first[0], first[1], last[0], last[1]) <= LOOP_CLOSE_KM
+        }
+        _ => false,
+    }
+}
+
+/// "Easy 5.2 km gravel loop, dogs allowed, near Wellington". Unknown
+/// distance, surface, dog access and place are left out rather than spelled
+/// "unknown".
+pub fn trail_summary(trail: &Trail) -> String {
+    let mut walk = vec![
+        match trail.difficulty {
+            Difficulty::Easy => "Easy",
+            Difficulty::Moderate => "Moderate",
+            Difficulty::Hard => "Hard",
+        }
+        .to_string(),
+    ];
+    if trail.distance_km > 0.0 {
+        let estimate = if trail.distance_source == DistanceSource::ComputedFromGeometry { "~" } else { "" };
+        walk.push(format!("{estimate}{:.1} km", trail.distance_km));
+    }
+    let surface = trail.surface.trim();
+    if !surface.is_empty() && !surface.eq_ignore_ascii_case("unknown") {
+        walk.push(surface.to_lowercase().replace('_', " "));
+    }
+    walk.push(if is_loop(trail) { "loop" } else { "walk" }.to_string());
+
+    let mut parts = vec![walk.join(" ")];
+    let dogs = match trail.dog_policy {
+        DogPolicy::Allowed => Some("dogs allowed"),
+        DogPolicy::Partial => Some("dogs on lead or in parts only"),
+        DogPolicy::HuntingPermit => Some("dogs with a hunting permit"),
+        DogPolicy::NotAllowed => Some("no dogs"),
+        DogPolicy::Unknown => None,
+    };
+    parts.extend(dogs.map(str::to_string));
+    let near = [&trail.place, &trail.district, &trail.region]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .chain([trail.location.as_str()])
+        .find(|place| !place.is_empty() && *place != "Unknown" && *place != "New Zealand");
+    parts.extend(near.map(|place| format!("near {place}")));
+    parts.join(", ")
+}
+
+/// 0 for a loop that passes `start`, rising to 1 for loops `NEAR_LOOP_KM` or
+/// more away and for anything that isn't a loop.
+fn near_loop_penalty(trail: &Trail, start: (f64, f64)) -> f32 {
+    if !is_loop(trail) {
+        return 1.0;
+    }
+    let nearest_km = trail
+        .line
+        .iter()
+        .flatten()
+        .map(|point| distance::haversine_km(start.0, start.1, point[0], point[1]))
+        .fold(f64::INFINITY, f64::min);
+    (nearest_km / NEAR_LOOP_KM).min(1.0) as f32
+}
+
+fn score_trail(
+    trail: &Trail,
+    range: &(Option<f32>, Option<f32>, Option<f32>),
+    effort: Option<&Effort>,
+    start: Option<(f64, f64)>,
+    weights: &ScoreWeights,
+    unknown_distance: Option<f32>,
+) -> f32 {
+    let distance_penalty = match unknown_distance {
+        Some(neutral) if trail.distance_km <= 0.0 => neutral,
+        _ => distance_penalty(trail.distance_km, range),
+    };
+
+    let effort_penalty = match effort {
+        Some(Effort::Easy) => difficulty_penalty(&trail.difficulty, &Difficulty::Easy),
+        Some(Effort::Steady) => difficulty_penalty(&trail.difficulty, &Difficulty::Moderate),
+        Some(Effort::Hard) => difficulty_penalty(&trail.difficulty, &Difficulty::Hard),
+        None => 0.5,
+    };
+
+    let elevation_penalty = trail.elevation_m.unwrap_or(0.0) / 600.0;
+    // Generated names and unknown surfaces mean sparse tagging, so trust those
+    // trails a little less.
+    let confidence_penalty = if trail.name_generated { weights.generated_name } else { 0.0 };
+    let surface_penalty = if trail.surface == "Unknown" { weights.unknown_surface } else { 0.0 };
+    let popularity_penalty = weights.popularity * (1.0 - trail.popularity.unwrap_or(0.5).clamp(0.0, 1.0));
+    let loop_penalty = match start {
+        Some(start) if weights.near_loop > 0.0 => weights.near_loop * near_loop_penalty(trail, start),
+        _ => 0.0,
+    };
+    distance_penalty
+        + effort_penalty * weights.effort
+        + elevation_penalty
+        + confidence_penalty
+        + surface_penalty
+        + popularity_penalty
+        + loop_penalty
+}
+
+fn distance_penalty(distance_km: f32, range: &(Option<f32>, Option<f32>, Option<f32>)) -> f32 {
+    (distance_km - range.2.unwrap_or(distance_km)).abs()
+}
+
+/// Mean distance penalty of the trails with a known length; 0 when none has one.
+fn average_distance_penalty(trails: &[&Trail], range: &(Option<f32>, Option<f32>, Option<f32>)) -> f32 {
+    let known: Vec<f32> = trails
+        .iter()
+        .filter(|trail| trail.distance_km > 0.0)
+        .map(|trail| distance_penalty(trail.distance_km, range))
+        .collect();
+    if known.is_empty() { 0.0 } else { known.iter().sum::<f32>() / known.len() as f32 }
+}
+
+fn difficulty_penalty(actual: &Difficulty, preferred: &Difficulty) -> f32 {
+    (actual.tier() - preferred.tier()).abs() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_trails() -> Vec<Trail> {
+        vec![
+            Trail {
+                id: "t1".to_string(),
+                name: "River Loop".to_string(),
+                provider: Provider::DOC,
+                location: "Wellington".to_string(),
+                distance_km: 5.0,
+                distance_source: DistanceSource::ProviderReported,
+                elevation_m: Some(120.0),
+                elevation_point_m: None,
+                net_elevation_m: None,
+                difficulty: Difficulty::Easy,
+                dog_policy: DogPolicy::Allowed,
+                dog_notes: None,
+                surface: "Gravel".to_string(),
+                surfaces: vec![("Gravel".to_string(), 1.0)],
+                surface_label: String::new(),
+                map_url: "https://www.doc.govt.nz".to_string(),
+                lat: Some(-41.3),
+                lon: Some(174.7),
+                has_location: true,
+                line: Vec::new(),
+                line_bbox: Bbox { min_lat: -41.3, min_lon: 174.7, max_lat: -41.3, max_lon: 174.7 },
+                match_score: None,
+                name_generated: false,
+                region: None,
+                district: None,
+                place: None,
+                hot_paw_risk: None,
+                slug: String::new(),
+                summary: String::new(),
+                raw: None,
+                water_crossing: false,
+                provider_name: String::new(),
+                difficulty_conflict: false,
+                thumbnail_url: None,
+                images: Vec::new(),
+                warnings: Vec::new(),
+                data_checked: None,
+                data_outdated: false,
+                featured: None,
+                popularity: None,
+                dog_seasons: Vec::new(),
+            },
+            Trail {
+                id: "t2".to_string(),
+                name: "Forest Ridge".to_string(),
+                provider: Provider::OpenStreetMap,
+                location: "Auckland".to_string(),
+                distance_km: 12.0,
+                distance_source: DistanceSource::ProviderReported,
+                elevation_m: Some(520.0),
+                elevation_point_m: None,
+                net_elevation_m: None,
+                difficulty: Difficulty::Hard,
+                dog_policy: DogPolicy::NotAllowed,
+                dog_notes: Some("Dog-free section after 2km".to_string()),
+                surface: "Dirt".to_string(),
+                surfaces: vec![("Dirt".to_string(), 1.0)],
+                surface_label: String::new(),
+                map_url: "https://www.openstreetmap.org/".to_string(),
+                lat: Some(-36.8),
+                lon: Some(174.7),
+                has_location: true,
+                line: Vec::new(),
+                line_bbox: Bbox { min_lat: -36.8, min_lon: 174.7, max_lat: -36.8, max_lon: 174.7 },
+                match_score: None,
+                name_generated: false,
+                region: None,
+                district: None,
+                place: None,
+                hot_paw_risk: None,
+                slug: String::new(),
+                summary: String::new(),
+                raw: None,
+                water_crossing: false,
+                provider_name: String::new(),
+                difficulty_conflict: false,
+                thumbnail_url: None,
+                images: Vec::new(),
+                warnings: Vec::new(),
+                data_checked: None,
+                data_outdated: false,
+                featured: None,
+                popularity: None,
+                dog_seasons: Vec::new(),
+            },
+        ]
+    }
+
+    /// An easy 5 km gravel DOC trail near Nelson that allows dogs, for tests
+    /// in other modules; everything not set here takes its serde default.
+    pub(crate) fn test_trail(id: &str, name: &str) -> Trail {
+        serde_json::from_value(serde_json::json!({
+            "id": id, "name": name, "provider": "DOC", "location": "Nelson",
+            "distance_km": 5.0, "elevation_m": null, "difficulty": "easy",
+            "dog_policy": "allowed", "dog_notes": null, "surface": "gravel",
+            "map_url": "https://example.test/?a=1&b=2", "lat": -41.3, "lon": 173.2,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn filters_dog_allowed_by_default() {
+        let trails = sample_trails();
+        let query = TrailQuery {
+            length: Some(Length::Short),
+            ..TrailQuery::default()
+        };
+        let results = filter_trails(&trails, &query);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "t1");
+    }
+
+    #[test]
+    fn custom_difficulty_thresholds_regrade_the_same_distance() {
+        let running = DifficultyThresholds { easy_max_km: 12.0, moderate_max_km: 25.0 };
+        assert_eq!(map_difficulty(None, 10.0, DifficultyThresholds::default()), Difficulty::Moderate);
+        assert_eq!(map_difficulty(None, 10.0, running), Difficulty::Easy);
+        assert_eq!(map_difficulty(None, 20.0, running), Difficulty::Moderate);
+        let hiking = "hiking".to_string();
+        assert_eq!(map_difficulty(Some(&hiking), 30.0, running), Difficulty::Easy);
+    }
+
+    #[test]
+    fn family_friendly_keeps_short_easy_gravel_trails() {
+        let mut trails = sample_trails();
+        trails[1].dog_policy = DogPolicy::Allowed;
+        assert!(is_family_friendly(&trails[0], &SurfaceClasses::default()));
+        assert!(!is_family_friendly(&trails[1], &SurfaceClasses::default()));
+        // Dogs must be known to be allowed.
+        let mut unknown = trails[0].clone();
+        unknown.dog_policy = DogPolicy::Unknown;
+        assert!(!is_family_friendly(&unknown, &SurfaceClasses::default()));
+        unknown.dog_policy = DogPolicy::Partial;
+        assert!(!is_family_friendly(&unknown, &SurfaceClasses::default()));
+        // A trail with a line must loop back to its start.
+        let mut out_and_back = trails[0].clone();
+        out_and_back.line = vec![vec![[-41.30, 174.70], [-41.30, 174.73], [-41.28, 174.75]]];
+        assert!(!is_family_friendly(&out_and_back, &SurfaceClasses::default()));
+        out_and_back.line[0].push([-41.30, 174.70]);
+        assert!(is_family_friendly(&out_and_back, &SurfaceClasses::default()));
+
+        let query = TrailQuery {
+            family_friendly: Some(true),
+            dog: Some(DogFilter::Any),
+            ..TrailQuery::default()
+        };
+        let results = filter_trails(&trails, &query);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "t1");
+    }
+
+    #[test]
+    fn surface_classes_can_be_overridden() {
+        let mut trail = sample_trails().remove(0);
+        trail.surface = "shell".to_string();
+        let defaults = SurfaceClasses::default();
+        assert!(!is_family_friendly(&trail, &defaults));
+        assert_eq!(hot_paw_risk("gravel", 1, &[1], &defaults), Some(false));
+
+        let local = SurfaceClasses {
+            natural: vec!["shell".to_string(), "sand".to_string()],
+            hard: vec!["gravel".to_string()],
+        };
+        assert!(is_family_friendly(&trail, &local));
+        assert_eq!(hot_paw_risk("gravel", 1, &[1], &local), Some(true));
+        assert_eq!(hot_paw_risk("asphalt", 1, &[1], &local), Some(false));
+    }
+
+    #[test]
+    fn hard_surfaces_match_whole_words() {
+        let surfaces = SurfaceClasses::default();
+        assert!(surfaces.is_hard("paved"));
+        assert!(surfaces.is_hard("paved_road"));
+        assert!(surfaces.is_hard("Paving_Stones"));
+        assert!(surfaces.is_hard("concrete:plates"));
+        assert!(!surfaces.is_hard("unpaved"));
+        assert_eq!(hot_paw_risk("unpaved", 1, &[1], &surfaces), Some(false));
+
+        // Natural surfaces match the same way.
+        assert!(surfaces.is_natural("Gravel"));
+        assert!(surfaces.is_natural("gravel;grass"));
+        assert!(!surfaces.is_natural("unpaved"));
+        assert!(!surfaces.is_natural(""));
+    }
+
+    #[test]
+    fn ascii_strips_macrons_only_when_asked() {
+        let trail = Trail {
+            name: "Ōtari Loop".to_string(),
+            location: "Te Whanganui-a-Tara".to_string(),
+            district: Some("Ōtaki".to_string()),
+            ..sample_trails().remove(0)
+        };
+        let kept = apply_ascii(vec![trail.clone()], &TrailQuery::default());
+        assert_eq!(kept[0].name, "Ōtari Loop");
+
+        let ascii = apply_ascii(vec![trail], &TrailQuery { ascii: Some(true), ..TrailQuery::default() });
+        assert_eq!(ascii[0].name, "Otari Loop");
+        assert_eq!(ascii[0].district.as_deref(), Some("Otaki"));
+        assert_eq!(strip_macrons("Ma\u{0304}ori"), "Maori");
+    }
+
+    #[test]
+    fn unknown_distance_ranks_mid_pack() {
+        let template = sample_trails().remove(0);
+        let trail = |id: &str, km: f32| Trail { id: id.to_string(), distance_km: km, ..template.clone() };
+        let trails = vec![trail("close", 10.0), trail("far", 3.0), trail("unknown", 0.0)];
+        let query = TrailQuery {
+            min_km: Some(0.0),
+            max_km: Some(20.0),
+            ..TrailQuery::default()
+        };
+        let ids = |weights: &ScoreWeights| -> Vec<String> {
+            filter_trails_weighted(&trails, &query, DEFAULT_PROVIDER_PRIORITY, weights, &SurfaceClasses::default())
+                .into_iter()
+                .map(|trail| trail.id)
+                .collect()
+        };
+        assert_eq!(ids(&ScoreWeights::default()), ["close", "unknown", "far"]);
+
+        let literal = ScoreWeights { neutral_unknown_distance: false, ..ScoreWeights::default() };
+        assert_eq!(ids(&literal), ["close", "far", "unknown"]);
+    }
+
+    #[test]
+    fn unknown_surface_weight_ranks_documented_trails_first() {
+        let documented = sample_trails().remove(0);
+        let undocumented = Trail {
+            id: "t0".to_string(),
+            surface: "Unknown".to_string(),
+            ..documented.clone()
+        };
+        let trails = vec![documented, undocumented];
+        let query = TrailQuery {
+            length: Some(Length::Short),
+            ..TrailQuery::default()
+        };
+
+        let neutral = filter_trails(&trails, &query);
+        assert_eq!(neutral[0].id, "t0");
+
+        let weights = ScoreWeights { unknown_surface: 0.5, ..ScoreWeights::default() };
+        let ranked = filter_trails_weighted(&trails, &query, DEFAULT_PROVIDER_PRIORITY, &weights, &SurfaceClasses::default());
+        assert_eq!(ranked[0].id, "t1");
+        assert_eq!(ranked[1].id, "t0");
+    }
+
+    #[test]
+    fn per_provider_pipeline_matches_combined_until_a_rule_is_added() {
+        let mut trails = sample_trails();
+        trails[1].dog_policy = DogPolicy::Allowed;
+        let query = TrailQuery {
+            dog: Some(DogFilter::Any),
+            min_km: Some(0.0),
+            max_km: Some(20.0),
+            ..TrailQuery::default()
+        };
+        let ids = |trails: Vec<Trail>| trails.into_iter().map(|trail| trail.id).collect::<Vec<_>>();
+        let service = || TrailService::new(Vec::new(), "test-key".to_string()).unwrap();
+
+        let combined = service().filter_trails(&trails, &query);
+        let per_provider = service()
+            .with_filter_pipeline(FilterPipeline::PerProvider)
+            .filter_trails(&trails, &query);
+        assert_eq!(combined.len(), 2);
+        assert_eq!(ids(per_provider.clone()), ids(combined.clone()));
+        assert_eq!(
+            per_provider.iter().map(|trail| trail.match_score).collect::<Vec<_>>(),
+            combined.iter().map(|trail| trail.match_score).collect::<Vec<_>>()
+        );
+
+        let no_hard_osm: ProviderRule = Arc::new(|trail, _| trail.difficulty != Difficulty::Hard);
+        let ruled = service()
+            .with_filter_pipeline(FilterPipeline::PerProvider)
+            .with_provider_rule(Provider::OpenStreetMap, no_hard_osm.clone());
+        assert_eq!(ids(ruled.filter_trails(&trails, &query)), vec!["t1".to_string()]);
+        let ignored = service().with_provider_rule(Provider::OpenStreetMap, no_hard_osm);
+        assert_eq!(ids(ignored.filter_trails(&trails, &query)), ids(combined));
+
+        // Relaxing widens the query but still applies the provider's rules.
+        let short = TrailQuery { dog: Some(DogFilter::Any), length: Some(Length::Short), ..TrailQuery::default() };
+        let (relaxed, was_relaxed) = service().filter_trails_relaxed(&trails, &short, 2);
+        assert!(was_relaxed);
+        assert_eq!(relaxed.len(), 2);
+        let ruled = service()
+            .with_filter_pipeline(FilterPipeline::PerProvider)
+            .with_provider_rule(Provider::OpenStreetMap, named_provider_rule("no_hard").unwrap());
+        let (relaxed, was_relaxed) = ruled.filter_trails_relaxed(&trails, &short, 2);
+        assert!(!was_relaxed);
+        assert_eq!(ids(relaxed), vec!["t1".to_string()]);
+        assert!(named_provider_rule("bogus").is_none());
+    }
+
+    #[test]
+    fn longest_bias_ranks_near_max_trails_first() {
+        let template = sample_trails().remove(0);
+        let trails = vec![
+            Trail { id: "mid".to_string(), distance_km: 9.0, ..template.clone() },
+            Trail { id: "near-max".to_string(), distance_km: 11.5, ..template.clone() },
+            Trail { id: "near-min".to_string(), distance_km: 6.5, ..template },
+        ];
+        let ids = |bias: Option<LengthBias>| {
+            let query = TrailQuery {
+                length: Some(Length::Medium),
+                length_bias: bias,
+                ..TrailQuery::default()
+            };
+            filter_trails(&trails, &query).into_iter().map(|trail| trail.id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(None), vec!["mid", "near-max", "near-min"]);
+        assert_eq!(ids(Some(LengthBias::Balanced)), ids(None));
+        assert_eq!(ids(Some(LengthBias::Longest)), vec!["near-max", "mid", "near-min"]);
+        assert_eq!(ids(Some(LengthBias::Shortest)), vec!["near-min", "mid", "near-max"]);
+    }
+
+    #[test]
+    fn seasonal_dog_policy_wraps_over_new_year() {
+        let seasons = vec![
+            DogSeason { start: (12, 1), end: (2, 28), policy: DogPolicy::Partial },
+            DogSeason { start: (3, 1), end: (11, 30), policy: DogPolicy::NotAllowed },
+        ];
+        assert_eq!(seasonal_dog_policy(&seasons, 1, 15), Some(DogPolicy::Partial));
+        assert_eq!(seasonal_dog_policy(&seasons, 12, 1), Some(DogPolicy::Partial));
+        assert_eq!(seasonal_dog_policy(&seasons, 7, 4), Some(DogPolicy::NotAllowed));
+        assert_eq!(seasonal_dog_policy(&seasons[..1], 7, 4), None);
+        assert_eq!(seasonal_dog_policy(&seasons, 2, 29), Some(DogPolicy::Partial));
+    }
+
+    #[test]
+    fn seasonal_rule_replaces_stale_dog_notes() {
+        let service = TrailService::new(Vec::new(), "test-key".to_string()).unwrap();
+        let mut trail = sample_trails().remove(0);
+        trail.dog_policy = DogPolicy::Unknown;
+        trail.dog_notes = Some("Dogs are not permitted.".to_string());
+        trail.dog_seasons = (1..=12)
+            .map(|month| DogSeason { start: (month, 1), end: (month, 28), policy: DogPolicy::Partial })
+            .chain((1..=12).map(|month| DogSeason { start: (month, 29), end: (month, 31), policy: DogPolicy::Partial }))
+            .collect();
+        service.finish_trail(&mut trail);
+        assert!(trail.dog_policy == DogPolicy::Partial);
+        assert!(trail.dog_notes.as_deref().unwrap().starts_with("Dogs on a lead 1 Jan–28 Jan."));
+    }
+
+    #[test]
+    fn near_loop_weight_prefers_loops_by_the_start() {
+        let template = Trail { distance_km: 9.0, ..sample_trails().remove(0) };
+        // A square roughly 1 km a side whose first corner is at `corner`.
+        let square = |corner: [f64; 2]| {
+            let [lat, lon] = corner;
+            vec![vec![[lat, lon], [lat, lon + 0.012], [lat - 0.009, lon + 0.012], [lat - 0.009, lon], [lat, lon]]]
+        };
+        let trails = vec![
+            Trail { id: "a-far-loop".to_string(), line: square([-41.20, 174.80]), ..template.clone() },
+            Trail {
+                id: "b-near-out-and-back".to_string(),
+                line: vec![vec![[-41.30, 174.70], [-41.30, 174.73], [-41.28, 174.75]]],
+                ..template.clone()
+            },
+            Trail { id: "c-near-loop".to_string(), line: square([-41.30, 174.70]), ..template },
+        ];
+        assert!(is_loop(&trails[0]) && !is_loop(&trails[1]) && is_loop(&trails[2]));
+        let query = TrailQuery {
+            min_lat: Some(-41.31),
+            min_lon: Some(174.69),
+            max_lat: Some(-41.29),
+            max_lon: Some(174.71),
+            ..TrailQuery::default()
+        };
+        let ids = |weights: &ScoreWeights| {
+            filter_trails_weighted(&trails, &query, DEFAULT_PROVIDER_PRIORITY, weights, &SurfaceClasses::default())
+                .into_iter()
+                .map(|trail| trail.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(&ScoreWeights::default())[0], "a-far-loop");
+        let weights = ScoreWeights { near_loop: 1.0, ..ScoreWeights::default() };
+        assert_eq!(ids(&weights)[0], "c-near-loop");
+    }
+
+    #[test]
+    fn popularity_weight_prefers_busier_trails() {
+        let quiet = Trail {
+            id: "t0".to_string(),
+            popularity: Some(0.2),
+            ..sample_trails().remove(0)
+        };
+        let busy = Trail {
+            popularity: Some(0.9),
+            ..sample_trails().remove(0)
+        };
+        let trails = vec![busy, quiet];
+        let query = TrailQuery {
+            length: Some(Length::Short),
+            ..TrailQuery::default()
+        };
+
+        assert_eq!(filter_trails(&trails, &query)[0].id, "t0");
+        let weights = ScoreWeights { popularity: 1.0, ..ScoreWeights::default() };
+        let ranked = filter_trails_weighted(&trails, &query, DEFAULT_PROVIDER_PRIORITY, &weights, &SurfaceClasses::default());
+        assert_eq!(ranked[0].id, "t1");
+
+        // An unranked trail sits between a quiet and a busy one.
+        let unranked = Trail {
+            id: "t2".to_string(),
+            popularity: None,
+            ..sample_trails().remove(0)
+        };
+        let trails = vec![trails[0].clone(), trails[1].clone(), unranked];
+        let ranked = filter_trails_weighted(&trails, &query, DEFAULT_PROVIDER_PRIORITY, &weights, &SurfaceClasses::default());
+        let ids: Vec<_> = ranked.iter().map(|trail| trail.id.as_str()).collect();
+        assert_eq!(ids, ["t1", "t2", "t0"]);
+    }
+
+    #[test]
+    fn featured_only_keeps_featured_tracks() {
+        let mut trails = sample_trails();
+        trails[0].featured = Some(true);
+        trails[1].dog_policy = DogPolicy::Allowed;
+        trails[1].featured = Some(false);
+        let query = TrailQuery {
+            featured_only: Some(true),
+            min_km: Some(0.0),
+            max_km: Some(20.0),
+            ..TrailQuery::default()
+        };
+        let results = filter_trails(&trails, &query);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "t1");
+    }
+
+    #[test]
+    fn trusted_only_drops_osm_trails() {
+        let mut trails = sample_trails();
+        trails[0].provider = Provider::DOC;
+        trails[1].provider = Provider::OpenStreetMap;
+        trails[1].dog_policy = DogPolicy::Allowed;
+        let query = TrailQuery {
+            min_km: Some(0.0),
+            max_km: Some(20.0),
+            ..TrailQuery::default()
+        };
+        assert_eq!(filter_trails(&trails, &query).len(), 2);
+
+        let trusted = TrailQuery { trusted_only: Some(true), ..query };
+        let results = filter_trails(&trails, &trusted);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].provider == Provider::DOC);
+    }
+
+    #[test]
+    fn center_cutoff_drops_trails_just_outside() {
+        let mut trails = sample_trails();
+        trails[1].dog_policy = DogPolicy::Allowed;
+        // About 9.9 km and 10.1 km due north of the centre at (-41.0, 174.0).
+        trails[0].lat = Some(-41.0 + 9.9 / 111.195);
+        trails[0].lon = Some(174.0);
+        trails[1].lat = Some(-41.0 + 10.1 / 111.195);
+        trails[1].lon = Some(174.0);
+        let query = TrailQuery {
+            min_km: Some(0.0),
+            max_km: Some(20.0),
+            min_lat: Some(-41.5),
+            max_lat: Some(-40.5),
+            min_lon: Some(173.5),
+            max_lon: Some(174.5),
+            max_distance_from_center_km: Some(10.0),
+            ..TrailQuery::default()
+        };
+        let results = filter_trails(&trails, &query);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "t1");
+
+        trails[1].lat = None;
+        trails[1].lon = None;
+        assert_eq!(filter_trails(&trails, &query).len(), 2);
+    }
+
+    #[test]
+    fn allows_any_dog_policy_when_requested() {
+        let trails = sample_trails();
+        let query = TrailQuery {
+            dog: Some(DogFilter::Any),
+            min_km: Some(0.0),
+            max_km: Some(20.0),
+            ..TrailQuery::default()
+        };
+        let results = filter_trails(&trails, &query);
+        assert_eq!(results.len(), 2);
+    }
+
+    async fn provider_status(service: &TrailService, provider: Provider) -> String {
+        service
+            .provider_info()
+            .await
+            .into_iter()
+            .find(|info| info.provider == provider)
+            .unwrap()
+            .api_status
+    }
+
+    #[tokio::test]
+    async fn provider_info_reflects_doc_key() {
+        let urls = vec!["https://overpass.example/api/interpreter".to_string()];
+        let service = TrailService::new(urls.clone(), String::new()).unwrap();
+        assert_eq!(provider_status(&service, Provider::DOC).await, "no key");
+        let service = TrailService::new(urls, "secret".to_string()).unwrap();
+        assert_eq!(provider_status(&service, Provider::DOC).await, "enabled");
+    }
+
+    #[tokio::test]
+    async fn provider_info_reflects_the_last_probe() {
+        let router = axum::Router::new()
+            .route(
+                "/ok/api/interpreter",
+                axum::routing::get(|| async { axum::Json(serde_json::json!({ "elements": [] })) }),
+            )
+            .route(
+                "/down/api/interpreter",
+                axum::routing::get(|| async { axum::http::StatusCode::SERVICE_UNAVAILABLE }),
+            );
+        let base = serve_mock(router).await;
+        let ok = format!("{base}/ok/api/interpreter");
+        let down = format!("{base}/down/api/interpreter");
+        let service = TrailService::new(vec![ok, down.clone()], String::new()).unwrap();
+        assert_eq!(provider_status(&service, Provider::OpenStreetMap).await, "enabled (2 mirrors)");
+
+        service.probe_overpass_mirrors().await;
+        assert_eq!(provider_status(&service, Provider::OpenStreetMap).await, "enabled (1 healthy, 1 failed)");
+
+        let service = TrailService::new(vec![down], String::new()).unwrap();
+        service.probe_overpass_mirrors().await;
+        assert_eq!(
+            provider_status(&service, Provider::OpenStreetMap).await,
+            "unavailable (every mirror failed the last probe)"
+        );
+    }
+
+    #[test]
+    fn rejects_service_without_providers() {
+        let result = TrailService::new(Vec::new(), String::new());
+        assert!(matches!(result, Err(TrailError::Config(_))));
+    }
+
+    #[test]
+    fn buckets_trails_by_length() {
+        let template = sample_trails().remove(0);
+        let trails: Vec<Trail> = [0.0, 1.5, 2.0, 5.9, 6.0, 11.0, 12.0, 24.0, 30.0]
+            .into_iter()
+            .map(|km| Trail { distance_km: km, ..template.clone() })
+            .collect();
+        let summary = summarize_trails(&trails);
+        assert_eq!(summary.total, 9);
+        assert_eq!(
+            summary.by_length,
+            LengthHistogram {
+                under_2_km: 1,
+                short: 2,
+                medium: 2,
+                long: 2,
+                over_24_km: 1,
+                unknown: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn drops_unlocated_trails_when_requested() {
+        let real = sample_trails().remove(0);
+        let mut missing = real.clone();
+        missing.id = "t0".to_string();
+        missing.lat = None;
+        missing.lon = None;
+        let query = TrailQuery {
+            length: Some(Length::Short),
+            with_coordinates: Some(true),
+            ..TrailQuery::default()
+        };
+        let results = filter_trails(&[missing, real], &query);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "t1");
+    }
+
+    #[test]
+    fn keeps_unlocated_trails_by_default() {
+        let mut missing = sample_trails().remove(0);
+        missing.lat = None;
+        missing.lon = None;
+        let query = TrailQuery {
+            length: Some(Length::Short),
+            ..TrailQuery::default()
+        };
+        assert_eq!(filter_trails(&[missing], &query).len(), 1);
+    }
+
+    #[test]
+    fn relaxes_strict_query_when_nothing_matches() {
+        let mut partial = sample_trails().remove(0);
+        partial.dog_policy = DogPolicy::Partial;
+        partial.distance_km = 9.0;
+        let query = TrailQuery {
+            dog: Some(DogFilter::AllowedOnly),
+            length: Some(Length::Short),
+            ..TrailQuery::default()
+        };
+        assert!(filter_trails(std::slice::from_ref(&partial), &query).is_empty());
+
+        let (results, relaxed) = filter_trails_relaxed(&[partial], &query, 1);
+        assert!(relaxed);
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn does_not_relax_when_enough_results() {
+        let query = TrailQuery {
+            length: Some(Length::Short),
+            ..TrailQuery::default()
+        };
+        let (results, relaxed) = filter_trails_relaxed(&sample_trails(), &query, 1);
+        assert!(!relaxed);
+        assert_eq!(results.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn compares_cached_trails() {
+        let service = TrailService::new(vec!["https://overpass.example".to_string()], String::new()).unwrap();
+        *service.overpass_cache.write().await = Some(OverpassCacheEntry {
+            fetched_at: Instant::now(),
+            bbox: Bbox::default(),
+            trails: sample_trails(),
+        });
+
+        let ids = vec!["t1".to_string(), "t2".to_string(), "nope".to_string()];
+        let comparison = service.compare(&ids).await;
+        let found: Vec<&str> = comparison.trails.iter().map(|trail| trail.id.as_str()).collect();
+        assert_eq!(found, vec!["t1", "t2"]);
+        assert_eq!(comparison.missing, vec!["nope".to_string()]);
+        assert_eq!(
+            comparison.diff,
+            ComparisonDiff {
+                distance_km_spread: 7.0,
+                elevation_m_spread: Some(400.0),
+                same_difficulty: false,
+            }
+        );
+    }
+
+    #[test]
+    fn best_trail_gets_highest_match_score() {
+        let mut near = sample_trails().remove(0);
+        near.distance_km = 4.0;
+        let mut far = near.clone();
+        far.id = "t5".to_string();
+        far.distance_km = 6.0;
+        let query = TrailQuery {
+            length: Some(Length::Short),
+            ..TrailQuery::default()
+        };
+        let results = filter_trails(&[far, near], &query);
+        assert_eq!(results[0].id, "t1");
+        assert_eq!(results[0].match_score, Some(100));
+        assert_eq!(results[1].match_score, Some(0));
+    }
+
+    #[test]
+    fn chains_joined_trails_towards_the_target() {
+        let template = sample_trails().remove(0);
+        let trail = |id: &str, km: f32, line: Vec<[f64; 2]>| Trail {
+            id: id.to_string(),
+            distance_km: km,
+            line: vec![line],
+            ..template.clone()
+        };
+        let trails = vec![
+            trail("a", 2.0, vec![[-43.500, 172.600], [-43.500, 172.620]]),
+            // Joins `a`'s end by its own end, so it's walked in reverse.
+            trail("b", 3.0, vec![[-43.520, 172.640], [-43.5005, 172.6205]]),
+            trail("c", 2.5, vec![[-43.5205, 172.6405], [-43.540, 172.660]]),
+            trail("far", 7.5, vec![[-41.0, 174.0], [-41.1, 174.1]]),
+        ];
+
+        let chain = chain_trails(&trails, 7.0).unwrap();
+        let ids: Vec<&str> = chain.trails.iter().map(|trail| trail.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+        assert_eq!(chain.total_km, 7.5);
+        assert!(chain_trails(&trails[3..], 7.0).is_none());
+    }
+
+    #[test]
+    fn merged_duplicate_takes_the_known_surface() {
+        let trails = sample_trails();
+        let mut doc = trails[0].clone();
+        doc.provider = Provider::DOC;
+        doc.surface = "Unknown".to_string();
+        doc.dog_policy = DogPolicy::Allowed;
+        let mut osm = trails[1].clone();
+        osm.provider = Provider::OpenStreetMap;
+        osm.surface = "gravel".to_string();
+        osm.dog_policy = DogPolicy::NotAllowed;
+        osm.water_crossing = true;
+
+        let merged = merge_duplicate(&osm, &doc, DEFAULT_PROVIDER_PRIORITY);
+        assert_eq!(merged.surface, "gravel");
+        assert_eq!(merged.id, doc.id);
+        // Both policies are known, so DOC's wins on priority.
+        assert_eq!(merged.dog_policy, DogPolicy::Allowed);
+        // A crossing either provider mentions is kept.
+        assert!(merged.water_crossing);
+    }
+
+    #[test]
+    fn merged_duplicate_flags_difficulties_two_tiers_apart() {
+        let trails = sample_trails();
+        let mut doc = trails[0].clone();
+        doc.provider = Provider::DOC;
+        doc.difficulty = Difficulty::Hard;
+        let mut osm = trails[1].clone();
+        osm.provider = Provider::OpenStreetMap;
+        osm.difficulty = Difficulty::Easy;
+
+        let merged = merge_duplicate(&osm, &doc, DEFAULT_PROVIDER_PRIORITY);
+        assert!(merged.difficulty_conflict);
+        assert_eq!(merged.difficulty, Difficulty::Hard);
+
+        osm.difficulty = Difficulty::Moderate;
+        assert!(!merge_duplicate(&osm, &doc, DEFAULT_PROVIDER_PRIORITY).difficulty_conflict);
+    }
+
+    #[test]
+    fn merged_duplicate_keeps_dog_notes_with_their_policy() {
+        let trails = sample_trails();
+        let mut doc = trails[0].clone();
+        doc.provider = Provider::DOC;
+        doc.dog_policy = DogPolicy::Unknown;
+        doc.dog_notes = Some("???".to_string());
+        let mut osm = trails[1].clone();
+        osm.provider = Provider::OpenStreetMap;
+        osm.dog_policy = DogPolicy::Partial;
+        osm.dog_notes = Some("On lead near the car park".to_string());
+
+        let merged = merge_duplicate(&osm, &doc, DEFAULT_PROVIDER_PRIORITY);
+        assert_eq!(merged.dog_policy, DogPolicy::Partial);
+        assert_eq!(merged.dog_notes.as_deref(), Some("On lead near the car park"));
+    }
+
+    #[test]
+    fn merged_duplicate_keeps_distance_with_its_source() {
+        let trails = sample_trails();
+        let mut doc = trails[0].clone();
+        doc.provider = Provider::DOC;
+        doc.distance_km = 0.0;
+        doc.distance_source = DistanceSource::Unknown;
+        doc.region = None;
+        let mut osm = trails[1].clone();
+        osm.provider = Provider::OpenStreetMap;
+        osm.distance_km = 4.2;
+        osm.distance_source = DistanceSource::ComputedFromGeometry;
+        osm.region = Some("Canterbury".to_string());
+
+        let merged = merge_duplicate(&osm, &doc, DEFAULT_PROVIDER_PRIORITY);
+        assert_eq!(merged.distance_km, 4.2);
+        assert_eq!(merged.distance_source, DistanceSource::ComputedFromGeometry);
+        assert_eq!(merged.region.as_deref(), Some("Canterbury"));
+    }
+
+    #[test]
+    fn dedupes_same_trail_across_providers() {
+        let template = sample_trails().remove(0);
+        let doc = Trail {
+            id: "doc-1".to_string(),
+            name: "Ōtari Loop Track".to_string(),
+            provider: Provider::DOC,
+            surface: "Unknown".to_string(),
+            difficulty: Difficulty::Hard,
+            lat: Some(-41.267),
+            lon: Some(174.753),
+            ..template.clone()
+        };
+        let osm = Trail {
+            id: "osm-1".to_string(),
+            name: "Otari loop".to_string(),
+            provider: Provider::OpenStreetMap,
+            surface: "gravel".to_string(),
+            difficulty: Difficulty::Easy,
+            lat: Some(-41.268),
+            lon: Some(174.754),
+            ..template.clone()
+        };
+        let far = Trail { id: "osm-2".to_string(), lat: Some(-43.5), lon: Some(172.6), ..osm.clone() };
+        let unnamed = Trail { id: "osm-3".to_string(), name_generated: true, ..osm.clone() };
+
+        let deduped = dedupe_trails(vec![osm, doc, far, unnamed], DEFAULT_PROVIDER_PRIORITY);
+        let ids: Vec<&str> = deduped.iter().map(|trail| trail.id.as_str()).collect();
+        assert_eq!(ids, vec!["doc-1", "osm-2", "osm-3"]);
+        assert_eq!(deduped[0].surface, "gravel");
+        assert!(deduped[0].difficulty_conflict);
+    }
+
+    #[test]
+    fn merges_segment_surfaces_by_distance() {
+        let segments = vec![
+            ("grass".to_string(), 1.0),
+            ("gravel".to_string(), 4.0),
+            ("grass".to_string(), 2.0),
+            ("gravel".to_string(), 3.0),
+        ];
+        let surfaces = merge_surfaces(&segments);
+        assert_eq!(surfaces, vec![("gravel".to_string(), 0.7), ("grass".to_string(), 0.3)]);
+        let mut trail = sample_trails().remove(0);
+        assert_eq!(describe_surfaces(&trail), trail.surface);
+        trail.surfaces = surfaces;
+        assert_eq!(describe_surfaces(&trail), "70% gravel, 30% grass");
+    }
+
+    /// Serve `router` on an ephemeral local port, returning its base URL.
+    async fn serve_mock(router: axum::Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        format!("http://{address}")
+    }
+
+    #[tokio::test]
+    async fn doc_tracks_all_returns_every_track_unfiltered() {
+        let tracks = serde_json::json!([
+            { "assetId": "doc-1", "name": "Port Hills", "x": 172.6, "y": -43.5 },
+            { "assetId": "doc-2", "name": "Tongariro", "x": 175.6, "y": -39.1 },
+        ]);
+        let router = axum::Router::new().route(
+            "/v1/tracks",
+            axum::routing::get(move || async move { axum::Json(tracks) }),
+        );
+        let base_url = serve_mock(router).await;
+        let service = TrailService::new(Vec::new(), "test-key".to_string())
+            .unwrap()
+            .with_doc_base_url(base_url);
+
+        let trails = service.doc_tracks_all().await.unwrap();
+        let ids: Vec<&str> = trails.iter().map(|trail| trail.id.as_str()).collect();
+        assert_eq!(ids, vec!["doc-1", "doc-2"]);
+    }
+
+    #[tokio::test]
+    async fn caches_doc_lines_already_simplified() {
+        let line: Vec<[f64; 2]> = (0..200).map(|index| [172.6 + index as f64 * 0.001, -43.5 + (index as f64).sin() * 0.001]).collect();
+        let tracks = serde_json::json!([{ "assetId": "doc-1", "name": "Port Hills", "line": [line] }]);
+        let router = axum::Router::new().route(
+            "/v1/tracks",
+            axum::routing::get(move || async move { axum::Json(tracks) }),
+        );
+        let base_url = serve_mock(router).await;
+        let service = TrailService::new(Vec::new(), "test-key".to_string())
+            .unwrap()
+            .with_doc_base_url(base_url)
+            .with_max_line_points(20);
+
+        let trails = service.doc_tracks_all().await.unwrap();
+        let points: usize = trails[0].line.iter().map(Vec::len).sum();
+        assert!((2..=20).contains(&points), "got {points} points");
+    }
+
+    #[tokio::test]
+    async fn unexpected_doc_list_shape_is_not_cached() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let router = axum::Router::new().route(
+            "/v1/tracks",
+            axum::routing::get(move || async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                axum::Json(serde_json::json!({ "data": [{ "assetId": "doc-1" }] }))
+            }),
+        );
+        let base_url = serve_mock(router).await;
+        let service = TrailService::new(Vec::new(), "test-key".to_string())
+            .unwrap()
+            .with_doc_base_url(base_url);
+
+        assert!(service.doc_tracks_all().await.unwrap().is_empty());
+        assert!(service.doc_summary_cache.read().await.is_none());
+        assert!(service.doc_tracks_all().await.unwrap().is_empty());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_doc_list_until_it_succeeds() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let router = axum::Router::new().route(
+            "/v1/tracks",
+            axum::routing::get(move || async move {
+                if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                    return Err(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+                }
+                Ok(axum::Json(serde_json::json!([
+                    { "assetId": "doc-1", "name": "Port Hills", "x": 172.6, "y": -43.5 },
+                ])))
+            }),
+        );
+        let base_url = serve_mock(router).await;
+        let service = TrailService::new(Vec::new(), "test-key".to_string())
+            .unwrap()
+            .with_doc_base_url(base_url.clone())
+            .with_doc_list_retries(2)
+            .with_doc_list_backoff(Duration::from_millis(10));
+
+        let trails = service.doc_tracks_all().await.unwrap();
+        assert_eq!(trails.len(), 1);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        requests.store(0, Ordering::SeqCst);
+        let impatient = TrailService::new(Vec::new(), "test-key".to_string())
+            .unwrap()
+            .with_doc_base_url(base_url)
+            .with_doc_list_retries(1)
+            .with_doc_list_backoff(Duration::from_millis(10));
+        assert!(matches!(impatient.doc_tracks_all().await, Err(TrailError::Upstream(_))));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn slow_overpass_reports_the_timeout() {
+        let router = axum::Router::new().route(
+            "/api/interpreter",
+            axum::routing::get(|| async {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                axum::Json(serde_json::json!({ "elements": [] }))
+            }),
+        );
+        let base_url = serve_mock(router).await;
+        let service = TrailService::new(vec![format!("{base_url}/api/interpreter")], String::new())
+            .unwrap()
+            .with_request_timeout(Duration::from_millis(200));
+
+        let err = service.fetch_trails(&TrailQuery::default()).await.err().unwrap();
+        assert!(matches!(err, TrailError::Network(_)));
+        assert_eq!(err.to_string(), "overpass request timed out after 200ms");
+    }
+
+    /// A DOC mock whose track list announces each request on the returned channel.
+    async fn doc_list_mock() -> (String, tokio::sync::mpsc::UnboundedReceiver<()>) {
+        let (arrived_tx, arrived) = tokio::sync::mpsc::unbounded_channel::<()>();
+        let router = axum::Router::new().route(
+            "/v1/tracks",
+            axum::routing::get(move || {
+                let arrived_tx = arrived_tx.clone();
+                async move {
+                    arrived_tx.send(()).unwrap();
+                    axum::Json(serde_json::json!([{ "assetId": "doc-1", "name": "Port Hills", "x": 172.6, "y": -43.5 }]))
+                }
+            }),
+        );
+        (serve_mock(router).await, arrived)
+    }
+
+    #[tokio::test]
+    async fn doc_refresher_renews_the_cache_before_it_expires() {
+        let (base_url, mut arrived) = doc_list_mock().await;
+        let ttl = Duration::from_millis(400);
+        let service = Arc::new(
+            TrailService::new(Vec::new(), "test-key".to_string())
+                .unwrap()
+                .with_doc_base_url(base_url)
+                .with_doc_summary_ttl(ttl),
+        );
+
+        let refresher = tokio::spawn({
+            let service = service.clone();
+            async move { service.run_doc_refresher(ttl / 2).await }
+        });
+        // The cold fill, then the renewal.
+        for _ in 0..2 {
+            tokio::time::timeout(Duration::from_secs(5), arrived.recv()).await.unwrap().unwrap();
+        }
+        refresher.abort();
+
+        let age = service.doc_summary_cache.read().await.as_ref().unwrap().fetched_at.elapsed();
+        assert!(age < ttl, "cache aged {age:?}");
+    }
+
+    #[tokio::test]
+    async fn doc_refresher_caps_its_lead_at_half_the_ttl() {
+        let (base_url, mut arrived) = doc_list_mock().await;
+        let ttl = Duration::from_millis(400);
+        let service = Arc::new(
+            TrailService::new(Vec::new(), "test-key".to_string())
+                .unwrap()
+                .with_doc_base_url(base_url)
+                .with_doc_summary_ttl(ttl),
+        );
+
+        // A lead past the whole TTL would otherwise refetch back-to-back.
+        let refresher = tokio::spawn({
+            let service = service.clone();
+            async move { service.run_doc_refresher(ttl * 2).await }
+        });
+        tokio::time::timeout(Duration::from_secs(5), arrived.recv()).await.unwrap().unwrap();
+        let filled = Instant::now();
+        tokio::time::timeout(Duration::from_secs(5), arrived.recv()).await.unwrap().unwrap();
+        refresher.abort();
+
+        assert!(filled.elapsed() >= ttl / 4, "renewed after {:?}", filled.elapsed());
+    }
+
+    #[tokio::test]
+    async fn lazy_mode_fetches_detail_only_on_demand() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let detail_requests = Arc::new(AtomicUsize::new(0));
+        let counter = detail_requests.clone();
+        let router = axum::Router::new()
+            .route(
+                "/v1/tracks",
+                axum::routing::get(|| async {
+                    axum::Json(serde_json::json!([
+                        { "assetId": "doc-1", "name": "Port Hills", "x": 172.62, "y": -43.55 },
+                        { "assetId": "doc-2", "name": "Bottle Lake", "x": 172.70, "y": -43.47 },
+                    ]))
+                }),
+            )
+            .route(
+                "/v1/tracks/:id/detail",
+                axum::routing::get(move || async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    axum::Json(serde_json::json!({ "line": [[[172.62, -43.55], [172.63, -43.56]]] }))
+                }),
+            );
+        let base_url = serve_mock(router).await;
+        let service = TrailService::new(Vec::new(), "test-key".to_string())
+            .unwrap()
+            .with_doc_base_url(base_url)
+            .with_lazy_doc_details(true);
+
+        let trails = service.fetch_trails(&TrailQuery::default()).await.unwrap();
+        assert_eq!(trails.len(), 2);
+        assert_eq!(detail_requests.load(Ordering::SeqCst), 0);
+
+        let trail = service.trail("doc-1").await.unwrap().unwrap();
+        assert_eq!(trail.id, "doc-1");
+        service.trail("doc-1").await.unwrap();
+        assert_eq!(detail_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn slim_doc_cache_drops_lines_but_keeps_bbox_filtering() {
+        let router = axum::Router::new()
+            .route(
+                "/v1/tracks",
+                axum::routing::get(|| async {
+                    axum::Json(serde_json::json!([
+                        { "assetId": "doc-1", "name": "Port Hills", "x": 172.62, "y": -43.55,
+                          "line": [[[172.62, -43.55], [172.63, -43.56]]] },
+                        { "assetId": "doc-2", "name": "Tongariro", "x": 175.6, "y": -39.1,
+                          "line": [[[175.6, -39.1], [175.61, -39.11]]] },
+                    ]))
+                }),
+            )
+            .route(
+                "/v1/tracks/:id/detail",
+                axum::routing::get(|| async {
+                    axum::Json(serde_json::json!({ "line": [[[172.62, -43.55], [172.63, -43.56]]] }))
+                }),
+            );
+        let base_url = serve_mock(router).await;
+        let service = TrailService::new(Vec::new(), "test-key".to_string())
+            .unwrap()
+            .with_doc_base_url(base_url)
+            .with_lazy_doc_details(true)
+            .with_slim_doc_cache(true);
+
+        let cached = service.doc_tracks_all().await.unwrap();
+        assert_eq!(cached.len(), 2);
+        assert!(cached.iter().all(|trail| trail.line.is_empty()));
+
+        let query = TrailQuery {
+            min_lat: Some(-44.0),
+            min_lon: Some(172.0),
+            max_lat: Some(-43.0),
+            max_lon: Some(173.0),
+            ..TrailQuery::default()
+        };
+        let visible = service.fetch_trails(&query).await.unwrap();
+        let ids: Vec<&str> = visible.iter().map(|trail| trail.id.as_str()).collect();
+        assert_eq!(ids, vec!["doc-1"]);
+
+        // Compare and profiles go through find_by_id, which fetches the missing line.
+        let found = service.find_by_id("doc-1").await.unwrap();
+        assert_eq!(found.line, vec![vec![[-43.55, 172.62], [-43.56, 172.63]]]);
+
+        let trail = service.trail("doc-1").await.unwrap().unwrap();
+        assert_eq!(trail.line, vec![vec![[-43.55, 172.62], [-43.56, 172.63]]]);
+    }
+
+    #[tokio::test]
+    async fn tries_fastest_mirror_first_after_probe() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        async fn mirror(delay: Duration, hits: Arc<AtomicUsize>) -> String {
+            let router = axum::Router::new().route(
+                "/api/interpreter",
+                axum::routing::get(move || async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(delay).await;
+                    axum::Json(serde_json::json!({ "elements": [] }))
+                }),
+            );
+            format!("{}/api/interpreter", serve_mock(router).await)
+        }
+        let slow_hits = Arc::new(AtomicUsize::new(0));
+        let fast_hits = Arc::new(AtomicUsize::new(0));
+        let slow = mirror(Duration::from_millis(150), slow_hits.clone()).await;
+        let fast = mirror(Duration::ZERO, fast_hits.clone()).await;
+        let service = TrailService::new(vec![slow.clone(), fast.clone()], String::new()).unwrap();
+        assert_eq!(service.ranked_overpass_urls().await, vec![slow.clone(), fast.clone()]);
+
+        service.probe_overpass_mirrors().await;
+        assert_eq!(service.ranked_overpass_urls().await, vec![fast, slow]);
+
+        service.fetch_trails(&TrailQuery::default()).await.unwrap();
+        assert_eq!(slow_hits.load(Ordering::SeqCst), 1);
+        assert_eq!(fast_hits.load(Ordering::SeqCst), 2);
     }
-}
 
-fn derive_distance_range(query: &TrailQuery) -> (Option<f32>, Option<f32>, Option<f32>) {
-    let min_km = query.min_km;
-    let max_km = query.max_km;
-    if min_km.is_some() || max_km.is_some() {
-        let target = min_km
-            .zip(max_km)
-            .map(|(min, max)| (min + max) / 2.0);
-        return (min_km, max_km, target);
+    #[tokio::test]
+    async fn falls_through_html_overpass_answers_to_the_next_mirror() {
+        let router = axum::Router::new()
+            .route(
+                "/busy/api/interpreter",
+                axum::routing::get(|| async {
+                    axum::response::Html("<html><body>Too many requests, try again later</body></html>")
+                }),
+            )
+            .route(
+                "/ok/api/interpreter",
+                axum::routing::get(|| async { axum::Json(serde_json::json!({ "elements": [] })) }),
+            );
+        let base_url = serve_mock(router).await;
+        let busy = format!("{base_url}/busy/api/interpreter");
+        let ok = format!("{base_url}/ok/api/interpreter");
+        let service = TrailService::new(vec![busy.clone()], String::new()).unwrap();
+        let client = reqwest::Client::new();
+
+        let result = overpass::fetch_overpass_with_fallback(&client, std::slice::from_ref(&busy), Bbox::default(), service.overpass_options()).await;
+        assert!(matches!(result, Err(TrailError::Upstream(ref message)) if message == "overpass returned non-JSON, likely overloaded"));
+
+        let trails = overpass::fetch_overpass_with_fallback(&client, &[busy, ok], Bbox::default(), service.overpass_options())
+            .await
+            .unwrap();
+        assert!(trails.is_empty());
     }
 
-    match query.length.clone().unwrap_or(Length::Medium) {
-        Length::Short => (Some(2.0), Some(6.0), Some(4.0)),
-        Length::Medium => (Some(6.0), Some(12.0), Some(9.0)),
-        Length::Long => (Some(12.0), Some(24.0), Some(16.0)),
+    #[tokio::test]
+    async fn posts_added_and_removed_ids_when_a_refresh_changes_the_cache() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let way = |id: u64| {
+            serde_json::json!({
+                "type": "way",
+                "id": id,
+                "tags": { "name": format!("Path {id}"), "highway": "path", "dog": "yes" },
+                "center": { "lat": -43.5, "lon": 172.6 }
+            })
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (sender, mut received) = tokio::sync::mpsc::unbounded_channel::<Value>();
+        let router = axum::Router::new()
+            .route(
+                "/api/interpreter",
+                axum::routing::get(move || async move {
+                    let ids: &[u64] = if calls.fetch_add(1, Ordering::SeqCst) == 0 { &[1, 2] } else { &[2, 3] };
+                    axum::Json(serde_json::json!({ "elements": ids.iter().map(|id| way(*id)).collect::<Vec<_>>() }))
+                }),
+            )
+            .route(
+                "/hook",
+                axum::routing::post(move |axum::Json(body): axum::Json<Value>| async move {
+                    sender.send(body).unwrap();
+                }),
+            );
+        let base_url = serve_mock(router).await;
+        let service = TrailService::new(vec![format!("{base_url}/api/interpreter")], String::new())
+            .unwrap()
+            .with_change_webhook(format!("{base_url}/hook"));
+
+        service.fetch_overpass_cached(Bbox::default()).await.unwrap();
+        if let Some(cached) = service.overpass_cache.write().await.as_mut() {
+            cached.fetched_at = Instant::now() - OVERPASS_TTL - service.overpass_stale_grace - Duration::from_secs(1);
+        }
+        service.fetch_overpass_cached(Bbox::default()).await.unwrap();
+
+        let body = tokio::time::timeout(Duration::from_secs(5), received.recv()).await.unwrap().unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "provider": "OpenStreetMap", "added": ["osm-3"], "removed": ["osm-1"] })
+        );
+        // The first fill had nothing to compare against.
+        assert!(received.try_recv().is_err());
     }
-}
 
-fn within_distance(distance_km: f32, range: &(Option<f32>, Option<f32>, Option<f32>)) -> bool {
-    if distance_km == 0.0 {
-        return true;
+    #[tokio::test]
+    async fn looks_up_single_trails_by_provider_id() {
+        let router = axum::Router::new()
+            .route(
+                "/api/interpreter",
+                axum::routing::get(|axum::extract::Query(params): axum::extract::Query<HashMap<String, String>>| async move {
+                    let elements = if params["data"].contains("way(id:12345)") {
+                        serde_json::json!([{
+                            "type": "way",
+                            "id": 12345,
+                            "tags": { "highway": "path", "dog": "leashed", "surface": "gravel" },
+                            "geometry": [{ "lat": -43.50, "lon": 172.60 }, { "lat": -43.51, "lon": 172.61 }]
+                        }])
+                    } else if params["data"].contains("way(id:777)") {
+                        serde_json::json!([{
+                            "type": "way",
+                            "id": 777,
+                            "tags": { "highway": "path", "name": "Sanctuary Track" },
+                            "geometry": [{ "lat": -43.50, "lon": 172.60 }, { "lat": -43.51, "lon": 172.61 }]
+                        }])
+                    } else {
+                        serde_json::json!([])
+                    };
+                    axum::Json(serde_json::json!({ "elements": elements }))
+                }),
+            )
+            .route(
+                "/v1/tracks/:id/detail",
+                axum::routing::get(|axum::extract::Path(id): axum::extract::Path<String>| async move {
+                    if id == "doc-7" {
+                        Ok(axum::Json(serde_json::json!({ "name": "Hooker Valley Track", "distance": "5 km", "x": 170.1, "y": -43.7 })))
+                    } else {
+                        Err(axum::http::StatusCode::NOT_FOUND)
+                    }
+                }),
+            );
+        let base_url = serve_mock(router).await;
+        let service = TrailService::new(vec![format!("{base_url}/api/interpreter")], "test-key".to_string())
+            .unwrap()
+            .with_doc_base_url(base_url);
+
+        let way = service.lookup(&Provider::OpenStreetMap, "12345").await.unwrap().unwrap();
+        assert_eq!(way.id, "osm-12345");
+        assert_eq!(way.surface, "gravel");
+        assert!(!way.slug.is_empty());
+        assert_eq!(service.lookup(&Provider::OpenStreetMap, "osm-12345").await.unwrap().unwrap().id, "osm-12345");
+        assert!(service.lookup(&Provider::OpenStreetMap, "999").await.unwrap().is_none());
+        assert!(matches!(service.lookup(&Provider::OpenStreetMap, "abc").await, Err(TrailError::InvalidQuery(_))));
+        // Looked up by id, a way without dog access is still the answer.
+        let closed = service.lookup(&Provider::OpenStreetMap, "777").await.unwrap().unwrap();
+        assert!(closed.dog_policy == DogPolicy::NotAllowed);
+
+        let track = service.lookup(&Provider::DOC, "doc-7").await.unwrap().unwrap();
+        assert_eq!(track.id, "doc-7");
+        assert_eq!(track.name, "Hooker Valley Track");
+        assert_eq!(track.distance_km, 5.0);
+        assert!(service.lookup(&Provider::DOC, "doc-8").await.unwrap().is_none());
+        // The ref is one path segment, so it can't walk to another track.
+        assert!(service.lookup(&Provider::DOC, "x/../doc-7").await.unwrap().is_none());
     }
-    let (min_km, max_km, _) = range;
-    if let Some(min) = min_km {
-        if distance_km < *min {
-            return false;
+
+    #[tokio::test]
+    async fn debug_attaches_raw_tags_only_when_enabled() {
+        let router = axum::Router::new().route(
+            "/api/interpreter",
+            axum::routing::get(|| async {
+                axum::Json(serde_json::json!({ "elements": [{
+                    "type": "way",
+                    "id": 7,
+                    "tags": { "name": "Bridle Path", "highway": "path", "dog": "yes" },
+                    "geometry": [{ "lat": -43.55, "lon": 172.70 }, { "lat": -43.56, "lon": 172.71 }]
+                }] }))
+            }),
+        );
+        let mirror = format!("{}/api/interpreter", serve_mock(router).await);
+        let debug = TrailQuery { debug: Some(true), ..TrailQuery::default() };
+
+        let service = TrailService::new(vec![mirror.clone()], String::new()).unwrap().with_raw_debug(true);
+        let trails = service.fetch_trails(&debug).await.unwrap();
+        let shown = service.apply_debug(trails.clone(), &debug);
+        assert_eq!(shown[0].raw.as_ref().unwrap()["highway"], "path");
+        assert!(service.apply_debug(trails, &TrailQuery::default())[0].raw.is_none());
+
+        let service = TrailService::new(vec![mirror], String::new()).unwrap();
+        let trails = service.fetch_trails(&debug).await.unwrap();
+        assert!(service.apply_debug(trails, &debug)[0].raw.is_none());
+    }
+
+    #[tokio::test]
+    async fn serves_stale_overpass_cache_and_refreshes_in_background() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        // Each request reports in and then waits at the gate until the test lets it through.
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let gate_in = gate.clone();
+        let (arrived_tx, mut arrived) = tokio::sync::mpsc::unbounded_channel::<()>();
+        let router = axum::Router::new().route(
+            "/api/interpreter",
+            axum::routing::get(move || async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                arrived_tx.send(()).unwrap();
+                gate_in.acquire().await.unwrap().forget();
+                axum::Json(serde_json::json!({ "elements": [] }))
+            }),
+        );
+        let mirror = format!("{}/api/interpreter", serve_mock(router).await);
+        let service = TrailService::new(vec![mirror], String::new())
+            .unwrap()
+            .with_overpass_stale_grace(Duration::from_secs(60));
+        // Backdate the entry so it is 30 s past TTL, inside the grace window.
+        *service.overpass_cache.write().await = Some(OverpassCacheEntry {
+            fetched_at: Instant::now() - OVERPASS_TTL - Duration::from_secs(30),
+            bbox: Bbox::default(),
+            trails: vec![sample_trails().remove(0)],
+        });
+
+        let (trails, info) = service.fetch_trails_with_cache_info(&TrailQuery::default()).await.unwrap();
+        assert_eq!(trails.len(), 1);
+        let info = info.unwrap();
+        assert_eq!(info.status, CacheStatus::Stale);
+        assert!(info.age_secs >= 630);
+        arrived.recv().await.unwrap();
+
+        // While that refresh is in flight, the same box is served stale without
+        // a second refresh, and another box is fetched rather than given this one.
+        let (_, info) = service.fetch_trails_with_cache_info(&TrailQuery::default()).await.unwrap();
+        assert_eq!(info.unwrap().status, CacheStatus::Stale);
+        let elsewhere = TrailQuery {
+            min_lat: Some(-41.4),
+            min_lon: Some(174.7),
+            max_lat: Some(-41.2),
+            max_lon: Some(174.9),
+            ..TrailQuery::default()
+        };
+        let release = async {
+            arrived.recv().await.unwrap();
+            gate.add_permits(2);
+        };
+        let (fetched, ()) = tokio::join!(service.fetch_trails_with_cache_info(&elsewhere), release);
+        let (trails, info) = fetched.unwrap();
+        assert!(trails.is_empty());
+        assert_eq!(info.unwrap().status, CacheStatus::Miss);
+
+        while !service.overpass_refreshing.read().await.is_empty() {
+            tokio::task::yield_now().await;
         }
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
     }
-    if let Some(max) = max_km {
-        if distance_km > *max {
-            return false;
+
+    #[tokio::test]
+    async fn caps_outbound_requests_across_providers() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Clone)]
+        struct InFlight {
+            current: Arc<AtomicUsize>,
+            max: Arc<AtomicUsize>,
+            total: Arc<AtomicUsize>,
+            arrived: tokio::sync::mpsc::UnboundedSender<()>,
+            gate: Arc<tokio::sync::Semaphore>,
+        }
+        impl InFlight {
+            // Report in, then wait at the gate until the test lets the request through.
+            async fn track(&self) {
+                let now = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max.fetch_max(now, Ordering::SeqCst);
+                self.total.fetch_add(1, Ordering::SeqCst);
+                self.arrived.send(()).unwrap();
+                self.gate.acquire().await.unwrap().forget();
+                self.current.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+
+        let (arrived_tx, mut arrived) = tokio::sync::mpsc::unbounded_channel::<()>();
+        let in_flight = InFlight {
+            current: Arc::new(AtomicUsize::new(0)),
+            max: Arc::new(AtomicUsize::new(0)),
+            total: Arc::new(AtomicUsize::new(0)),
+            arrived: arrived_tx,
+            gate: Arc::new(tokio::sync::Semaphore::new(0)),
+        };
+        let tracks: Vec<Value> = (0..2)
+            .map(|index| serde_json::json!({ "assetId": format!("doc-{index}"), "name": "Track", "x": 172.6, "y": -43.5 }))
+            .collect();
+        let (overpass_flight, detail_flight) = (in_flight.clone(), in_flight.clone());
+        let router = axum::Router::new()
+            .route(
+                "/api/interpreter",
+                axum::routing::get(move || async move {
+                    overpass_flight.track().await;
+                    axum::Json(serde_json::json!({ "elements": [] }))
+                }),
+            )
+            .route("/v1/tracks", axum::routing::get(move || async move { axum::Json(tracks) }))
+            .route(
+                "/v1/tracks/:id/detail",
+                axum::routing::get(move || async move {
+                    detail_flight.track().await;
+                    axum::Json(serde_json::json!({}))
+                }),
+            );
+        let base_url = serve_mock(router).await;
+        let service = TrailService::new(vec![format!("{base_url}/api/interpreter")], "test-key".to_string())
+            .unwrap()
+            .with_doc_base_url(base_url)
+            .with_max_outbound_requests(2);
+        service.doc_tracks_all().await.unwrap();
+
+        // A probe, an Overpass fetch and two DOC details, each ready at once.
+        let release = async {
+            for _ in 0..2 {
+                arrived.recv().await.unwrap();
+            }
+            // Each request let through makes room for the next.
+            for _ in 0..2 {
+                in_flight.gate.add_permits(1);
+                arrived.recv().await.unwrap();
+            }
+            in_flight.gate.add_permits(2);
+        };
+        let (_, fetched, first, second, ()) = tokio::join!(
+            service.probe_overpass_mirrors(),
+            service.fetch_overpass_cached(Bbox::default()),
+            service.trail("doc-0"),
+            service.trail("doc-1"),
+            release,
+        );
+        assert!(fetched.is_ok() && first.unwrap().is_some() && second.unwrap().is_some());
+        assert_eq!(in_flight.total.load(Ordering::SeqCst), 4);
+        assert_eq!(in_flight.max.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn fetches_overpass_tiles_with_bounded_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (current, max, total) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let (current_in, max_in, total_in) = (current.clone(), max.clone(), total.clone());
+        // Each tile request reports in and then waits at the gate until the test lets it through.
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let gate_in = gate.clone();
+        let (arrived_tx, mut arrived) = tokio::sync::mpsc::unbounded_channel::<()>();
+        let router = axum::Router::new().route(
+            "/api/interpreter",
+            axum::routing::get(move || async move {
+                let now = current_in.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in.fetch_max(now, Ordering::SeqCst);
+                total_in.fetch_add(1, Ordering::SeqCst);
+                arrived_tx.send(()).unwrap();
+                gate_in.acquire().await.unwrap().forget();
+                current_in.fetch_sub(1, Ordering::SeqCst);
+                // Every tile returns the same way, as an edge-crossing way would.
+                axum::Json(serde_json::json!({ "elements": [{
+                    "type": "way",
+                    "id": 7,
+                    "tags": { "name": "Bottle Lake Loop", "highway": "path", "dog": "yes" },
+                    "geometry": [{ "lat": -43.50, "lon": 172.60 }, { "lat": -43.50, "lon": 172.65 }]
+                }] }))
+            }),
+        );
+        let mirror = format!("{}/api/interpreter", serve_mock(router).await);
+        let service = TrailService::new(vec![mirror], String::new())
+            .unwrap()
+            .with_overpass_tiles(0.1)
+            .with_tile_concurrency(2);
+        assert_eq!(Bbox::default().split(0.1).len(), 6);
+
+        let release = async {
+            // Two tiles in flight; each one let through makes room for the next.
+            for _ in 0..2 {
+                arrived.recv().await.unwrap();
+            }
+            for _ in 0..4 {
+                gate.add_permits(1);
+                arrived.recv().await.unwrap();
+            }
+            gate.add_permits(2);
+        };
+        let (fetched, ()) = tokio::join!(service.fetch_overpass_cached(Bbox::default()), release);
+        assert_eq!(fetched.unwrap().0.len(), 1);
+        assert_eq!(total.load(Ordering::SeqCst), 6);
+        assert_eq!(max.load(Ordering::SeqCst), 2);
+
+        // All of New Zealand in 0.1° tiles would be thousands of queries.
+        let country = Bbox { min_lat: -47.5, min_lon: 166.0, max_lat: -34.0, max_lon: 179.0 };
+        assert!(service.overpass_tiles(country).len() <= MAX_OVERPASS_TILES);
+    }
+
+    #[tokio::test]
+    async fn skips_detail_for_tracks_that_keep_failing() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let tracks = serde_json::json!([
+            { "assetId": "doc-broken", "name": "Broken Track", "x": 172.6, "y": -43.5 },
+            { "assetId": "doc-fine", "name": "Fine Track", "x": 172.6, "y": -43.5 }
+        ]);
+        let router = axum::Router::new()
+            .route("/v1/tracks", axum::routing::get(move || async move { axum::Json(tracks) }))
+            .route(
+                "/v1/tracks/:id/detail",
+                axum::routing::get(move |axum::extract::Path(id): axum::extract::Path<String>| async move {
+                    if id == "doc-broken" {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR)
+                    } else {
+                        Ok(axum::Json(serde_json::json!({})))
+                    }
+                }),
+            );
+        let base_url = serve_mock(router).await;
+        let service = TrailService::new(Vec::new(), "test-key".to_string())
+            .unwrap()
+            .with_doc_base_url(base_url)
+            .with_doc_detail_failure_limit(2)
+            .with_doc_detail_cooldown(Duration::from_secs(60));
+
+        for _ in 0..4 {
+            assert_eq!(service.fetch_trails(&TrailQuery::default()).await.unwrap().len(), 2);
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        // Once the cooldown has passed the track gets one more try.
+        if let Some(failure) = service.doc_detail_failures.write().await.get_mut("doc-broken") {
+            failure.1 = Instant::now() - Duration::from_secs(61);
         }
+        service.fetch_trails(&TrailQuery::default()).await.unwrap();
+        service.fetch_trails(&TrailQuery::default()).await.unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        // A stale failure is dropped the next time any failure is recorded.
+        service
+            .doc_detail_failures
+            .write()
+            .await
+            .insert("doc-gone".to_string(), (1, Instant::now() - Duration::from_secs(61)));
+        if let Some(failure) = service.doc_detail_failures.write().await.get_mut("doc-broken") {
+            failure.1 = Instant::now() - Duration::from_secs(61);
+        }
+        service.fetch_trails(&TrailQuery::default()).await.unwrap();
+        let failures = service.doc_detail_failures.read().await;
+        assert!(!failures.contains_key("doc-gone"));
+        assert!(failures.contains_key("doc-broken"));
     }
-    true
-}
 
-fn score_trail(trail: &Trail, range: &(Option<f32>, Option<f32>, Option<f32>), effort: Option<&Effort>) -> f32 {
-    let target = range.2.unwrap_or(trail.distance_km);
-    let distance_penalty = (trail.distance_km - target).abs();
+    #[tokio::test]
+    async fn single_trail_falls_back_to_summary_when_detail_fails() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
 
-    let effort_penalty = match effort {
-        Some(Effort::Easy) => difficulty_penalty(&trail.difficulty, &Difficulty::Easy),
-        Some(Effort::Steady) => difficulty_penalty(&trail.difficulty, &Difficulty::Moderate),
-        Some(Effort::Hard) => difficulty_penalty(&trail.difficulty, &Difficulty::Hard),
-        None => 0.5,
-    };
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let tracks = serde_json::json!([
+            { "assetId": "doc-broken", "name": "Broken Track", "x": 172.6, "y": -43.5 }
+        ]);
+        let router = axum::Router::new()
+            .route("/v1/tracks", axum::routing::get(move || async move { axum::Json(tracks) }))
+            .route(
+                "/v1/tracks/:id/detail",
+                axum::routing::get(move || async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    axum::http::StatusCode::INTERNAL_SERVER_ERROR
+                }),
+            );
+        let base_url = serve_mock(router).await;
+        let service = TrailService::new(Vec::new(), "test-key".to_string())
+            .unwrap()
+            .with_doc_base_url(base_url)
+            .with_lazy_doc_details(true)
+            .with_doc_detail_failure_limit(2)
+            .with_doc_detail_cooldown(Duration::from_secs(60));
+        service.fetch_trails(&TrailQuery::default()).await.unwrap();
 
-    let elevation_penalty = trail.elevation_m.unwrap_or(0.0) / 600.0;
-    distance_penalty + effort_penalty * 2.0 + elevation_penalty
-}
+        for _ in 0..3 {
+            let trail = service.trail("doc-broken").await.unwrap().unwrap();
+            assert_eq!(trail.name, "Broken Track");
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
 
-fn difficulty_penalty(actual: &Difficulty, preferred: &Difficulty) -> f32 {
-    let actual_score = difficulty_rank(actual);
-    let preferred_score = difficulty_rank(preferred);
-    (actual_score - preferred_score).abs() as f32
-}
+    #[tokio::test]
+    async fn resolves_colliding_ids_to_doc_first() {
+        let service = TrailService::new(vec!["https://overpass.example".to_string()], "test-key".to_string()).unwrap();
+        let mut trails = sample_trails();
+        trails[1].id = "t1".to_string();
+        *service.overpass_cache.write().await = Some(OverpassCacheEntry {
+            fetched_at: Instant::now(),
+            bbox: Bbox::default(),
+            trails: trails.clone(),
+        });
+        assert_eq!(service.find_by_id("t1").await.unwrap().name, trails[0].name);
 
-fn difficulty_rank(difficulty: &Difficulty) -> i32 {
-    match difficulty {
-        Difficulty::Easy => 1,
-        Difficulty::Moderate => 2,
-        Difficulty::Hard => 3,
+        let mut doc_trail = trails[1].clone();
+        doc_trail.name = "DOC copy".to_string();
+        *service.doc_summary_cache.write().await = Some(DocSummaryCache {
+            fetched_at: Instant::now(),
+            trails: vec![doc_trail],
+        });
+        for _ in 0..3 {
+            assert_eq!(service.find_by_id("t1").await.unwrap().name, "DOC copy");
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[tokio::test]
+    async fn doc_tracks_all_requires_key() {
+        let service = TrailService::new(vec!["https://overpass.example".to_string()], String::new()).unwrap();
+        assert!(matches!(service.doc_tracks_all().await, Err(TrailError::Config(_))));
+    }
 
-    fn sample_trails() -> Vec<Trail> {
-        vec![
-            Trail {
-                id: "t1".to_string(),
-                name: "River Loop".to_string(),
-                provider: Provider::DOC,
-                location: "Wellington".to_string(),
-                distance_km: 5.0,
-                elevation_m: Some(120.0),
-                difficulty: Difficulty::Easy,
-                dog_policy: DogPolicy::Allowed,
-                dog_notes: None,
-                surface: "Gravel".to_string(),
-                map_url: "https://www.doc.govt.nz".to_string(),
-                lat: -41.3,
-                lon: 174.7,
-                line: Vec::new(),
-                line_bbox: Bbox { min_lat: -41.3, min_lon: 174.7, max_lat: -41.3, max_lon: 174.7 },
+    #[test]
+    fn simplifies_dense_line_under_cap() {
+        let line: Vec<[f64; 2]> = (0..5000)
+            .map(|index| {
+                let t = index as f64 / 100.0;
+                [-43.5 + t * 0.001, 172.6 + t.sin() * 0.01]
+            })
+            .collect();
+        let simplified = simplify_sections(std::slice::from_ref(&line), 500).remove(0);
+        assert!(simplified.len() <= 500, "got {} points", simplified.len());
+        assert!(simplified.len() > 2);
+        assert_eq!(simplified.first(), line.first());
+        assert_eq!(simplified.last(), line.last());
+    }
+
+    #[test]
+    fn keeps_sectioned_lines_within_the_point_budget() {
+        let wiggle = |offset: f64| -> Vec<[f64; 2]> {
+            (0..50).map(|index| [offset + index as f64 * 0.001, (index as f64).sin() * 0.01]).collect()
+        };
+        // Sixty sections of 50 points: an even split would give each fewer
+        // than its two endpoints.
+        let line: Vec<Vec<[f64; 2]>> = (0..60).map(|section| wiggle(section as f64)).collect();
+        let simplified = simplify_sections(&line, 100);
+        assert!(simplified.iter().map(Vec::len).sum::<usize>() <= 100);
+        assert_eq!(simplified.len(), 50);
+        assert!(simplified.iter().all(|section| section.len() >= 2));
+
+        let simplified = simplify_sections(&line[..2], 60);
+        assert!(simplified.iter().map(Vec::len).sum::<usize>() <= 60);
+        assert_eq!(simplified[0].first(), line[0].first());
+        assert_eq!(simplified[1].last(), line[1].last());
+    }
+
+    #[test]
+    fn leaves_short_lines_alone() {
+        let line = vec![vec![[0.0, 0.0], [0.5, 0.5], [1.0, 0.0]]];
+        assert_eq!(simplify_sections(&line, 500), line);
+    }
+
+    #[test]
+    fn classifies_region_from_coordinates() {
+        let service = TrailService::new(vec!["https://overpass.example".to_string()], String::new()).unwrap();
+        let mut trail = sample_trails().remove(0);
+        service.finish_trail(&mut trail);
+        assert_eq!(trail.region.as_deref(), Some("Wellington"));
+
+        let mut trail = sample_trails().remove(0);
+        trail.lat = None;
+        trail.lon = None;
+        service.finish_trail(&mut trail);
+        assert_eq!(trail.region, None);
+    }
+
+    #[test]
+    fn prefers_the_providers_region() {
+        let service = TrailService::new(vec!["https://overpass.example".to_string()], String::new()).unwrap();
+        let mut trail = sample_trails().remove(0);
+        trail.region = Some("Canterbury".to_string());
+        service.finish_trail(&mut trail);
+        assert_eq!(trail.region.as_deref(), Some("Canterbury"));
+    }
+
+    #[test]
+    fn dog_notes_dictionary_overrides_partial_note() {
+        let notes = DogNotes::new(vec![
+            DogNoteEntry {
+                policy: DogPolicy::Partial,
+                provider: None,
+                region: None,
+                note: "Dogs on lead.".to_string(),
             },
-            Trail {
-                id: "t2".to_string(),
-                name: "Forest Ridge".to_string(),
-                provider: Provider::OpenStreetMap,
-                location: "Auckland".to_string(),
-                distance_km: 12.0,
-                elevation_m: Some(520.0),
-                difficulty: Difficulty::Hard,
-                dog_policy: DogPolicy::NotAllowed,
-                dog_notes: Some("Dog-free section after 2km".to_string()),
-                surface: "Dirt".to_string(),
-                map_url: "https://www.openstreetmap.org/".to_string(),
-                lat: -36.8,
-                lon: 174.7,
-                line: Vec::new(),
-                line_bbox: Bbox { min_lat: -36.8, min_lon: 174.7, max_lat: -36.8, max_lon: 174.7 },
+            DogNoteEntry {
+                policy: DogPolicy::Partial,
+                provider: Some(Provider::DOC),
+                region: Some("Wellington".to_string()),
+                note: "Dogs on lead Sep–Dec for nesting birds in this reserve.".to_string(),
             },
-        ]
+        ]);
+        let service = TrailService::new(vec!["https://overpass.example".to_string()], String::new())
+            .unwrap()
+            .with_dog_notes(notes);
+
+        let mut trail = sample_trails().remove(0);
+        trail.dog_policy = DogPolicy::Partial;
+        trail.dog_notes = Some("Dogs must be on a lead.".to_string());
+        service.finish_trail(&mut trail);
+        assert_eq!(
+            trail.dog_notes.as_deref(),
+            Some("Dogs on lead Sep–Dec for nesting birds in this reserve.")
+        );
+
+        let mut allowed = sample_trails().remove(0);
+        service.finish_trail(&mut allowed);
+        assert_eq!(allowed.dog_notes, None);
     }
 
     #[test]
-    fn filters_dog_allowed_by_default() {
-        let trails = sample_trails();
+    fn cautions_hard_trails_that_allow_dogs() {
+        let service = TrailService::new(vec!["https://overpass.example".to_string()], String::new()).unwrap();
+        let mut hard = sample_trails().remove(0);
+        hard.difficulty = Difficulty::Hard;
+        service.finish_trail(&mut hard);
+        assert_eq!(hard.dog_notes.as_deref(), Some(HARD_TERRAIN_CAUTION));
+
+        let mut easy = sample_trails().remove(0);
+        easy.difficulty = Difficulty::Easy;
+        service.finish_trail(&mut easy);
+        assert_eq!(easy.dog_notes, None);
+
+        let service = service.with_hard_terrain_caution(false);
+        let mut hard = sample_trails().remove(0);
+        hard.difficulty = Difficulty::Hard;
+        service.finish_trail(&mut hard);
+        assert_eq!(hard.dog_notes, None);
+    }
+
+    #[tokio::test]
+    async fn elevation_profile_follows_polyline_length() {
+        let router = axum::Router::new().route(
+            "/api/v1/lookup",
+            axum::routing::get(|query: axum::extract::Query<HashMap<String, String>>| async move {
+                let count = query["locations"].split('|').count();
+                let results: Vec<Value> = (0..count)
+                    .map(|index| serde_json::json!({ "elevation": 100.0 + index as f32 * 10.0 }))
+                    .collect();
+                axum::Json(serde_json::json!({ "results": results }))
+            }),
+        );
+        let dem_url = serve_mock(router).await;
+        let service = TrailService::new(vec!["https://overpass.example".to_string()], String::new())
+            .unwrap()
+            .with_dem_url(dem_url);
+        let mut trail = sample_trails().remove(0);
+        trail.line = vec![vec![[-41.30, 174.70], [-41.30, 174.71], [-41.31, 174.71]]];
+        let expected_km = polyline_length_km(&trail.line, DistanceModel::Haversine);
+        *service.overpass_cache.write().await = Some(OverpassCacheEntry {
+            fetched_at: Instant::now(),
+            bbox: Bbox::default(),
+            trails: vec![trail],
+        });
+
+        let profile = service.elevation_profile("t1").await.unwrap().unwrap();
+        assert_eq!(profile.len(), 3);
+        assert_eq!(profile[0].distance_km, 0.0);
+        assert!((profile[2].distance_km as f64 - expected_km).abs() < 1e-4);
+        assert_eq!(profile[2].elevation_m, 120.0);
+        assert!(service.elevation_profile("missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rounds_handed_out_coordinates_but_not_the_cache() {
+        let service = TrailService::new(vec!["https://overpass.example".to_string()], String::new())
+            .unwrap()
+            .with_coordinate_decimals(4);
+        let mut trail = sample_trails().remove(1);
+        trail.lat = Some(-41.123456);
+        trail.lon = Some(174.987654);
+        trail.line = vec![vec![[-41.123456, 174.987654], [-41.00004, 174.99996]]];
+        *service.overpass_cache.write().await = Some(OverpassCacheEntry {
+            fetched_at: Instant::now(),
+            bbox: Bbox::default(),
+            trails: vec![trail],
+        });
+
+        let rounded = service.find_by_id("t2").await.unwrap();
+        assert_eq!((rounded.lat, rounded.lon), (Some(-41.1235), Some(174.9877)));
+        assert_eq!(rounded.line, vec![vec![[-41.1235, 174.9877], [-41.0, 175.0]]]);
+
+        let cached = service.overpass_cache.read().await.as_ref().unwrap().trails[0].clone();
+        assert_eq!((cached.lat, cached.lon), (Some(-41.123456), Some(174.987654)));
+    }
+
+    #[tokio::test]
+    async fn trail_detail_reports_net_elevation_change() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let counter = lookups.clone();
+        // Ground rises 100 m per 0.01° east.
+        let router = axum::Router::new().route(
+            "/api/v1/lookup",
+            axum::routing::get(move |query: axum::extract::Query<HashMap<String, String>>| async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let results: Vec<Value> = query["locations"]
+                    .split('|')
+                    .map(|location| {
+                        let lon: f64 = location.split(',').nth(1).unwrap().parse().unwrap();
+                        serde_json::json!({ "elevation": 100.0 + (lon - 174.70) * 10_000.0 })
+                    })
+                    .collect();
+                axum::Json(serde_json::json!({ "results": results }))
+            }),
+        );
+        let dem_url = serve_mock(router).await;
+        let service = TrailService::new(vec!["https://overpass.example".to_string()], String::new())
+            .unwrap()
+            .with_dem_url(dem_url);
+        let template = Trail { elevation_m: None, ..sample_trails().remove(1) };
+        let uphill = Trail {
+            id: "uphill".to_string(),
+            line: vec![vec![[-41.30, 174.70], [-41.30, 174.71], [-41.30, 174.72]]],
+            ..template.clone()
+        };
+        let round = Trail {
+            id: "round".to_string(),
+            line: vec![vec![[-41.30, 174.70], [-41.30, 174.71], [-41.31, 174.71], [-41.31, 174.70]]],
+            ..template
+        };
+        *service.overpass_cache.write().await = Some(OverpassCacheEntry {
+            fetched_at: Instant::now(),
+            bbox: Bbox::default(),
+            trails: vec![uphill, round],
+        });
+
+        let uphill = service.trail("uphill").await.unwrap().unwrap();
+        let net = uphill.net_elevation_m.unwrap();
+        assert!((net - 200.0).abs() < 0.5, "net {net}");
+        assert!((uphill.elevation_m.unwrap() - 200.0).abs() < 0.5);
+
+        let round = service.trail("round").await.unwrap().unwrap();
+        assert!(round.net_elevation_m.unwrap().abs() < 0.5);
+        assert!((round.elevation_m.unwrap() - 100.0).abs() < 0.5);
+
+        // Asking again is answered from the profile cache.
+        let fetched = lookups.load(Ordering::SeqCst);
+        let uphill = service.trail("uphill").await.unwrap().unwrap();
+        assert!((uphill.net_elevation_m.unwrap() - 200.0).abs() < 0.5);
+        assert_eq!(lookups.load(Ordering::SeqCst), fetched);
+    }
+
+    #[tokio::test]
+    async fn elevation_profile_requires_dem() {
+        let service = TrailService::new(vec!["https://overpass.example".to_string()], String::new()).unwrap();
+        assert!(matches!(service.elevation_profile("t1").await, Err(TrailError::Config(_))));
+    }
+
+    #[test]
+    fn filters_large_generated_set_consistently() {
+        let template = sample_trails().remove(0);
+        let trails: Vec<Trail> = (0..5000)
+            .map(|index| Trail {
+                id: format!("gen-{index:05}"),
+                distance_km: (index % 40) as f32,
+                dog_policy: if index % 3 == 0 { DogPolicy::NotAllowed } else { DogPolicy::Allowed },
+                ..template.clone()
+            })
+            .collect();
         let query = TrailQuery {
-            length: Some(Length::Short),
+            length: Some(Length::Medium),
             ..TrailQuery::default()
         };
+        let range = derive_distance_range(&query);
+        let weights = ScoreWeights::default();
+
         let results = filter_trails(&trails, &query);
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].id, "t1");
+        let expected = trails
+            .iter()
+            .filter(|trail| trail.dog_policy == DogPolicy::Allowed && within_distance(trail.distance_km, &range))
+            .count();
+        assert_eq!(results.len(), expected);
+        assert_eq!(results[0].match_score, Some(100));
+        for pair in results.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            assert!(a.match_score >= b.match_score);
+            if a.match_score == b.match_score {
+                assert!(score_trail(a, &range, None, None, &weights, None) < score_trail(b, &range, None, None, &weights, None) || a.id < b.id);
+            }
+        }
     }
 
     #[test]
-    fn allows_any_dog_policy_when_requested() {
-        let trails = sample_trails();
+    fn summary_describes_a_fully_known_trail() {
+        let mut trail = sample_trails().remove(0);
+        trail.distance_km = 5.2;
+        trail.line = vec![vec![[-41.30, 174.70], [-41.31, 174.71], [-41.30, 174.7001]]];
+        trail.dog_policy = DogPolicy::Partial;
+        trail.place = Some("Karori".to_string());
+        assert_eq!(trail_summary(&trail), "Easy 5.2 km gravel loop, dogs on lead or in parts only, near Karori");
+    }
+
+    #[test]
+    fn summary_leaves_out_unknown_parts() {
+        let mut trail = sample_trails().remove(1);
+        trail.distance_km = 0.0;
+        trail.surface = "Unknown".to_string();
+        trail.dog_policy = DogPolicy::Unknown;
+        trail.location = "Unknown".to_string();
+        assert_eq!(trail_summary(&trail), "Hard walk");
+
+        trail.distance_km = 3.0;
+        trail.distance_source = DistanceSource::ComputedFromGeometry;
+        trail.surface = "fine_gravel".to_string();
+        assert_eq!(trail_summary(&trail), "Hard ~3.0 km fine gravel walk");
+    }
+
+    #[test]
+    fn diversify_keeps_only_spatially_distinct_trails() {
+        let template = sample_trails().remove(0);
+        let trail = |id: &str, lat: f64, lon: f64| Trail {
+            id: id.to_string(),
+            lat: Some(lat),
+            lon: Some(lon),
+            ..template.clone()
+        };
+        let mut unplaced = trail("unplaced", 0.0, 0.0);
+        (unplaced.lat, unplaced.lon) = (None, None);
+        let ranked = vec![
+            trail("hill", -41.300, 174.700),
+            // About 110 m and 220 m from "hill".
+            trail("hill-east", -41.300, 174.7013),
+            trail("hill-north", -41.298, 174.700),
+            trail("valley", -41.320, 174.700),
+            trail("valley-west", -41.320, 174.6995),
+            unplaced,
+        ];
+
+        let ids: Vec<String> = diversify_trails(ranked.clone(), 0.5).into_iter().map(|trail| trail.id).collect();
+        assert_eq!(ids, ["hill", "valley", "unplaced"]);
+        assert_eq!(diversify_trails(ranked, 0.1).len(), 5);
+    }
+
+    #[test]
+    fn bbox_from_query_needs_all_corners_or_none() {
+        assert!(matches!(Bbox::from_query(&TrailQuery::default()), Ok(None)));
+
+        let partial = TrailQuery { min_lat: Some(-43.6), max_lat: Some(-43.4), ..TrailQuery::default() };
+        assert!(matches!(Bbox::from_query(&partial), Err(TrailError::InvalidQuery(_))));
+
+        let full = TrailQuery { min_lon: Some(172.5), max_lon: Some(172.8), ..partial };
+        let bbox = Bbox::from_query(&full).unwrap().unwrap();
+        assert_eq!((bbox.min_lat, bbox.min_lon, bbox.max_lat, bbox.max_lon), (-43.6, 172.5, -43.4, 172.8));
+    }
+
+    #[test]
+    fn display_names_are_separate_from_the_serialized_provider() {
+        assert_eq!(serde_json::to_value(Provider::DOC).unwrap(), "DOC");
+        assert_eq!(Provider::DOC.display_name(), "NZ DOC");
+
+        let service = TrailService::new(Vec::new(), "test-key".to_string())
+            .unwrap()
+            .with_display_name(Provider::OpenStreetMap, "OSM community data");
+        let mut trails = sample_trails();
+        for trail in &mut trails {
+            service.finish_trail(trail);
+        }
+        assert_eq!(trails[0].provider_name, "NZ DOC");
+        assert_eq!(trails[1].provider_name, "OSM community data");
+        assert_eq!(serde_json::to_value(&trails[1]).unwrap()["provider"], "OpenStreetMap");
+    }
+
+    #[test]
+    fn trail_slugs_are_short_and_stable() {
+        let slug = trail_slug(&Provider::DOC, "doc-1");
+        assert_eq!(slug.len(), 8);
+        assert_eq!(slug, trail_slug(&Provider::DOC, "doc-1"));
+        assert_eq!(slug, "54wlam8r");
+        assert_ne!(slug, trail_slug(&Provider::OpenStreetMap, "doc-1"));
+    }
+
+    #[test]
+    fn flags_data_checked_before_the_threshold() {
+        assert!(data_outdated(Some("2019-05-01"), 2026, 3));
+        assert!(!data_outdated(Some("2024"), 2026, 3));
+        assert!(!data_outdated(Some("soon"), 2026, 3));
+        assert!(!data_outdated(None, 2026, 3));
+        assert_eq!(today_iso().len(), 10);
+    }
+
+    #[test]
+    fn flags_hot_paw_risk_on_sealed_surfaces_in_summer() {
+        let summer = [12, 1, 2];
+        let surfaces = SurfaceClasses::default();
+        assert_eq!(hot_paw_risk("asphalt", 1, &summer, &surfaces), Some(true));
+        assert_eq!(hot_paw_risk("Asphalt", 7, &summer, &surfaces), Some(false));
+        assert_eq!(hot_paw_risk("gravel", 1, &summer, &surfaces), Some(false));
+        assert_eq!(hot_paw_risk("Unknown", 1, &summer, &surfaces), None);
+        let (year, month, day) = civil_today();
+        assert!(year >= 2024);
+        assert!((1..=12).contains(&month));
+        assert!((1..=31).contains(&day));
+    }
+
+    #[test]
+    fn dates_follow_new_zealand_time() {
+        // 11:30 UTC is 23:30 in NZ standard time and 00:30 the next day in daylight time.
+        assert_eq!(nz_civil_date(1_706_700_600), (2024, 2, 1));
+        assert_eq!(nz_civil_date(1_712_316_600), (2024, 4, 6));
+        assert_eq!(nz_civil_date(1_712_575_800), (2024, 4, 8));
+        assert_eq!(nz_civil_date(1_719_747_000), (2024, 6, 30));
+        assert_eq!(nz_civil_date(1_727_436_600), (2024, 9, 27));
+        assert_eq!(nz_civil_date(1_727_695_800), (2024, 10, 1));
+        assert_eq!(days_from_civil(2024, 2, 29), 19_782);
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+    }
+
+    #[test]
+    fn warns_about_hot_surfaces() {
+        let mut trail = sample_trails().remove(0);
+        assert!(trail_warnings(&trail).is_empty());
+        trail.hot_paw_risk = Some(true);
+        let warnings = trail_warnings(&trail);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, "hot-paw");
+        assert_eq!(warnings[0].message, HOT_PAW_WARNING);
+        trail.water_crossing = true;
+        trail.difficulty_conflict = true;
+        let kinds: Vec<_> = trail_warnings(&trail).into_iter().map(|warning| warning.kind).collect();
+        assert_eq!(kinds, ["water-crossing", "difficulty-conflict", "hot-paw"]);
+    }
+
+    #[test]
+    fn lists_unknown_query_params() {
+        let keys = ["dificulty".to_string(), "length".to_string(), "dgo".to_string()];
+        assert_eq!(TrailQuery::unknown_params(&keys), vec!["dgo", "dificulty"]);
+    }
+
+    #[test]
+    fn breaks_score_ties_by_id() {
+        let mut first = sample_trails().remove(0);
+        first.id = "t9".to_string();
+        let mut second = first.clone();
+        second.id = "t3".to_string();
         let query = TrailQuery {
-            dog: Some(DogFilter::Any),
-            min_km: Some(0.0),
-            max_km: Some(20.0),
+            length: Some(Length::Short),
+            ..TrailQuery::default()
+        };
+        let results = filter_trails(&[first, second], &query);
+        let ids: Vec<&str> = results.iter().map(|trail| trail.id.as_str()).collect();
+        assert_eq!(ids, vec!["t3", "t9"]);
+    }
+
+    #[test]
+    fn breaks_score_ties_by_provider_priority() {
+        let mut osm = sample_trails().remove(0);
+        osm.id = "a-osm".to_string();
+        osm.provider = Provider::OpenStreetMap;
+        let mut doc = osm.clone();
+        doc.id = "z-doc".to_string();
+        doc.provider = Provider::DOC;
+        let query = TrailQuery {
+            length: Some(Length::Short),
             ..TrailQuery::default()
         };
+        let trails = [osm, doc];
+
         let results = filter_trails(&trails, &query);
-        assert_eq!(results.len(), 2);
+        let ids: Vec<&str> = results.iter().map(|trail| trail.id.as_str()).collect();
+        assert_eq!(ids, vec!["z-doc", "a-osm"]);
+
+        let results = filter_trails_by_priority(&trails, &query, &[Provider::OpenStreetMap]);
+        assert_eq!(results[0].id, "a-osm");
     }
 }
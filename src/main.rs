@@ -1,19 +1,35 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    extract::{rejection::QueryRejection, Path, Query, State},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
     routing::get,
     Json, Router,
 };
-use tower_http::services::ServeDir;
+use serde::Deserialize;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    services::ServeDir,
+    set_header::SetResponseHeaderLayer,
+    trace::TraceLayer,
+};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use dogtrails::{filter_trails, ProviderInfo, TrailQuery, TrailService};
+use dogtrails::{apply_ascii, chain_trails, named_provider_rule, paginate, render_page, render_trail, summarize_trails, trails_to_gpx, DifficultyThresholds, DistanceModel, DogNotes, FilterPipeline, Metrics, ProfilePoint, Provider, ProviderInfo, ProviderRule, ScoreWeights, SurfaceClasses, TrailChain, TrailComparison, TrailError, TrailQuery, TrailService, TrailSummary, MAX_GPX_TRAILS};
 
 #[derive(Clone)]
 struct AppState {
     service: Arc<TrailService>,
+    /// Result count below which `?relax=true` loosens the filters.
+    relax_min_results: usize,
+    /// Whether `/api/doc/tracks` may serve the full national DOC list.
+    doc_tracks_enabled: bool,
+    /// Built frontend served at `/`.
+    frontend_dir: String,
+    /// Shared with `service`; counts requests here and upstream traffic there.
+    metrics: Arc<Metrics>,
 }
 
 #[tokio::main]
@@ -45,21 +61,256 @@ async fn main() {
             ]
         });
 
-    let doc_api_key = std::env::var("DOC_API_KEY").unwrap();
-    let service = TrailService::new(overpass_urls, doc_api_key)
-        .expect("failed to create trail service");
+    let doc_api_key = std::env::var("DOC_API_KEY").unwrap_or_default();
+    let doc_enabled = !doc_api_key.trim().is_empty();
+    let include_unnamed = std::env::var("INCLUDE_UNNAMED_TRAILS")
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    let max_line_points = std::env::var("MAX_LINE_POINTS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(500);
+    let coordinate_decimals = std::env::var("COORDINATE_DECIMALS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(6);
+    let dog_notes = match std::env::var("DOG_NOTES_FILE") {
+        Ok(path) => DogNotes::from_file(&path).expect("failed to load dog notes"),
+        Err(_) => DogNotes::default(),
+    };
+    let lazy_doc_details = std::env::var("LAZY_DOC_DETAILS")
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    let slim_doc_cache = std::env::var("SLIM_DOC_CACHE")
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    let hard_terrain_caution = std::env::var("HARD_TERRAIN_CAUTION")
+        .map(|value| !(value == "0" || value.eq_ignore_ascii_case("false")))
+        .unwrap_or(true);
+    let summer_months = std::env::var("SUMMER_MONTHS")
+        .ok()
+        .map(|value| value.split(',').filter_map(|month| month.trim().parse().ok()).collect())
+        .unwrap_or_else(|| vec![12, 1, 2]);
+    let surface_list = |name: &str| {
+        std::env::var(name).ok().map(|value| {
+            value
+                .split(',')
+                .map(|surface| surface.trim().to_lowercase())
+                .filter(|surface| !surface.is_empty())
+                .collect::<Vec<_>>()
+        })
+    };
+    let default_surfaces = SurfaceClasses::default();
+    let surface_classes = SurfaceClasses {
+        natural: surface_list("NATURAL_SURFACES").unwrap_or(default_surfaces.natural),
+        hard: surface_list("HARD_SURFACES").unwrap_or(default_surfaces.hard),
+    };
+    let outdated_after_years = std::env::var("OUTDATED_AFTER_YEARS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(3);
+    let filter_pipeline = match std::env::var("FILTER_PIPELINE").as_deref() {
+        Ok("per_provider") => FilterPipeline::PerProvider,
+        _ => FilterPipeline::Combined,
+    };
+    // "osm:named,doc:with_line": built-in rules for the per-provider pipeline.
+    let provider_rules: Vec<(Provider, ProviderRule)> = std::env::var("PROVIDER_RULES")
+        .map(|value| {
+            value
+                .split(',')
+                .filter(|entry| !entry.trim().is_empty())
+                .filter_map(|entry| {
+                    let parsed = entry
+                        .trim()
+                        .split_once(':')
+                        .and_then(|(provider, rule)| Some((parse_provider(provider)?, named_provider_rule(rule)?)));
+                    if parsed.is_none() {
+                        tracing::warn!("ignoring unknown PROVIDER_RULES entry {entry:?}");
+                    }
+                    parsed
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let distance_model = match std::env::var("DISTANCE_MODEL").as_deref() {
+        Ok("vincenty") => DistanceModel::Vincenty,
+        _ => DistanceModel::Haversine,
+    };
+    let difficulty_thresholds = std::env::var("DIFFICULTY_THRESHOLDS_KM")
+        .ok()
+        .and_then(|value| {
+            let (easy, moderate) = value.split_once(',')?;
+            Some(DifficultyThresholds {
+                easy_max_km: easy.trim().parse().ok()?,
+                moderate_max_km: moderate.trim().parse().ok()?,
+            })
+        })
+        .unwrap_or_default();
+    let neutral_unknown_distance = std::env::var("NEUTRAL_UNKNOWN_DISTANCE")
+        .map(|value| !(value == "0" || value.eq_ignore_ascii_case("false")))
+        .unwrap_or(true);
+    let unknown_surface_penalty = std::env::var("UNKNOWN_SURFACE_PENALTY")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0.0);
+    let popularity_weight = std::env::var("POPULARITY_WEIGHT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0.0);
+    let near_loop_weight = std::env::var("NEAR_LOOP_WEIGHT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0.0);
+    let request_timeout_secs = std::env::var("REQUEST_TIMEOUT_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(30);
+    let overpass_stale_grace_secs = std::env::var("OVERPASS_STALE_GRACE_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(300);
+    let overpass_tile_deg: f64 = std::env::var("OVERPASS_TILE_DEG")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0.0);
+    let raw_debug = std::env::var("DEBUG_RAW")
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    let diversify = std::env::var("DIVERSIFY")
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    let diversify_spacing_m: f64 = std::env::var("DIVERSIFY_SPACING_M")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(500.0);
+    let mut service = TrailService::new(overpass_urls, doc_api_key)
+        .expect("failed to create trail service")
+        .with_include_unnamed(include_unnamed)
+        .with_distance_model(distance_model)
+        .with_difficulty_thresholds(difficulty_thresholds)
+        .with_score_weights(ScoreWeights {
+            unknown_surface: unknown_surface_penalty,
+            popularity: popularity_weight,
+            near_loop: near_loop_weight,
+            neutral_unknown_distance,
+            ..ScoreWeights::default()
+        })
+        .with_hard_terrain_caution(hard_terrain_caution)
+        .with_summer_months(summer_months)
+        .with_surface_classes(surface_classes)
+        .with_outdated_after_years(outdated_after_years)
+        .with_lazy_doc_details(lazy_doc_details)
+        .with_slim_doc_cache(slim_doc_cache)
+        .with_filter_pipeline(filter_pipeline)
+        .with_request_timeout(std::time::Duration::from_secs(request_timeout_secs))
+        .with_overpass_stale_grace(std::time::Duration::from_secs(overpass_stale_grace_secs))
+        .with_overpass_tiles(overpass_tile_deg)
+        .with_diversify(diversify, diversify_spacing_m / 1000.0)
+        .with_raw_debug(raw_debug)
+        .with_max_line_points(max_line_points)
+        .with_coordinate_decimals(coordinate_decimals)
+        .with_dog_notes(dog_notes);
+    for (provider, rule) in provider_rules {
+        service = service.with_provider_rule(provider, rule);
+    }
+    // "doc,osm": which provider wins score ties and merged fields.
+    if let Ok(value) = std::env::var("PROVIDER_PRIORITY") {
+        let priority: Vec<Provider> = value
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .filter_map(|entry| {
+                let parsed = parse_provider(entry);
+                if parsed.is_none() {
+                    tracing::warn!("ignoring unknown PROVIDER_PRIORITY entry {entry:?}");
+                }
+                parsed
+            })
+            .collect();
+        service = service.with_provider_priority(priority);
+    }
+    if let Some(max) = std::env::var("MAX_OUTBOUND_REQUESTS").ok().and_then(|value| value.parse().ok()) {
+        service = service.with_max_outbound_requests(max);
+    }
+    if let Some(retries) = std::env::var("DOC_LIST_RETRIES").ok().and_then(|value| value.parse().ok()) {
+        service = service.with_doc_list_retries(retries);
+    }
+    if let Some(limit) = std::env::var("DOC_DETAIL_FAILURE_LIMIT").ok().and_then(|value| value.parse().ok()) {
+        service = service.with_doc_detail_failure_limit(limit);
+    }
+    if let Some(tile_concurrency) = std::env::var("OVERPASS_TILE_CONCURRENCY").ok().and_then(|value| value.parse().ok()) {
+        service = service.with_tile_concurrency(tile_concurrency);
+    }
+    if let Ok(dem_url) = std::env::var("DEM_URL") {
+        service = service.with_dem_url(dem_url);
+    }
+    if let Ok(name) = std::env::var("DOC_DISPLAY_NAME") {
+        service = service.with_display_name(Provider::DOC, name);
+    }
+    if let Ok(name) = std::env::var("OSM_DISPLAY_NAME") {
+        service = service.with_display_name(Provider::OpenStreetMap, name);
+    }
+    if let Ok(webhook) = std::env::var("TRAILS_CHANGED_WEBHOOK") {
+        service = service.with_change_webhook(webhook);
+    }
+    let relax_min_results = std::env::var("RELAX_MIN_RESULTS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1);
+    let doc_tracks_enabled = std::env::var("DOC_TRACKS_ENDPOINT")
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    let doc_background_refresh = std::env::var("DOC_BACKGROUND_REFRESH")
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    let metrics_log = std::env::var("METRICS_LOG").ok().map(std::path::PathBuf::from);
+    if let Some(path) = &metrics_log {
+        service = service.with_metrics(Metrics::resume(path));
+    }
+    let service = Arc::new(service);
+    if let Some(path) = metrics_log {
+        let metrics = service.metrics();
+        let snapshot_secs: u64 = std::env::var("METRICS_SNAPSHOT_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(60);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(snapshot_secs.max(1)));
+            loop {
+                interval.tick().await;
+                if let Err(err) = metrics.append_snapshot(&path).await {
+                    tracing::warn!("failed to write metrics snapshot to {}: {}", path.display(), err);
+                }
+            }
+        });
+    }
+    if doc_background_refresh && doc_enabled {
+        let service = service.clone();
+        tokio::spawn(async move {
+            service.run_doc_refresher(std::time::Duration::from_secs(10 * 60)).await;
+        });
+    }
+    let probe_secs: u64 = std::env::var("OVERPASS_PROBE_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(300);
+    if probe_secs > 0 {
+        let service = service.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(probe_secs));
+            loop {
+                interval.tick().await;
+                service.probe_overpass_mirrors().await;
+            }
+        });
+    }
     let state = AppState {
-        service: Arc::new(service),
+        metrics: service.metrics(),
+        service,
+        relax_min_results,
+        doc_tracks_enabled,
+        frontend_dir: std::env::var("FRONTEND_DIR").unwrap_or_else(|_| "frontend/dist".to_string()),
     };
-
-    let app = Router::new()
-        .route("/api/trails", get(get_trails))
-        .route("/api/providers", get(get_providers))
-        .nest_service(
-            "/",
-            ServeDir::new("frontend/dist").append_index_html_on_directories(true),
-        )
-        .with_state(state);
+    let app = build_router(state);
 
     let port: u16 = std::env::var("PORT")
         .ok()
@@ -76,19 +327,663 @@ async fn main() {
         .expect("server error");
 }
 
+/// Every route, static file and middleware layer the server runs with.
+fn build_router(state: AppState) -> Router {
+    let frontend = ServeDir::new(&state.frontend_dir).append_index_html_on_directories(true);
+    let app = Router::new()
+        .route("/api/trails", get(get_trails))
+        .route("/api/trails.gpx", get(get_trails_gpx))
+        .route("/api/trails/summary", get(get_trail_summary))
+        .route("/api/trails/fragment", get(get_trail_fragments))
+        .route("/api/trails/compare", get(get_trail_comparison))
+        .route("/api/trails/chain", get(get_trail_chain))
+        .route("/api/trails/lookup", get(get_trail_lookup))
+        .route("/api/trails/:id", get(get_trail))
+        .route("/api/trails/:id/profile", get(get_trail_profile))
+        .route("/api/doc/tracks", get(get_doc_tracks))
+        .route("/api/providers", get(get_providers))
+        .route("/metrics", get(get_metrics))
+        .route("/", get(get_root))
+        .route("/lite", get(get_lite_page))
+        .route("/t/:slug", get(get_shared_trail))
+        .merge(static_files())
+        .fallback_service(frontend)
+        .layer(middleware::from_fn_with_state(state.clone(), count_requests))
+        .with_state(state);
+    with_request_id(app)
+}
+
+/// `/static` assets from `public/`. `ServeDir` answers `If-Modified-Since` with
+/// 304s and prefers a `.gz` sibling when present; browsers may cache for a day.
+fn static_files<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    Router::new()
+        .nest_service("/static", ServeDir::new("public").precompressed_gzip())
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=86400"),
+        ))
+}
+
+/// Feeds `dogtrails_requests_total`.
+async fn count_requests(State(state): State<AppState>, request: Request<axum::body::Body>, next: Next) -> Response {
+    state.metrics.record_request();
+    next.run(request).await
+}
+
+/// Tag each request with an `X-Request-Id` (keeping one the client sent), record it
+/// on the request span so upstream warnings can be correlated, and echo it back.
+fn with_request_id(router: Router) -> Router {
+    router
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(TraceLayer::new_for_http().make_span_with(|request: &Request<_>| {
+            let request_id = request
+                .headers()
+                .get("x-request-id")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("-");
+            tracing::info_span!(
+                "request",
+                request_id = %request_id,
+                method = %request.method(),
+                uri = %request.uri(),
+            )
+        }))
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+/// With `?strict=true`, or always in debug builds, reject query parameters
+/// `TrailQuery` doesn't know instead of silently ignoring them.
+fn check_strict(params: &HashMap<String, String>) -> Result<(), (StatusCode, String)> {
+    let strict = cfg!(debug_assertions) || params.get("strict").is_some_and(|value| value == "true");
+    let unknown = TrailQuery::unknown_params(params.keys());
+    if strict && !unknown.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("unknown query parameters: {}", unknown.join(", ")),
+        ));
+    }
+    Ok(())
+}
+
+/// A failed service call as a response, the same for every route: bad
+/// parameters are the caller's mistake, a feature this server isn't set up
+/// for is 501, a provider that didn't answer in time is 504, and any other
+/// provider failure is 502.
+fn search_error(err: TrailError) -> (StatusCode, String) {
+    match err {
+        TrailError::InvalidQuery(message) => (StatusCode::BAD_REQUEST, message),
+        TrailError::Config(message) => (StatusCode::NOT_IMPLEMENTED, message),
+        TrailError::Network(message) => (StatusCode::GATEWAY_TIMEOUT, message),
+        TrailError::Upstream(message) => (StatusCode::BAD_GATEWAY, message),
+    }
+}
+
+/// One page of `trails` for `query`, as every search endpoint serves it:
+/// relaxed when asked and too few match, diversified, paged, and with debug
+/// data and macrons handled. The flag is `true` when relaxing produced the page.
+fn search_page(state: &AppState, trails: &[dogtrails::Trail], query: &TrailQuery) -> (Vec<dogtrails::Trail>, bool) {
+    let (filtered, relaxed) = if query.relax.unwrap_or(false) {
+        state.service.filter_trails_relaxed(trails, query, state.relax_min_results)
+    } else {
+        (state.service.filter_trails(trails, query), false)
+    };
+    let filtered = state.service.diversify(filtered, query);
+    let visible = state.service.apply_debug(paginate(filtered, query), query);
+    (apply_ascii(visible, query), relaxed)
+}
+
+/// Why a `trusted_only` search has fewer results.
+const TRUSTED_ONLY_NOTE: &str = "Official sources only: community-tagged OpenStreetMap trails are hidden.";
+
 async fn get_trails(
     State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+    Query(query): Query<TrailQuery>,
+) -> Result<(HeaderMap, Json<Vec<dogtrails::Trail>>), (StatusCode, String)> {
+    check_strict(&params)?;
+    let (trails, cache_info) = state
+        .service
+        .fetch_trails_with_cache_info(&query)
+        .await
+        .map_err(search_error)?;
+    let (visible, relaxed) = search_page(&state, &trails, &query);
+    let mut headers = HeaderMap::new();
+    if let Some(info) = cache_info {
+        headers.insert("x-cache", HeaderValue::from_static(info.status.as_header()));
+        headers.insert(header::AGE, HeaderValue::from(info.age_secs));
+    }
+    if relaxed {
+        headers.insert("x-results-relaxed", HeaderValue::from_static("true"));
+    }
+    if query.trusted_only.unwrap_or(false) {
+        headers.insert("x-results-note", HeaderValue::from_static(TRUSTED_ONLY_NOTE));
+    }
+    Ok((headers, Json(visible)))
+}
+
+/// The same search as `/api/trails` as one GPX download, capped at
+/// `MAX_GPX_TRAILS` trails.
+async fn get_trails_gpx(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+    Query(query): Query<TrailQuery>,
+) -> Result<([(header::HeaderName, &'static str); 2], String), (StatusCode, String)> {
+    check_strict(&params)?;
+    let trails = state
+        .service
+        .fetch_trails(&query)
+        .await
+        .map_err(search_error)?;
+    let (mut visible, _) = search_page(&state, &trails, &query);
+    visible.truncate(MAX_GPX_TRAILS);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/gpx+xml"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"dogtrails.gpx\""),
+        ],
+        trails_to_gpx(&visible),
+    ))
+}
+
+/// The same search as `/api/trails` as bare `<article class="trail">` cards,
+/// for a page to swap into its results without a reload.
+async fn get_trail_fragments(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+    Query(query): Query<TrailQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    check_strict(&params)?;
+    let trails = state
+        .service
+        .fetch_trails(&query)
+        .await
+        .map_err(search_error)?;
+    let (visible, _) = search_page(&state, &trails, &query);
+    let fragment: String = visible.iter().map(render_trail).collect();
+    Ok(html_response(fragment))
+}
+
+async fn get_trail_summary(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
     Query(query): Query<TrailQuery>,
+) -> Result<Json<TrailSummary>, (StatusCode, String)> {
+    check_strict(&params)?;
+    let trails = state
+        .service
+        .fetch_trails(&query)
+        .await
+        .map_err(search_error)?;
+    let filtered = state.service.filter_trails(&trails, &query);
+    Ok(Json(summarize_trails(&filtered)))
+}
+
+#[derive(Deserialize)]
+struct CompareQuery {
+    ids: String,
+    debug: Option<bool>,
+}
+
+async fn get_trail_comparison(
+    State(state): State<AppState>,
+    Query(query): Query<CompareQuery>,
+) -> Result<Json<TrailComparison>, (StatusCode, String)> {
+    let ids: Vec<String> = query
+        .ids
+        .split(',')
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect();
+    if ids.is_empty() || ids.len() > 3 {
+        return Err((StatusCode::BAD_REQUEST, "ids must list one to three trail ids".to_string()));
+    }
+    let mut comparison = state.service.compare(&ids).await;
+    if comparison.trails.is_empty() {
+        return Err((StatusCode::NOT_FOUND, format!("no cached trails for ids: {}", query.ids)));
+    }
+    let debug = TrailQuery { debug: query.debug, ..TrailQuery::default() };
+    comparison.trails = state.service.apply_debug(comparison.trails, &debug);
+    Ok(Json(comparison))
+}
+
+#[derive(Deserialize)]
+struct ChainQuery {
+    target_km: f32,
+}
+
+/// Nearby trails that join up into one outing of about `target_km`, for when
+/// no single trail is long enough. Other filters apply to each trail in it.
+async fn get_trail_chain(
+    State(state): State<AppState>,
+    Query(mut params): Query<HashMap<String, String>>,
+    Query(chain): Query<ChainQuery>,
+    Query(query): Query<TrailQuery>,
+) -> Result<Json<TrailChain>, (StatusCode, String)> {
+    params.remove("target_km");
+    check_strict(&params)?;
+    if chain.target_km.is_nan() || chain.target_km <= 0.0 {
+        return Err((StatusCode::BAD_REQUEST, "target_km must be positive".to_string()));
+    }
+    let trails = state
+        .service
+        .fetch_trails(&query)
+        .await
+        .map_err(search_error)?;
+    // Any trail shorter than the target can be a link.
+    let links = TrailQuery { min_km: Some(0.0), max_km: Some(chain.target_km), ..query };
+    let filtered = state.service.apply_debug(state.service.filter_trails(&trails, &links), &links);
+    chain_trails(&filtered, chain.target_km)
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "no nearby trails join up".to_string()))
+}
+
+#[derive(Deserialize)]
+struct LookupQuery {
+    provider: String,
+    #[serde(rename = "ref")]
+    reference: String,
+}
+
+/// A provider by the short name the lookup endpoint and settings use.
+fn parse_provider(name: &str) -> Option<Provider> {
+    match name.trim() {
+        "osm" => Some(Provider::OpenStreetMap),
+        "doc" => Some(Provider::DOC),
+        _ => None,
+    }
+}
+
+/// One trail by its OSM way id or DOC asset id, fetched straight from the
+/// provider rather than found in an area search.
+async fn get_trail_lookup(
+    State(state): State<AppState>,
+    Query(lookup): Query<LookupQuery>,
+) -> Result<Json<dogtrails::Trail>, (StatusCode, String)> {
+    let Some(provider) = parse_provider(&lookup.provider) else {
+        return Err((StatusCode::BAD_REQUEST, format!("provider must be osm or doc, not {}", lookup.provider)));
+    };
+    match state.service.lookup(&provider, &lookup.reference).await {
+        Ok(Some(trail)) => Ok(Json(trail)),
+        Ok(None) => Err((StatusCode::NOT_FOUND, format!("no {} trail {}", lookup.provider, lookup.reference))),
+        Err(err) => Err(search_error(err)),
+    }
+}
+
+async fn get_doc_tracks(
+    State(state): State<AppState>,
 ) -> Result<Json<Vec<dogtrails::Trail>>, (StatusCode, String)> {
+    if !state.doc_tracks_enabled {
+        return Err((StatusCode::NOT_FOUND, "DOC track listing is disabled".to_string()));
+    }
+    match state.service.doc_tracks_all().await {
+        Ok(trails) => Ok(Json(state.service.apply_debug(trails, &TrailQuery::default()))),
+        Err(err) => Err(search_error(err)),
+    }
+}
+
+/// An HTML page with its charset spelled out, so clients that don't default to
+/// UTF-8 still show macrons in names like "Ōtari" correctly.
+fn html_response(body: String) -> Response {
+    ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], body).into_response()
+}
+
+#[derive(Deserialize)]
+struct ModeQuery {
+    mode: Option<String>,
+}
+
+/// `/` picks a UI explicitly: the Yew app by default or with `?mode=spa`, and
+/// the server-rendered `/lite` page with `?mode=ssr`, taking the same filters.
+async fn get_root(
+    State(state): State<AppState>,
+    Query(mode): Query<ModeQuery>,
+    query: Result<Query<TrailQuery>, QueryRejection>,
+) -> Result<Response, (StatusCode, String)> {
+    if mode.mode.as_deref() == Some("ssr") {
+        let query = query.map_err(|err| (err.status(), err.body_text()))?;
+        return get_lite_page(State(state), query).await;
+    }
+    let index = std::path::Path::new(&state.frontend_dir).join("index.html");
+    tokio::fs::read_to_string(&index).await.map(html_response).map_err(|err| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("frontend not built ({}: {err}); try /?mode=ssr", index.display()),
+        )
+    })
+}
+
+async fn get_lite_page(
+    State(state): State<AppState>,
+    Query(query): Query<TrailQuery>,
+) -> Result<Response, (StatusCode, String)> {
     let trails = state
         .service
         .fetch_trails(&query)
         .await
-        .map_err(|err| (StatusCode::BAD_GATEWAY, err.to_string()))?;
-    let filtered = filter_trails(&trails, &query);
-    Ok(Json(filtered))
+        .map_err(search_error)?;
+    let filtered = state.service.diversify(state.service.filter_trails(&trails, &query), &query);
+    let filtered = apply_ascii(filtered, &query);
+    Ok(html_response(render_page(&query, &filtered)))
+}
+
+async fn get_trail(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<dogtrails::Trail>, (StatusCode, String)> {
+    match state.service.trail(&id).await {
+        Ok(Some(trail)) => Ok(Json(trail)),
+        Ok(None) => Err((StatusCode::NOT_FOUND, format!("no cached trail with id {id}"))),
+        Err(err) => Err(search_error(err)),
+    }
+}
+
+/// Shareable link for one trail, rendered as the no-JS page.
+async fn get_shared_trail(
+    State(state): State<AppState>,
+    Path(slug): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    match state.service.trail_by_slug(&slug).await {
+        Ok(Some(trail)) => Ok(html_response(render_page(&TrailQuery::default(), &[trail]))),
+        Ok(None) => Err((StatusCode::NOT_FOUND, format!("no cached trail for link {slug}"))),
+        Err(err) => Err(search_error(err)),
+    }
+}
+
+async fn get_trail_profile(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<ProfilePoint>>, (StatusCode, String)> {
+    match state.service.elevation_profile(&id).await {
+        Ok(Some(profile)) => Ok(Json(profile)),
+        Ok(None) => Err((StatusCode::NOT_FOUND, format!("no cached trail with id {id}"))),
+        Err(err) => Err(search_error(err)),
+    }
+}
+
+async fn get_providers(State(state): State<AppState>) -> Json<Vec<ProviderInfo>> {
+    Json(state.service.provider_info().await)
 }
 
-async fn get_providers() -> Json<Vec<ProviderInfo>> {
-    Json(ProviderInfo::default_providers())
+/// Prometheus text format counters; see `Metrics`.
+async fn get_metrics(State(state): State<AppState>) -> ([(header::HeaderName, &'static str); 1], String) {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(state.service.outbound_in_flight()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn echoes_provided_request_id() {
+        let app = with_request_id(Router::new().route("/", get(|| async { "ok" })));
+        let request = Request::builder()
+            .uri("/")
+            .header("x-request-id", "abc-123")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["x-request-id"], "abc-123");
+    }
+
+    #[tokio::test]
+    async fn caches_static_stylesheet() {
+        let app: Router = static_files();
+        let request = Request::builder().uri("/static/lite.css").body(Body::empty()).unwrap();
+        let first = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(first.headers()[header::CACHE_CONTROL], "public, max-age=86400");
+        let last_modified = first.headers()[header::LAST_MODIFIED].clone();
+
+        let request = Request::builder()
+            .uri("/static/lite.css")
+            .header(header::IF_MODIFIED_SINCE, last_modified)
+            .body(Body::empty())
+            .unwrap();
+        let second = app.oneshot(request).await.unwrap();
+        assert_eq!(second.status(), StatusCode::NOT_MODIFIED);
+        assert!(second.headers().contains_key(header::CACHE_CONTROL));
+    }
+
+    /// App state backed by a local mock Overpass mirror serving one trail,
+    /// with the frontend served from a temporary directory.
+    async fn test_state() -> AppState {
+        test_state_with(|service| service).await
+    }
+
+    /// `test_state` with the service adjusted by `configure`.
+    async fn test_state_with(configure: impl FnOnce(TrailService) -> TrailService) -> AppState {
+        let overpass = Router::new().route(
+            "/api/interpreter",
+            get(|| async {
+                Json(serde_json::json!({
+                    "elements": [{
+                        "type": "way",
+                        "id": 7,
+                        "tags": { "name": "Bottle Lake Loop", "highway": "path", "dog": "yes" },
+                        "geometry": [
+                            { "lat": -43.50, "lon": 172.60 },
+                            { "lat": -43.50, "lon": 172.65 },
+                            { "lat": -43.53, "lon": 172.65 }
+                        ]
+                    }]
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, overpass).await.unwrap();
+        });
+
+        let frontend_dir = std::env::temp_dir().join(format!("dogtrails-frontend-{}", address.port()));
+        std::fs::create_dir_all(&frontend_dir).unwrap();
+        std::fs::write(frontend_dir.join("index.html"), "<!doctype html><div id=\"app\"></div>").unwrap();
+
+        let service = configure(TrailService::new(vec![format!("http://{address}/api/interpreter")], String::new()).unwrap());
+        AppState {
+            metrics: service.metrics(),
+            service: Arc::new(service),
+            relax_min_results: 1,
+            doc_tracks_enabled: false,
+            frontend_dir: frontend_dir.to_string_lossy().into_owned(),
+        }
+    }
+
+    async fn get_body(app: Router, uri: &str) -> (StatusCode, String) {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn metrics_count_requests_and_cache_use() {
+        let app = build_router(test_state().await);
+        get_body(app.clone(), "/api/trails?length=medium").await;
+        get_body(app.clone(), "/api/trails?length=medium").await;
+        let (status, body) = get_body(app, "/metrics").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("dogtrails_requests_total 3\n"), "{body}");
+        assert!(body.contains("dogtrails_cache_misses_total{provider=\"OpenStreetMap\"} 1\n"), "{body}");
+        assert!(body.contains("dogtrails_cache_hits_total{provider=\"OpenStreetMap\"} 1\n"), "{body}");
+        assert!(body.contains("dogtrails_outbound_in_flight 0\n"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn raw_data_only_appears_when_debugging() {
+        let app = build_router(test_state_with(|service| service.with_raw_debug(true)).await);
+        let (_, body) = get_body(app.clone(), "/api/trails?length=medium&debug=true").await;
+        assert!(body.contains("\"raw\""), "{body}");
+        let id = serde_json::from_str::<serde_json::Value>(&body).unwrap()[0]["id"].as_str().unwrap().to_string();
+
+        let (status, body) = get_body(app.clone(), &format!("/api/trails/compare?ids={id}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.contains("\"raw\""), "{body}");
+        let (_, body) = get_body(app, &format!("/api/trails/compare?ids={id}&debug=true")).await;
+        assert!(body.contains("\"raw\""), "{body}");
+    }
+
+    #[tokio::test]
+    async fn chain_rejects_unknown_parameters_in_strict_mode() {
+        let app = build_router(test_state().await);
+        let (status, body) = get_body(app, "/api/trails/chain?target_km=10&lenght=long&strict=true").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("lenght"), "{body}");
+    }
+
+    #[test]
+    fn maps_each_error_to_its_own_status() {
+        let status = |err| search_error(err).0;
+        assert_eq!(status(TrailError::InvalidQuery("partial bbox".to_string())), StatusCode::BAD_REQUEST);
+        assert_eq!(status(TrailError::Config("no DEM".to_string())), StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(status(TrailError::Network("timed out".to_string())), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(status(TrailError::Upstream("bad JSON".to_string())), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn downloads_search_as_gpx() {
+        let app = build_router(test_state().await);
+        let request = Request::builder().uri("/api/trails.gpx?length=medium").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_DISPOSITION], "attachment; filename=\"dogtrails.gpx\"");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let gpx = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(gpx.contains("<trk>\n<name>Bottle Lake Loop</name>"));
+    }
+
+    #[tokio::test]
+    async fn serves_trails_through_full_router() {
+        let app = build_router(test_state().await);
+        let (status, body) = get_body(app, "/api/trails?length=medium").await;
+        assert_eq!(status, StatusCode::OK);
+        let trails: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        assert_eq!(trails.len(), 1);
+        assert_eq!(trails[0]["name"], "Bottle Lake Loop");
+        assert_eq!(trails[0]["provider"], "OpenStreetMap");
+    }
+
+    #[tokio::test]
+    async fn serves_trail_cards_as_a_fragment() {
+        let app = build_router(test_state().await);
+        let request = Request::builder().uri("/api/trails/fragment?length=medium").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(html.starts_with("<article class=\"trail\">"), "{html}");
+        assert!(html.contains(">Bottle Lake Loop</a>"));
+        assert!(!html.contains("<html"));
+        assert!(!html.contains("<form"));
+    }
+
+    #[tokio::test]
+    async fn resolves_shared_trail_slug() {
+        let app = build_router(test_state().await);
+        let (_, body) = get_body(app.clone(), "/api/trails").await;
+        let trails: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        let slug = trails[0]["slug"].as_str().unwrap().to_string();
+        assert_eq!(slug, dogtrails::trail_slug(&dogtrails::Provider::OpenStreetMap, "osm-7"));
+
+        let (status, body) = get_body(app.clone(), &format!("/t/{slug}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Bottle Lake Loop"));
+        let (status, _) = get_body(app, "/t/zzzzzzzz").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn rejects_misspelled_query_parameter() {
+        let app = build_router(test_state().await);
+        let (status, body) = get_body(app, "/api/trails?dificulty=easy&strict=true").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("dificulty"), "got {body}");
+    }
+
+    #[tokio::test]
+    async fn serves_providers_through_full_router() {
+        let app = build_router(test_state().await);
+        let (status, body) = get_body(app, "/api/providers").await;
+        assert_eq!(status, StatusCode::OK);
+        let providers: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        assert!(providers.iter().any(|provider| provider["api_status"] == "no key"));
+        assert!(providers.iter().any(|provider| provider["api_status"] == "enabled (1 mirror)"));
+    }
+
+    #[tokio::test]
+    async fn serves_frontend_index_at_root() {
+        let app = build_router(test_state().await);
+        let (status, body) = get_body(app, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<div id=\"app\">"));
+    }
+
+    #[tokio::test]
+    async fn html_keeps_macrons_as_utf8() {
+        let (_, body) = get_body(build_router(test_state().await), "/api/trails?length=medium").await;
+        let mut trails: Vec<dogtrails::Trail> = serde_json::from_str(&body).unwrap();
+        trails[0].name = "Ōtari Loop".to_string();
+
+        let response = html_response(render_page(&TrailQuery::default(), &trails));
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let name = b">\xC5\x8Ctari Loop<";
+        assert!(bytes.windows(name.len()).any(|window| window == name));
+    }
+
+    #[tokio::test]
+    async fn summary_applies_provider_rules() {
+        let app = build_router(test_state().await);
+        let (_, body) = get_body(app, "/api/trails/summary?length=medium").await;
+        assert!(body.contains("\"total\":1"), "{body}");
+
+        let drop_all: ProviderRule = Arc::new(|_, _| false);
+        let app = build_router(
+            test_state_with(|service| {
+                service
+                    .with_filter_pipeline(FilterPipeline::PerProvider)
+                    .with_provider_rule(Provider::OpenStreetMap, drop_all)
+            })
+            .await,
+        );
+        let (status, body) = get_body(app, "/api/trails/summary?length=medium").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("\"total\":0"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn root_mode_picks_the_ui() {
+        let state = test_state().await;
+        let (status, spa) = get_body(build_router(state.clone()), "/?mode=spa").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(spa.contains("<div id=\"app\">"));
+
+        let (status, spa) = get_body(build_router(state.clone()), "/?difficulty=bogus").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(spa.contains("<div id=\"app\">"));
+        let (status, _) = get_body(build_router(state.clone()), "/?mode=ssr&difficulty=bogus").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, ssr) = get_body(build_router(state), "/?mode=ssr&length=medium").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(ssr.contains("<h1>For you + dog</h1>"));
+        assert!(ssr.contains("<a href=\"/?mode=spa\">"));
+        assert!(ssr.contains("Bottle Lake Loop"));
+    }
+
+    #[tokio::test]
+    async fn generates_request_id_when_missing() {
+        let app = with_request_id(Router::new().route("/", get(|| async { "ok" })));
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.headers().contains_key("x-request-id"));
+    }
 }
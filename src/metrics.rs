@@ -0,0 +1,176 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::Provider;
+
+/// Process-wide counters exposed at `/metrics` in Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    requests: AtomicU64,
+    doc: ProviderCounters,
+    osm: ProviderCounters,
+}
+
+#[derive(Default)]
+struct ProviderCounters {
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    upstream_errors: AtomicU64,
+}
+
+/// Counter values at one moment, one JSON line per snapshot in the log file.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    pub requests: u64,
+    /// Cache hits, cache misses and upstream errors.
+    pub doc: [u64; 3],
+    pub osm: [u64; 3],
+}
+
+impl ProviderCounters {
+    fn snapshot(&self) -> [u64; 3] {
+        [&self.cache_hits, &self.cache_misses, &self.upstream_errors].map(|counter| counter.load(Ordering::Relaxed))
+    }
+
+    fn restore([hits, misses, errors]: [u64; 3]) -> Self {
+        Self {
+            cache_hits: AtomicU64::new(hits),
+            cache_misses: AtomicU64::new(misses),
+            upstream_errors: AtomicU64::new(errors),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A cached answer for `provider`, fresh or stale, or a trip upstream.
+    pub fn record_cache(&self, provider: &Provider, hit: bool) {
+        let counters = self.counters(provider);
+        let counter = if hit { &counters.cache_hits } else { &counters.cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_upstream_error(&self, provider: &Provider) {
+        self.counters(provider).upstream_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counters resuming from the last complete snapshot in `path`. The log is
+    /// compacted to that one line so it doesn't grow across restarts. A
+    /// missing or unreadable log starts from zero.
+    pub fn resume(path: &Path) -> Self {
+        let contents = std::fs::read_to_string(path).unwrap_or_default();
+        let Some(line) = contents.lines().rev().find(|line| serde_json::from_str::<MetricsSnapshot>(line).is_ok()) else {
+            return Self::default();
+        };
+        if let Err(err) = std::fs::write(path, format!("{line}\n")) {
+            tracing::warn!("failed to compact metrics log {}: {}", path.display(), err);
+        }
+        let snapshot: MetricsSnapshot = serde_json::from_str(line).unwrap_or_default();
+        Self {
+            requests: AtomicU64::new(snapshot.requests),
+            doc: ProviderCounters::restore(snapshot.doc),
+            osm: ProviderCounters::restore(snapshot.osm),
+        }
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            doc: self.doc.snapshot(),
+            osm: self.osm.snapshot(),
+        }
+    }
+
+    /// Append the current counters to the log at `path`.
+    pub async fn append_snapshot(&self, path: &Path) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(&self.snapshot()).map_err(std::io::Error::other)?;
+        line.push('\n');
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await
+    }
+
+    fn counters(&self, provider: &Provider) -> &ProviderCounters {
+        match provider {
+            Provider::DOC => &self.doc,
+            Provider::OpenStreetMap => &self.osm,
+        }
+    }
+
+    /// Prometheus exposition text, with the current outbound request gauge.
+    pub fn render(&self, outbound_in_flight: usize) -> String {
+        let mut text = String::new();
+        text.push_str("# HELP dogtrails_requests_total HTTP requests received.\n");
+        text.push_str("# TYPE dogtrails_requests_total counter\n");
+        let _ = writeln!(text, "dogtrails_requests_total {}", self.requests.load(Ordering::Relaxed));
+
+        let families = [
+            ("dogtrails_cache_hits_total", "Searches answered from a provider cache.", 0),
+            ("dogtrails_cache_misses_total", "Searches that had to fetch from a provider.", 1),
+            ("dogtrails_upstream_errors_total", "Failed requests to a provider.", 2),
+        ];
+        for (name, help, index) in families {
+            let _ = writeln!(text, "# HELP {name} {help}");
+            let _ = writeln!(text, "# TYPE {name} counter");
+            for provider in [Provider::DOC, Provider::OpenStreetMap] {
+                let counters = self.counters(&provider);
+                let counter = [&counters.cache_hits, &counters.cache_misses, &counters.upstream_errors][index];
+                let _ = writeln!(text, "{name}{{provider=\"{provider}\"}} {}", counter.load(Ordering::Relaxed));
+            }
+        }
+
+        text.push_str("# HELP dogtrails_outbound_in_flight Upstream requests currently holding a slot.\n");
+        text.push_str("# TYPE dogtrails_outbound_in_flight gauge\n");
+        let _ = writeln!(text, "dogtrails_outbound_in_flight {outbound_in_flight}");
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters_per_provider() {
+        let metrics = Metrics::default();
+        metrics.record_request();
+        metrics.record_cache(&Provider::OpenStreetMap, true);
+        metrics.record_cache(&Provider::OpenStreetMap, false);
+        metrics.record_upstream_error(&Provider::DOC);
+
+        let text = metrics.render(2);
+        assert!(text.contains("dogtrails_requests_total 1\n"));
+        assert!(text.contains("dogtrails_cache_hits_total{provider=\"OpenStreetMap\"} 1\n"));
+        assert!(text.contains("dogtrails_cache_misses_total{provider=\"OpenStreetMap\"} 1\n"));
+        assert!(text.contains("dogtrails_upstream_errors_total{provider=\"DOC\"} 1\n"));
+        assert!(text.contains("dogtrails_outbound_in_flight 2\n"));
+    }
+
+    #[tokio::test]
+    async fn resumes_counters_from_the_snapshot_log() {
+        let path = std::env::temp_dir().join(format!("dogtrails-metrics-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let metrics = Metrics::default();
+        metrics.record_request();
+        metrics.append_snapshot(&path).await.unwrap();
+        metrics.record_request();
+        metrics.record_cache(&Provider::DOC, false);
+        metrics.append_snapshot(&path).await.unwrap();
+        // A write cut short by a crash leaves a partial last line.
+        std::fs::write(&path, std::fs::read_to_string(&path).unwrap() + "{\"requests\":9").unwrap();
+
+        let resumed = Metrics::resume(&path);
+        assert_eq!(resumed.snapshot(), MetricsSnapshot { requests: 2, doc: [0, 1, 0], osm: [0, 0, 0] });
+        resumed.record_request();
+        assert!(resumed.render(0).contains("dogtrails_requests_total 3\n"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+}
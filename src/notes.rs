@@ -0,0 +1,47 @@
+//! Operator-supplied dog notes that replace the built-in wording.
+
+use serde::Deserialize;
+
+use crate::{DogPolicy, Provider, Trail, TrailError};
+
+/// One dictionary entry. `provider` and `region` are optional; when absent
+/// the entry applies to any provider or region.
+#[derive(Clone, Deserialize)]
+pub struct DogNoteEntry {
+    pub policy: DogPolicy,
+    pub provider: Option<Provider>,
+    pub region: Option<String>,
+    pub note: String,
+}
+
+/// Dog-note overrides loaded from a JSON array of `DogNoteEntry`.
+#[derive(Clone, Default)]
+pub struct DogNotes {
+    entries: Vec<DogNoteEntry>,
+}
+
+impl DogNotes {
+    pub fn new(entries: Vec<DogNoteEntry>) -> Self {
+        Self { entries }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, TrailError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| TrailError::Config(format!("failed to read dog notes {path}: {err}")))?;
+        let entries = serde_json::from_str(&text)
+            .map_err(|err| TrailError::Config(format!("failed to parse dog notes {path}: {err}")))?;
+        Ok(Self::new(entries))
+    }
+
+    /// The most specific note for the trail's policy, provider and region.
+    /// Entries naming a region beat those naming only a provider.
+    pub fn lookup(&self, trail: &Trail) -> Option<&str> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.policy == trail.dog_policy)
+            .filter(|entry| entry.provider.as_ref().is_none_or(|provider| *provider == trail.provider))
+            .filter(|entry| entry.region.is_none() || entry.region == trail.region)
+            .max_by_key(|entry| (entry.region.is_some(), entry.provider.is_some()))
+            .map(|entry| entry.note.as_str())
+    }
+}
@@ -3,7 +3,23 @@ use std::time::Duration;
 
 use serde::Deserialize;
 
-use crate::{Bbox, DogPolicy, Provider, Trail, TrailError};
+use crate::backoff::Backoff;
+use crate::{polyline_length_km, Bbox, DifficultyThresholds, DistanceModel, DistanceSource, DogPolicy, Provider, Trail, TrailError};
+
+/// How Overpass ways are turned into trails.
+#[derive(Clone, Copy)]
+pub(crate) struct OverpassOptions {
+    pub include_unnamed: bool,
+    pub distance_model: DistanceModel,
+    pub timeout: Duration,
+    pub difficulty_thresholds: DifficultyThresholds,
+    /// Fixes the jitter on 429/504 retries; random when `None`.
+    pub backoff_seed: Option<u64>,
+    /// Copy each way's tags into `Trail::raw`.
+    pub keep_raw: bool,
+    /// Simplify each way's line to at most this many points.
+    pub max_line_points: usize,
+}
 
 #[derive(Deserialize)]
 struct OverpassResponse {
@@ -30,10 +46,35 @@ pub(crate) async fn fetch_overpass_with_fallback(
     client: &reqwest::Client,
     overpass_urls: &[String],
     bbox: Bbox,
+    options: OverpassOptions,
+) -> Result<Vec<Trail>, TrailError> {
+    query_with_fallback(client, overpass_urls, &build_overpass_query(bbox), options).await
+}
+
+/// One way by id, whatever its tags say about dogs. `None` when Overpass has
+/// no such way or it carries no tags.
+pub(crate) async fn fetch_overpass_way(
+    client: &reqwest::Client,
+    overpass_urls: &[String],
+    way_id: u64,
+    options: OverpassOptions,
+) -> Result<Option<Trail>, TrailError> {
+    let query = format!("[out:json][timeout:25];way(id:{way_id});out tags geom;");
+    // Asked for by id, so an unnamed way is still the answer.
+    let options = OverpassOptions { include_unnamed: true, ..options };
+    Ok(query_with_fallback(client, overpass_urls, &query, options).await?.into_iter().next())
+}
+
+/// Run `query` against each mirror in turn until one answers.
+async fn query_with_fallback(
+    client: &reqwest::Client,
+    overpass_urls: &[String],
+    query: &str,
+    options: OverpassOptions,
 ) -> Result<Vec<Trail>, TrailError> {
     let mut last_error: Option<TrailError> = None;
     for url in overpass_urls {
-        match fetch_overpass_trails(client, url, bbox).await {
+        match fetch_overpass_trails(client, url, query, options).await {
             Ok(trails) => return Ok(trails),
             Err(err) => {
                 tracing::warn!("overpass request failed for {}: {}", url, err);
@@ -41,44 +82,63 @@ pub(crate) async fn fetch_overpass_with_fallback(
             }
         }
     }
-    Err(last_error.unwrap_or_else(|| TrailError("no overpass endpoints configured".to_string())))
+    Err(last_error.unwrap_or_else(|| TrailError::Config("no overpass endpoints configured".to_string())))
+}
+
+/// Wait before the first 429/504 retry; later waits double.
+const OVERPASS_RETRY_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Smallest useful query: one node id, no geometry.
+const PROBE_QUERY: &str = "[out:json][timeout:5];node(1);out ids;";
+
+/// Round-trip time of a tiny query against one mirror.
+pub(crate) async fn probe_latency(client: &reqwest::Client, overpass_url: &str) -> Result<Duration, TrailError> {
+    let started = std::time::Instant::now();
+    let response = client
+        .get(append_overpass_query(overpass_url, PROBE_QUERY))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .map_err(|err| TrailError::Upstream(format!("overpass probe failed: {err}")))?;
+    if !response.status().is_success() {
+        return Err(TrailError::Upstream(format!(
+            "overpass probe failed with status {}",
+            response.status()
+        )));
+    }
+    Ok(started.elapsed())
 }
 
 async fn fetch_overpass_trails(
     client: &reqwest::Client,
     overpass_url: &str,
-    bbox: Bbox,
+    query: &str,
+    options: OverpassOptions,
 ) -> Result<Vec<Trail>, TrailError> {
-    let query = format!(
-        "[out:json][timeout:25];(way[highway=path][dog]({min_lat},{min_lon},{max_lat},{max_lon});way[highway=footway][dog]({min_lat},{min_lon},{max_lat},{max_lon});way[route=hiking][dog]({min_lat},{min_lon},{max_lat},{max_lon}););out tags geom;",
-        min_lat = bbox.min_lat,
-        min_lon = bbox.min_lon,
-        max_lat = bbox.max_lat,
-        max_lon = bbox.max_lon
-    );
-
     if query.trim().is_empty() {
-        return Err(TrailError("overpass query is empty".to_string()));
+        return Err(TrailError::Upstream("overpass query is empty".to_string()));
     }
 
-    let url = append_overpass_query(overpass_url, &query);
+    let url = append_overpass_query(overpass_url, query);
 
     let max_retries = 3;
+    let mut backoff = Backoff::new(OVERPASS_RETRY_BACKOFF, options.backoff_seed);
     let mut attempt = 0;
     loop {
         attempt += 1;
         let response = client
             .get(&url)
+            .timeout(options.timeout)
             .send()
             .await
-            .map_err(|err| TrailError(format!("overpass request failed: {err}")))?;
+            .map_err(|err| crate::request_error("overpass request", err, options.timeout))?;
 
         // Overpass often also returns 504 when "The server is too busy to handle the request".
         if matches!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS | reqwest::StatusCode::GATEWAY_TIMEOUT) {
             if attempt >= max_retries {
-                return Err(TrailError("overpass rate limited after retries".to_string()));
+                return Err(TrailError::Upstream("overpass rate limited after retries".to_string()));
             }
-            let delay = Duration::from_secs(2u64.pow(attempt as u32));
+            let delay = backoff.delay(attempt);
             tracing::warn!(
                 "overpass too many requests, retrying in {:?} (attempt {}/{})",
                 delay,
@@ -95,26 +155,51 @@ async fn fetch_overpass_trails(
                 .text()
                 .await
                 .unwrap_or_else(|_| "<no body>".to_string());
-            return Err(TrailError(format!(
+            return Err(TrailError::Upstream(format!(
                 "overpass request failed with status {}: {}",
                 status, body
             )));
         }
 
-        let data: OverpassResponse = response
-            .json()
+        // A busy mirror may answer 200 with an HTML error page instead of JSON.
+        let json_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_none_or(|value| value.contains("json"));
+        let body = response
+            .text()
             .await
-            .map_err(|err| TrailError(format!("overpass response parse failed: {err}")))?;
+            .map_err(|err| crate::request_error("overpass response", err, options.timeout))?;
+        if !json_type || body.trim_start().starts_with('<') {
+            return Err(TrailError::Upstream("overpass returned non-JSON, likely overloaded".to_string()));
+        }
+        let data: OverpassResponse = serde_json::from_str(&body)
+            .map_err(|err| TrailError::Upstream(format!("overpass response parse failed: {err}")))?;
 
         return Ok(data
             .elements
             .into_iter()
             .filter(|element| element.element_type == "way")
-            .filter_map(map_overpass_element)
+            .filter_map(|element| map_overpass_element(element, options))
             .collect());
     }
 }
 
+/// Dog access tags, most authoritative first. Ways carrying any of them are fetched.
+const DOG_TAG_KEYS: &[&str] = &["dog", "dog:conditional", "dog:leashed"];
+
+/// Overpass QL for paths, footways and hiking routes with any of `DOG_TAG_KEYS`,
+/// matched with one key regex per way type rather than a clause per key.
+/// Overpass bboxes are `(south,west,north,east)`, i.e. `(min_lat,min_lon,max_lat,max_lon)`.
+fn build_overpass_query(bbox: Bbox) -> String {
+    let area = format!("({},{},{},{})", bbox.min_lat, bbox.min_lon, bbox.max_lat, bbox.max_lon);
+    let dog = format!("[~\"^({})$\"~\".\"]", DOG_TAG_KEYS.join("|"));
+    format!(
+        "[out:json][timeout:25];(way[highway=path]{dog}{area};way[highway=footway]{dog}{area};way[route=hiking]{dog}{area};);out tags geom;"
+    )
+}
+
 fn append_overpass_query(base_url: &str, query: &str) -> String {
     let encoded = urlencoding::encode(query);
     if base_url.contains('?') {
@@ -124,36 +209,37 @@ fn append_overpass_query(base_url: &str, query: &str) -> String {
     }
 }
 
-fn map_overpass_element(element: OverpassElement) -> Option<Trail> {
+fn map_overpass_element(element: OverpassElement, options: OverpassOptions) -> Option<Trail> {
     let tags = element.tags?;
-    let name = tags.get("name")?.to_string();
-    let dog_policy = map_dog_policy(tags.get("dog"));
-    if dog_policy == DogPolicy::NotAllowed {
+    let name = tags.get("name").cloned();
+    if name.is_none() && !options.include_unnamed {
         return None;
     }
-    let dog_notes = tags.get("dog").and_then(|value| match value.as_str() {
-        "leashed" | "on_leash" | "conditional" => {
-            Some("Dogs must be leashed or have restrictions.".to_string())
-        }
-        _ => None,
-    });
+    // No-dog ways are kept with their notes; the search's dog filter decides
+    // whether they're shown.
+    let (dog_policy, dog_notes) = map_dog_access(&tags);
 
     let surface = tags
         .get("surface")
         .cloned()
         .unwrap_or_else(|| "Unknown".to_string());
-    let distance_km = element
+    let line: Vec<Vec<[f64; 2]>> = element
         .geometry
         .as_ref()
-        .map(|points| compute_distance_km(points))
-        .unwrap_or(0.0);
+        .filter(|pts| !pts.is_empty())
+        .map(|pts| vec![pts.iter().map(|p| [p.lat, p.lon]).collect()])
+        .unwrap_or_default();
 
-    let difficulty = crate::map_difficulty(tags.get("sac_scale"), distance_km);
+    let distance_km = polyline_length_km(&line, options.distance_model) as f32;
+
+    let difficulty = crate::map_difficulty(tags.get("sac_scale"), distance_km, options.difficulty_thresholds);
     let location = tags
         .get("addr:city")
         .cloned()
         .unwrap_or_else(|| "Unknown".to_string());
     let map_url = format!("https://www.openstreetmap.org/way/{}", element.id);
+    let name_generated = name.is_none();
+    let name = name.unwrap_or_else(|| generate_name(&tags, &location));
 
     let lat = element
         .center
@@ -167,8 +253,7 @@ fn map_overpass_element(element: OverpassElement) -> Option<Trail> {
                     Some(pts.iter().map(|p| p.lat).sum::<f64>() / pts.len() as f64)
                 }
             })
-        })
-        .unwrap_or(0.0);
+        });
     let lon = element
         .center
         .as_ref()
@@ -181,23 +266,17 @@ fn map_overpass_element(element: OverpassElement) -> Option<Trail> {
                     Some(pts.iter().map(|p| p.lon).sum::<f64>() / pts.len() as f64)
                 }
             })
-        })
-        .unwrap_or(0.0);
-
-    let line: Vec<[f64; 2]> = element
-        .geometry
-        .as_ref()
-        .map(|pts| pts.iter().map(|p| [p.lat, p.lon]).collect())
-        .unwrap_or_default();
+        });
 
     let line_bbox = if line.is_empty() {
+        let (lat, lon) = (lat.unwrap_or_default(), lon.unwrap_or_default());
         Bbox { min_lat: lat, min_lon: lon, max_lat: lat, max_lon: lon }
     } else {
         let mut min_lat_b = f64::MAX;
         let mut max_lat_b = f64::MIN;
         let mut min_lon_b = f64::MAX;
         let mut max_lon_b = f64::MIN;
-        for pt in &line {
+        for pt in line.iter().flatten() {
             min_lat_b = min_lat_b.min(pt[0]);
             max_lat_b = max_lat_b.max(pt[0]);
             min_lon_b = min_lon_b.min(pt[1]);
@@ -205,6 +284,8 @@ fn map_overpass_element(element: OverpassElement) -> Option<Trail> {
         }
         Bbox { min_lat: min_lat_b, min_lon: min_lon_b, max_lat: max_lat_b, max_lon: max_lon_b }
     };
+    // Measured and boxed above at full detail.
+    let line = crate::simplify_sections(&line, options.max_line_points);
 
     Some(Trail {
         id: format!("osm-{}", element.id),
@@ -212,47 +293,243 @@ fn map_overpass_element(element: OverpassElement) -> Option<Trail> {
         provider: Provider::OpenStreetMap,
         location,
         distance_km,
-        elevation_m: tags.get("ele").and_then(|value| value.parse::<f32>().ok()),
+        distance_source: if distance_km > 0.0 { DistanceSource::ComputedFromGeometry } else { DistanceSource::Unknown },
+        // `ele` is the height of a point, not gain, so it never feeds `elevation_m`.
+        elevation_m: None,
+        elevation_point_m: tags.get("ele").and_then(|value| value.parse::<f32>().ok()),
+        net_elevation_m: None,
         difficulty,
         dog_policy,
         dog_notes,
+        surfaces: vec![(surface.clone(), 1.0)],
+        surface_label: String::new(),
         surface,
         map_url,
         lat,
         lon,
+        has_location: lat.is_some() && lon.is_some(),
         line,
         line_bbox,
+        match_score: None,
+        region: None,
+        district: None,
+        place: None,
+        hot_paw_risk: None,
+        slug: String::new(),
+        summary: String::new(),
+        water_crossing: false,
+        provider_name: String::new(),
+        difficulty_conflict: false,
+        thumbnail_url: None,
+        images: Vec::new(),
+        warnings: Vec::new(),
+        raw: if options.keep_raw { serde_json::to_value(&tags).ok() } else { None },
+        data_checked: tags.get("check_date").cloned(),
+        data_outdated: false,
+        featured: None,
+        popularity: None,
+        dog_seasons: Vec::new(),
+        name_generated,
     })
 }
 
+/// A stand-in name for an unnamed way, from its `ref` or nearby place.
+fn generate_name(tags: &std::collections::HashMap<String, String>, location: &str) -> String {
+    if let Some(reference) = tags.get("ref") {
+        return format!("Unnamed path {reference}");
+    }
+    if location != "Unknown" {
+        return format!("Unnamed path near {location}");
+    }
+    "Unnamed path".to_string()
+}
+
+/// Policy and notes from every dog tag on a way. Plain `dog` sets the base;
+/// `dog:leashed=yes` and `dog:conditional` narrow full access to partial, and a
+/// conditional `yes` opens up a way that is otherwise closed to dogs.
+fn map_dog_access(tags: &std::collections::HashMap<String, String>) -> (DogPolicy, Option<String>) {
+    let mut policy = tags.get("dog").map(|value| map_dog_policy(Some(value)));
+    let mut notes: Vec<String> = map_dog_notes(tags.get("dog")).into_iter().collect();
+
+    if tags.get("dog:leashed").is_some_and(|value| value == "yes")
+        && matches!(policy, None | Some(DogPolicy::Allowed))
+    {
+        policy = Some(DogPolicy::Partial);
+        notes.push("Dogs must be on a leash.".to_string());
+    }
+    if let Some(conditional) = tags.get("dog:conditional") {
+        let opens = conditional.trim_start().starts_with("yes");
+        match policy {
+            None | Some(DogPolicy::Allowed) => policy = Some(DogPolicy::Partial),
+            Some(DogPolicy::NotAllowed) if opens => policy = Some(DogPolicy::Partial),
+            _ => {}
+        }
+        if policy == Some(DogPolicy::Partial) {
+            notes.push(format!("Dog access changes: {conditional}."));
+        }
+    }
+
+    let notes = (!notes.is_empty()).then(|| notes.join(" "));
+    (policy.unwrap_or(DogPolicy::NotAllowed), notes)
+}
+
 fn map_dog_policy(value: Option<&String>) -> DogPolicy {
     match value.map(|value| value.as_str()) {
         Some("yes") => DogPolicy::Allowed,
         Some("leashed") | Some("on_leash") | Some("conditional") => DogPolicy::Partial,
-        Some("no") => DogPolicy::NotAllowed,
+        Some("permit") | Some("customers") => DogPolicy::Partial,
+        Some("no") | Some("private") | Some("no_access") => DogPolicy::NotAllowed,
         _ => DogPolicy::NotAllowed,
     }
 }
 
-fn compute_distance_km(points: &[OverpassPoint]) -> f32 {
-    if points.len() < 2 {
-        return 0.0;
-    }
-    let mut total = 0.0;
-    for window in points.windows(2) {
-        total += haversine_km(window[0].lat, window[0].lon, window[1].lat, window[1].lon);
+fn map_dog_notes(value: Option<&String>) -> Option<String> {
+    match value.map(|value| value.as_str()) {
+        Some("leashed") | Some("on_leash") | Some("conditional") => {
+            Some("Dogs must be leashed or have restrictions.".to_string())
+        }
+        Some("permit") => Some("Dogs require a permit.".to_string()),
+        Some("customers") => Some("Dogs allowed for customers only.".to_string()),
+        Some("private") => Some("Dog access is private.".to_string()),
+        Some("no_access") => Some("No public dog access.".to_string()),
+        _ => None,
     }
-    total as f32
 }
 
-fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
-    let radius = 6371.0;
-    let dlat = (lat2 - lat1).to_radians();
-    let dlon = (lon2 - lon1).to_radians();
-    let lat1 = lat1.to_radians();
-    let lat2 = lat2.to_radians();
 
-    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
-    let c = 2.0 * a.sqrt().asin();
-    radius * c
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dog(value: &str) -> (DogPolicy, Option<String>) {
+        let value = value.to_string();
+        (map_dog_policy(Some(&value)), map_dog_notes(Some(&value)))
+    }
+
+    #[test]
+    fn query_uses_south_west_north_east_order() {
+        let bbox = Bbox { min_lat: -43.6, min_lon: 172.5, max_lat: -43.45, max_lon: 172.77 };
+        let query = build_overpass_query(bbox);
+        let area = "(-43.6,172.5,-43.45,172.77)";
+        let dog = "[~\"^(dog|dog:conditional|dog:leashed)$\"~\".\"]";
+        assert!(query.contains(&format!("way[highway=path]{dog}{area}")));
+        assert!(query.contains(&format!("way[highway=footway]{dog}{area}")));
+        assert!(query.contains(&format!("way[route=hiking]{dog}{area}")));
+        assert_eq!(query.matches(area).count(), 3);
+    }
+
+    fn options(include_unnamed: bool) -> OverpassOptions {
+        OverpassOptions {
+            include_unnamed,
+            distance_model: DistanceModel::Haversine,
+            timeout: Duration::from_secs(30),
+            difficulty_thresholds: DifficultyThresholds::default(),
+            backoff_seed: None,
+            keep_raw: false,
+            max_line_points: 500,
+        }
+    }
+
+    fn element(tags: &[(&str, &str)]) -> OverpassElement {
+        OverpassElement {
+            element_type: "way".to_string(),
+            id: 42,
+            tags: Some(tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
+            geometry: None,
+            center: Some(OverpassPoint { lat: -43.5, lon: 172.6 }),
+        }
+    }
+
+    #[test]
+    fn drops_unnamed_ways_by_default() {
+        assert!(map_overpass_element(element(&[("dog", "yes")]), options(false)).is_none());
+    }
+
+    #[test]
+    fn names_unnamed_ways_when_included() {
+        let trail = map_overpass_element(element(&[("dog", "yes"), ("addr:city", "Christchurch")]), options(true)).unwrap();
+        assert_eq!(trail.name, "Unnamed path near Christchurch");
+        assert!(trail.name_generated);
+
+        let trail = map_overpass_element(element(&[("dog", "yes"), ("ref", "CT4")]), options(true)).unwrap();
+        assert_eq!(trail.name, "Unnamed path CT4");
+
+        let trail = map_overpass_element(element(&[("dog", "yes"), ("name", "Loop")]), options(true)).unwrap();
+        assert_eq!(trail.name, "Loop");
+        assert!(!trail.name_generated);
+    }
+
+    #[test]
+    fn reads_check_date_tag() {
+        let trail = map_overpass_element(element(&[("dog", "yes"), ("name", "Old"), ("check_date", "2019-03-14")]), options(false)).unwrap();
+        assert_eq!(trail.data_checked.as_deref(), Some("2019-03-14"));
+        let trail = map_overpass_element(element(&[("dog", "yes"), ("name", "New")]), options(false)).unwrap();
+        assert_eq!(trail.data_checked, None);
+    }
+
+    #[test]
+    fn geometry_distances_are_computed() {
+        let mut way = element(&[("dog", "yes"), ("name", "Loop")]);
+        way.geometry = Some(vec![OverpassPoint { lat: -43.5, lon: 172.6 }, OverpassPoint { lat: -43.51, lon: 172.61 }]);
+        let trail = map_overpass_element(way, options(false)).unwrap();
+        assert_eq!(trail.distance_source, DistanceSource::ComputedFromGeometry);
+        assert_eq!(trail.distance_km, polyline_length_km(&trail.line, DistanceModel::Haversine) as f32);
+
+        let trail = map_overpass_element(element(&[("dog", "yes"), ("name", "Point")]), options(false)).unwrap();
+        assert_eq!(trail.distance_source, DistanceSource::Unknown);
+    }
+
+    #[test]
+    fn point_elevation_is_not_gain() {
+        let trail = map_overpass_element(element(&[("dog", "yes"), ("name", "Hill"), ("ele", "520")]), options(false)).unwrap();
+        assert_eq!(trail.elevation_m, None);
+        assert_eq!(trail.elevation_point_m, Some(520.0));
+    }
+
+    #[test]
+    fn maps_private_and_no_access_to_not_allowed() {
+        assert_eq!(dog("private"), (DogPolicy::NotAllowed, Some("Dog access is private.".to_string())));
+        assert_eq!(dog("no_access"), (DogPolicy::NotAllowed, Some("No public dog access.".to_string())));
+    }
+
+    #[test]
+    fn keeps_private_ways_with_their_note() {
+        let trail = map_overpass_element(element(&[("dog", "private"), ("name", "Farm Track")]), options(false)).unwrap();
+        assert!(trail.dog_policy == DogPolicy::NotAllowed);
+        assert_eq!(trail.dog_notes.as_deref(), Some("Dog access is private."));
+    }
+
+    fn access(tags: &[(&str, &str)]) -> (DogPolicy, Option<String>) {
+        map_dog_access(&tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    #[test]
+    fn combines_namespaced_dog_tags() {
+        assert_eq!(access(&[("dog", "yes")]), (DogPolicy::Allowed, None));
+        assert_eq!(
+            access(&[("dog", "yes"), ("dog:leashed", "yes")]),
+            (DogPolicy::Partial, Some("Dogs must be on a leash.".to_string()))
+        );
+        assert_eq!(
+            access(&[("dog:leashed", "yes")]),
+            (DogPolicy::Partial, Some("Dogs must be on a leash.".to_string()))
+        );
+        assert_eq!(access(&[("dog", "yes"), ("dog:leashed", "no")]), (DogPolicy::Allowed, None));
+        assert_eq!(
+            access(&[("dog", "yes"), ("dog:conditional", "no @ (Sep-Dec)")]),
+            (DogPolicy::Partial, Some("Dog access changes: no @ (Sep-Dec).".to_string()))
+        );
+        assert_eq!(
+            access(&[("dog", "no"), ("dog:conditional", "yes @ (Jun-Aug)")]),
+            (DogPolicy::Partial, Some("Dog access changes: yes @ (Jun-Aug).".to_string()))
+        );
+        assert_eq!(access(&[("dog", "no"), ("dog:conditional", "no @ (Sep-Dec)")]).0, DogPolicy::NotAllowed);
+        assert_eq!(access(&[("highway", "path")]), (DogPolicy::NotAllowed, None));
+    }
+
+    #[test]
+    fn maps_permit_and_customers_to_partial() {
+        assert_eq!(dog("permit"), (DogPolicy::Partial, Some("Dogs require a permit.".to_string())));
+        assert_eq!(dog("customers"), (DogPolicy::Partial, Some("Dogs allowed for customers only.".to_string())));
+    }
 }
@@ -0,0 +1,344 @@
+//! Server-rendered, no-JS results page.
+
+use crate::{describe_surfaces, Difficulty, DogFilter, Effort, Length, LengthBias, Trail, TrailQuery};
+
+/// Quick-filter presets shown as chips above the form: label and query string.
+/// There is no water filter, so "Flat & near water" narrows on effort alone.
+const PRESETS: &[(&str, &str)] = &[
+    ("Easy & dogs allowed", "difficulty=easy&dog=allowed_only"),
+    ("Long runs", "length=long&effort=hard"),
+    ("Flat & near water", "effort=easy"),
+    ("Flat & short", "length=short&effort=easy"),
+];
+
+/// Results per page when the query gives no `limit`.
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+const DIFFICULTY_LEGEND: &[(&str, &str)] = &[
+    ("Easy", "Mostly flat, well-formed paths."),
+    ("Moderate", "Some climbs or rougher ground."),
+    ("Hard", "Sustained climbs, rough or alpine terrain."),
+];
+
+pub fn render_page(query: &TrailQuery, trails: &[Trail]) -> String {
+    let mut html = String::new();
+    html.push_str("<!doctype html>\n<html lang=\"en\">\n<head>\n");
+    html.push_str("<meta charset=\"UTF-8\" />\n");
+    html.push_str("<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\" />\n");
+    html.push_str("<title>Dogtrails</title>\n");
+    html.push_str("<link rel=\"stylesheet\" href=\"/static/lite.css\" />\n");
+    html.push_str("<link rel=\"stylesheet\" href=\"/static/warnings.css\" />\n");
+    html.push_str("</head>\n<body>\n<main>\n<h1>For you + dog</h1>\n");
+    html.push_str("<p class=\"mode-switch\"><a href=\"/?mode=spa\">Open the interactive map</a></p>\n");
+
+    html.push_str("<nav class=\"chips\">\n");
+    for (label, params) in PRESETS {
+        html.push_str(&format!(
+            "<a class=\"chip\" href=\"/lite?{}\">{}</a>\n",
+            escape(params),
+            escape(label)
+        ));
+    }
+    html.push_str("</nav>\n");
+
+    html.push_str(&render_form(query));
+
+    html.push_str("<dl class=\"legend\">\n");
+    for (label, description) in DIFFICULTY_LEGEND {
+        html.push_str(&format!("<dt>{}</dt><dd>{}</dd>\n", label, description));
+    }
+    html.push_str("</dl>\n");
+
+    if trails.is_empty() {
+        html.push_str("<p class=\"warning\">No trails matched your filters.</p>\n");
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).max(1);
+    let offset = query.offset.unwrap_or(0);
+    for trail in trails.iter().skip(offset).take(limit) {
+        html.push_str(&render_trail(trail));
+    }
+    html.push_str(&render_pager(query, trails.len(), offset, limit));
+
+    html.push_str("</main>\n</body>\n</html>\n");
+    html
+}
+
+/// "Page X of Y" with prev/next links that keep every other filter.
+fn render_pager(query: &TrailQuery, total: usize, offset: usize, limit: usize) -> String {
+    if total <= limit && offset == 0 {
+        return String::new();
+    }
+    let mut html = String::from("<nav class=\"pager\">\n");
+    if offset > 0 {
+        html.push_str(&format!(
+            "<a rel=\"prev\" href=\"/lite?{}\">Previous</a>\n",
+            escape(&page_query(query, offset.saturating_sub(limit), limit))
+        ));
+    }
+    html.push_str(&format!(
+        "<span>Page {} of {}</span>\n",
+        offset / limit + 1,
+        total.div_ceil(limit).max(1)
+    ));
+    if offset + limit < total {
+        html.push_str(&format!(
+            "<a rel=\"next\" href=\"/lite?{}\">Next</a>\n",
+            escape(&page_query(query, offset + limit, limit))
+        ));
+    }
+    html.push_str("</nav>\n");
+    html
+}
+
+/// Query string for `query` with the paging window replaced.
+fn page_query(query: &TrailQuery, offset: usize, limit: usize) -> String {
+    let mut params: Vec<(&str, String)> = Vec::new();
+    let optional = [
+        ("effort", query.effort.as_ref().map(effort_param)),
+        ("length", query.length.as_ref().map(length_param)),
+        ("length_bias", query.length_bias.as_ref().map(length_bias_param)),
+        ("dog", query.dog.as_ref().map(dog_param)),
+        ("difficulty", query.difficulty.as_ref().map(difficulty_param)),
+    ];
+    for (key, value) in optional {
+        if let Some(value) = value {
+            params.push((key, value.to_string()));
+        }
+    }
+    let numbers = [
+        ("min_km", query.min_km.map(|value| value.to_string())),
+        ("max_km", query.max_km.map(|value| value.to_string())),
+        ("with_coordinates", query.with_coordinates.map(|value| value.to_string())),
+        ("relax", query.relax.map(|value| value.to_string())),
+        ("family_friendly", query.family_friendly.map(|value| value.to_string())),
+        ("featured_only", query.featured_only.map(|value| value.to_string())),
+        ("trusted_only", query.trusted_only.map(|value| value.to_string())),
+        ("ascii", query.ascii.map(|value| value.to_string())),
+        ("diversify", query.diversify.map(|value| value.to_string())),
+        ("debug", query.debug.map(|value| value.to_string())),
+        (
+            "max_distance_from_center_km",
+            query.max_distance_from_center_km.map(|value| value.to_string()),
+        ),
+        ("min_lat", query.min_lat.map(|value| value.to_string())),
+        ("min_lon", query.min_lon.map(|value| value.to_string())),
+        ("max_lat", query.max_lat.map(|value| value.to_string())),
+        ("max_lon", query.max_lon.map(|value| value.to_string())),
+    ];
+    for (key, value) in numbers {
+        if let Some(value) = value {
+            params.push((key, value));
+        }
+    }
+    params.push(("offset", offset.to_string()));
+    params.push(("limit", limit.to_string()));
+    params
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, urlencoding::encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn effort_param(effort: &Effort) -> &'static str {
+    match effort {
+        Effort::Easy => "easy",
+        Effort::Steady => "steady",
+        Effort::Hard => "hard",
+    }
+}
+
+fn length_param(length: &Length) -> &'static str {
+    match length {
+        Length::Short => "short",
+        Length::Medium => "medium",
+        Length::Long => "long",
+    }
+}
+
+fn length_bias_param(bias: &LengthBias) -> &'static str {
+    match bias {
+        LengthBias::Shortest => "shortest",
+        LengthBias::Balanced => "balanced",
+        LengthBias::Longest => "longest",
+    }
+}
+
+fn dog_param(dog: &DogFilter) -> &'static str {
+    match dog {
+        DogFilter::AllowedOnly => "allowed_only",
+        DogFilter::AllowedOrPartial => "allowed_or_partial",
+        DogFilter::Any => "any",
+    }
+}
+
+fn difficulty_param(difficulty: &Difficulty) -> &'static str {
+    match difficulty {
+        Difficulty::Easy => "easy",
+        Difficulty::Moderate => "moderate",
+        Difficulty::Hard => "hard",
+    }
+}
+
+fn render_form(query: &TrailQuery) -> String {
+    let effort = query.effort.as_ref().map(effort_param);
+    let length = query.length.as_ref().map(length_param);
+    let dog = query.dog.as_ref().map(dog_param);
+    let difficulty = query.difficulty.as_ref().map(difficulty_param);
+
+    let mut html = String::from("<form method=\"get\" action=\"/lite\">\n");
+    html.push_str(&render_select(
+        "effort",
+        "Effort",
+        &[("easy", "Easy"), ("steady", "Steady"), ("hard", "Hard")],
+        effort.unwrap_or("steady"),
+    ));
+    html.push_str(&render_select(
+        "length",
+        "Length",
+        &[("short", "Short (2-6 km)"), ("medium", "Medium (6-12 km)"), ("long", "Long (12-24 km)")],
+        length.unwrap_or("medium"),
+    ));
+    html.push_str(&render_select(
+        "dog",
+        "Dog access",
+        &[
+            ("allowed_only", "Dogs allowed only"),
+            ("allowed_or_partial", "Allowed or partial (with notes)"),
+            ("any", "Show all (include no-dog)"),
+        ],
+        dog.unwrap_or("allowed_or_partial"),
+    ));
+    html.push_str(&render_select(
+        "difficulty",
+        "Difficulty",
+        &[("", "Any"), ("easy", "Easy"), ("moderate", "Moderate"), ("hard", "Hard")],
+        difficulty.unwrap_or(""),
+    ));
+    html.push_str("<button type=\"submit\">Find trails</button>\n</form>\n");
+    html
+}
+
+fn render_select(name: &str, label: &str, options: &[(&str, &str)], selected: &str) -> String {
+    let mut html = format!("<label>{}\n<select name=\"{}\">\n", label, name);
+    for (value, text) in options {
+        let marker = if *value == selected { " selected" } else { "" };
+        html.push_str(&format!("<option value=\"{}\"{}>{}</option>\n", value, marker, text));
+    }
+    html.push_str("</select>\n</label>\n");
+    html
+}
+
+/// One `<article class="trail">` card, as the page and `/api/trails/fragment` show it.
+pub fn render_trail(trail: &Trail) -> String {
+    let notes = trail
+        .dog_notes
+        .as_deref()
+        .map(|notes| format!("<p class=\"warning\">{}</p>\n", escape(notes)))
+        .unwrap_or_default();
+    let warnings: String = trail
+        .warnings
+        .iter()
+        .map(|warning| format!("<p class=\"warning {}\">{}</p>\n", escape(&warning.kind), escape(&warning.message)))
+        .collect();
+    let outdated = match (&trail.data_checked, trail.data_outdated) {
+        (Some(checked), true) => format!(
+            "<p class=\"warning\">Last verified {} — may be outdated.</p>\n",
+            escape(checked.get(..4).unwrap_or(checked))
+        ),
+        _ => String::new(),
+    };
+    let provider = if trail.provider_name.is_empty() {
+        trail.provider.display_name()
+    } else {
+        &trail.provider_name
+    };
+    let star = if trail.featured == Some(true) {
+        " <span class=\"featured\" title=\"Featured track\">★</span>"
+    } else {
+        ""
+    };
+    // The summary names the dominant surface, so only a breakdown adds to it.
+    let surfaces = if trail.surfaces.len() > 1 {
+        format!("{} · ", escape(&describe_surfaces(trail)))
+    } else {
+        String::new()
+    };
+    format!(
+        "<article class=\"trail\">\n<h3><a href=\"{}\">{}</a>{}</h3>\n<p class=\"summary\">{}</p>\n<p>{}{}</p>\n{}{}{}</article>\n",
+        escape(&trail.map_url),
+        escape(&trail.name),
+        star,
+        escape(&trail.summary),
+        surfaces,
+        escape(provider),
+        notes,
+        warnings,
+        outdated
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_preset_chips() {
+        let html = render_page(&TrailQuery::default(), &[]);
+        assert!(html.contains("<a class=\"chip\" href=\"/lite?difficulty=easy&amp;dog=allowed_only\">Easy &amp; dogs allowed</a>"));
+        assert!(html.contains("href=\"/lite?length=long&amp;effort=hard\""));
+        assert!(html.contains("<a class=\"chip\" href=\"/lite?effort=easy\">Flat &amp; near water</a>"));
+        assert!(html.contains("href=\"/lite?length=short&amp;effort=easy\""));
+    }
+
+    #[test]
+    fn renders_second_page_with_links_back() {
+        let template = crate::tests::test_trail("t0", "Trail");
+        let trails: Vec<crate::Trail> = (1..=5)
+            .map(|index| crate::Trail {
+                id: format!("t{index}"),
+                name: format!("Trail number {index}"),
+                ..template.clone()
+            })
+            .collect();
+        let query = TrailQuery {
+            dog: Some(DogFilter::AllowedOnly),
+            offset: Some(2),
+            limit: Some(2),
+            ..TrailQuery::default()
+        };
+
+        let html = render_page(&query, &trails);
+        assert!(!html.contains("Trail number 2<"));
+        assert!(html.contains("Trail number 3<"));
+        assert!(html.contains("Trail number 4<"));
+        assert!(!html.contains("Trail number 5<"));
+        assert!(html.contains("Page 2 of 3"));
+        assert!(html.contains("<a rel=\"prev\" href=\"/lite?dog=allowed_only&amp;offset=0&amp;limit=2\">"));
+        assert!(html.contains("<a rel=\"next\" href=\"/lite?dog=allowed_only&amp;offset=4&amp;limit=2\">"));
+    }
+
+    #[test]
+    fn card_shows_summary_instead_of_separate_fields() {
+        let mut trail = crate::tests::test_trail("t0", "Trail");
+        trail.summary = crate::trail_summary(&trail);
+        let html = render_trail(&trail);
+        assert!(html.contains("<p class=\"summary\">Easy 5.0 km gravel walk, dogs allowed, near Nelson</p>"));
+        assert_eq!(html.matches("5.0 km").count(), 1);
+
+        trail.surfaces = vec![("gravel".to_string(), 0.7), ("grass".to_string(), 0.3)];
+        assert!(render_trail(&trail).contains("<p>70% gravel, 30% grass · NZ DOC</p>"));
+    }
+
+    #[test]
+    fn renders_difficulty_legend() {
+        let html = render_page(&TrailQuery::default(), &[]);
+        assert!(html.contains("<dt>Moderate</dt>"));
+    }
+}